@@ -0,0 +1,2187 @@
+//! The front end. The source is assembled once, before anything runs, into decoded
+//! instructions with typed operands: the first pass collects labels, procs and variables,
+//! the second decodes every line against them.
+//!
+//! A line that fails to decode still becomes an instruction. It raises its error when
+//! executed, so a program can catch it with a #UD handler like any other invalid opcode.
+
+use crate::{
+    error_code::ErrorCode,
+    expression::{evaluate, Scope, Value},
+    instruction::Instruction,
+    interrupt::INTERRUPT_MNEMONICS,
+    lexer::{split_line, tokenize, unquote, Dialect, LexerOptions, TokenKind},
+    preprocessor::{in_file, preprocess, preprocess_all, PreprocessOptions, Source, SourceLine},
+    register::{get_register_size, RegisterName},
+    utils::{char_immediate, fits_in},
+    variable_metadata::{VariableMetadata, VariableSize},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mnemonic {
+    Mov,
+    Lea,
+    Add,
+    Sub,
+    Inc,
+    Dec,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+    Shl,
+    Shr,
+    Cmp,
+    Jmp,
+    Je,
+    Jz,
+    Jne,
+    Jnz,
+    Jg,
+    Jge,
+    Jl,
+    Jle,
+    Ja,
+    Jae,
+    Jb,
+    Jbe,
+    Call,
+    Ret,
+    Enter,
+    Leave,
+    Push,
+    Pop,
+    Print,
+    Int,
+    Int3,
+    Into,
+    Iret,
+    Cli,
+    Sti,
+    Hlt,
+    In,
+    Out,
+    // `name db, ...` is stored at load time and executes as a no-op. `name LABEL WORD` stores nothing.
+    Define(VariableSize),
+    // `align n` and `even` make the next variable start at a multiple of n
+    Align,
+    // `name:` executes as a no-op
+    Label,
+    // `name EQU value` and `name = value` execute as no-ops
+    Equ,
+    // `name PROC` jumps over the body of the proc
+    Proc,
+    // `name ENDP` is only reached when the proc didn't return
+    EndProc,
+    // `.model`, `.data`, `segment`, `assume` and the like only shape the program and
+    // execute as no-ops, as do loads into segment registers: memory is flat
+    Directive,
+    // `.stack size` sizes the stack when the program is loaded
+    Stack,
+    // Anything else
+    Unknown,
+}
+
+// Every instruction, by the name it is written with
+pub const MNEMONICS: [(&str, Mnemonic); 42] = [
+    ("mov", Mnemonic::Mov),
+    ("lea", Mnemonic::Lea),
+    ("add", Mnemonic::Add),
+    ("sub", Mnemonic::Sub),
+    ("inc", Mnemonic::Inc),
+    ("dec", Mnemonic::Dec),
+    ("mul", Mnemonic::Mul),
+    ("imul", Mnemonic::Imul),
+    ("div", Mnemonic::Div),
+    ("idiv", Mnemonic::Idiv),
+    ("shl", Mnemonic::Shl),
+    ("shr", Mnemonic::Shr),
+    ("cmp", Mnemonic::Cmp),
+    ("jmp", Mnemonic::Jmp),
+    ("je", Mnemonic::Je),
+    ("jz", Mnemonic::Jz),
+    ("jne", Mnemonic::Jne),
+    ("jnz", Mnemonic::Jnz),
+    ("jg", Mnemonic::Jg),
+    ("jge", Mnemonic::Jge),
+    ("jl", Mnemonic::Jl),
+    ("jle", Mnemonic::Jle),
+    ("ja", Mnemonic::Ja),
+    ("jae", Mnemonic::Jae),
+    ("jb", Mnemonic::Jb),
+    ("jbe", Mnemonic::Jbe),
+    ("call", Mnemonic::Call),
+    ("ret", Mnemonic::Ret),
+    ("enter", Mnemonic::Enter),
+    ("leave", Mnemonic::Leave),
+    ("push", Mnemonic::Push),
+    ("pop", Mnemonic::Pop),
+    ("print", Mnemonic::Print),
+    ("int", Mnemonic::Int),
+    ("int3", Mnemonic::Int3),
+    ("into", Mnemonic::Into),
+    ("iret", Mnemonic::Iret),
+    ("cli", Mnemonic::Cli),
+    ("sti", Mnemonic::Sti),
+    ("hlt", Mnemonic::Hlt),
+    ("in", Mnemonic::In),
+    ("out", Mnemonic::Out),
+];
+
+impl Mnemonic {
+    pub fn parse(text: &str) -> Option<Self> {
+        MNEMONICS
+            .iter()
+            .find(|(name, _)| *name == text)
+            .map(|(_, mnemonic)| *mnemonic)
+    }
+
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Mnemonic::Jmp
+                | Mnemonic::Je
+                | Mnemonic::Jz
+                | Mnemonic::Jne
+                | Mnemonic::Jnz
+                | Mnemonic::Jg
+                | Mnemonic::Jge
+                | Mnemonic::Jl
+                | Mnemonic::Jle
+                | Mnemonic::Ja
+                | Mnemonic::Jae
+                | Mnemonic::Jb
+                | Mnemonic::Jbe
+        )
+    }
+
+    // The syntax printed when the instruction is misused
+    pub fn help(&self) -> Option<Instruction> {
+        let instruction = match self {
+            Mnemonic::Mov => Instruction::Mov,
+            Mnemonic::Lea => Instruction::Lea,
+            Mnemonic::Add => Instruction::Add,
+            Mnemonic::Sub => Instruction::Sub,
+            Mnemonic::Inc => Instruction::Inc,
+            Mnemonic::Dec => Instruction::Dec,
+            Mnemonic::Mul => Instruction::Mul,
+            Mnemonic::Imul => Instruction::Imul,
+            Mnemonic::Div => Instruction::Div,
+            Mnemonic::Idiv => Instruction::Idiv,
+            Mnemonic::Shl => Instruction::Shl,
+            Mnemonic::Shr => Instruction::Shr,
+            Mnemonic::Cmp => Instruction::Cmp,
+            Mnemonic::Jmp => Instruction::Jmp,
+            Mnemonic::Je => Instruction::Je,
+            Mnemonic::Jz => Instruction::Jz,
+            Mnemonic::Jne => Instruction::Jne,
+            Mnemonic::Jnz => Instruction::Jnz,
+            Mnemonic::Jg => Instruction::Jg,
+            Mnemonic::Jge => Instruction::Jge,
+            Mnemonic::Jl => Instruction::Jl,
+            Mnemonic::Jle => Instruction::Jle,
+            Mnemonic::Ja => Instruction::Ja,
+            Mnemonic::Jae => Instruction::Jae,
+            Mnemonic::Jb => Instruction::Jb,
+            Mnemonic::Jbe => Instruction::Jbe,
+            Mnemonic::Call => Instruction::Call,
+            Mnemonic::Ret => Instruction::Ret,
+            Mnemonic::Enter => Instruction::Enter,
+            Mnemonic::Leave => Instruction::Leave,
+            Mnemonic::Push => Instruction::Push,
+            Mnemonic::Pop => Instruction::Pop,
+            Mnemonic::Int | Mnemonic::Int3 => Instruction::Int,
+            Mnemonic::Into => Instruction::Into,
+            Mnemonic::Iret => Instruction::Iret,
+            Mnemonic::Cli => Instruction::Cli,
+            Mnemonic::Sti => Instruction::Sti,
+            Mnemonic::Hlt => Instruction::Hlt,
+            Mnemonic::In => Instruction::In,
+            Mnemonic::Out => Instruction::Out,
+            _ => return None,
+        };
+        Some(instruction)
+    }
+}
+
+/*
+Effective Address = Base + (Index * Scale) + Displacement (+ Variable)
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryOperand {
+    pub base: Option<RegisterName>,
+    pub index: Option<RegisterName>,
+    pub scale: u32,
+    pub disp: i64,
+    // From BYTE/WORD/DWORD PTR, or the declared size of a lone [var] or [label].
+    // Anything else is a byte pointer.
+    pub size: Option<VariableSize>,
+    // Variables are placed at load time, after the IVT is reserved, so their address is added at run time
+    pub variable: Option<String>,
+}
+
+impl MemoryOperand {
+    pub fn size(&self) -> VariableSize {
+        self.size.unwrap_or(VariableSize::Byte)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub address: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Register(RegisterName),
+    Immediate(i64),
+    Memory(MemoryOperand),
+    // A label or proc, resolved to the IP it starts at
+    Label(Label),
+    // A quoted string, quotes included
+    Text(String),
+    // print's `char` modifier
+    Char(Box<Operand>),
+    // OFFSET var: read as the address of the variable plus `disp`
+    Address(MemoryOperand),
+    // `push DWORD 5`: a constant pushed at the given size rather than the smallest that holds it
+    Sized(Box<Operand>, VariableSize),
+}
+
+impl Operand {
+    // Registers, constants and memory: anything an instruction can read a value from
+    pub fn is_value(&self) -> bool {
+        matches!(
+            self,
+            Operand::Register(_)
+                | Operand::Immediate(_)
+                | Operand::Memory(_)
+                | Operand::Address(_)
+                | Operand::Sized(..)
+        )
+    }
+
+    // Values fixed when assembling, which take the size of the other operand
+    pub fn is_constant(&self) -> bool {
+        matches!(self, Operand::Immediate(_) | Operand::Address(_))
+    }
+
+    fn from_value(value: Value) -> Self {
+        match value.variable {
+            None => Operand::Immediate(value.constant),
+            Some(variable) => Operand::Address(MemoryOperand {
+                base: None,
+                index: None,
+                scale: 1,
+                disp: value.constant,
+                size: None,
+                variable: Some(variable),
+            }),
+        }
+    }
+
+    fn is_register(&self, names: &[RegisterName]) -> bool {
+        matches!(self, Operand::Register(name) if names.contains(name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedInstruction {
+    pub mnemonic: Mnemonic,
+    pub operands: Vec<Operand>,
+    // The label, proc or variable the line defines
+    pub name: Option<String>,
+    // Index of the line after macro expansion. IP holds line + 1 while the instruction executes.
+    pub line: usize,
+    // Index into `Program::files` of the file the line came from
+    pub file: usize,
+    // Line in that file: the call site for lines a macro expanded to
+    pub source_line: usize,
+    // The mnemonic and operands as written, for messages and the debug view
+    pub text: Vec<String>,
+    // Why the line could not be decoded. Raised when the instruction executes.
+    pub error: Option<ErrorCode>,
+}
+
+// `name dw ?` inside a STRUC, with the values an instance starts with
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub offset: usize,
+    // Bytes per element, as for a variable
+    pub width: usize,
+    pub length: usize,
+    pub defaults: Vec<String>,
+}
+
+impl Field {
+    pub fn size(&self) -> VariableSize {
+        element_size(self.width)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Struct {
+    pub fields: Vec<Field>,
+    pub size: usize,
+}
+
+/// Why a program didn't load: the error, and the file and line (from 0) of the statement at
+/// fault when there is one. Syntax errors carry their own position.
+#[derive(Debug)]
+pub struct LoadError {
+    pub error: ErrorCode,
+    pub location: Option<(PathBuf, usize)>,
+}
+
+impl From<ErrorCode> for LoadError {
+    fn from(error: ErrorCode) -> Self {
+        LoadError {
+            error,
+            location: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Program {
+    pub instructions: Vec<DecodedInstruction>,
+    pub labels: HashMap<String, usize>,
+    // Proc name -> (first line of the body, line after ENDP)
+    pub procs: HashMap<String, (usize, usize)>,
+    // Start indexes count from the first data definition, in source order
+    pub variables: HashMap<String, VariableMetadata>,
+    // EQU and `=` symbols. `=` symbols take the value of the latest definition.
+    pub constants: HashMap<String, Value>,
+    // Set when the program installs handlers, so the IVT has to be kept free
+    pub uses_interrupts: bool,
+    // Names declared with SEGMENT
+    pub segments: HashSet<String>,
+    // Types declared with STRUC, and the type of every variable that is an instance of one
+    pub structs: HashMap<String, Struct>,
+    pub variable_types: HashMap<String, String>,
+    // The line named by `end start`, where execution begins
+    pub entry: Option<usize>,
+    // The file being assembled, then every file it included
+    pub files: Vec<PathBuf>,
+    // For every source line, the index of the first instruction at or after it
+    next_instruction: Vec<usize>,
+}
+
+impl Program {
+    // The instruction executed when IP is `ip`: blank and comment lines are skipped.
+    pub fn at(&self, ip: usize) -> Option<&DecodedInstruction> {
+        let index = *self.next_instruction.get(ip)?;
+        self.instructions.get(index)
+    }
+
+    // `$` outside code: `location` bytes into the data, as an offset from the first variable
+    fn data_location(&self, location: usize) -> Option<Value> {
+        let (first, _) = self
+            .variables
+            .iter()
+            .find(|(_, metadata)| metadata.start_index == 0)?;
+        Some(Value {
+            constant: location as i64,
+            variable: Some(first.clone()),
+        })
+    }
+
+    // Where each element of a variable goes, relative to its start, and how wide it is.
+    // Elements of a structure follow its fields.
+    pub fn element_layout(
+        &self,
+        name: &str,
+        size: VariableSize,
+        index: usize,
+    ) -> (usize, VariableSize) {
+        let structure = self
+            .variable_types
+            .get(name)
+            .and_then(|type_name| self.structs.get(type_name));
+        let Some(structure) = structure.filter(|structure| structure.size > 0) else {
+            return (index * size.value(), size);
+        };
+        let elements: Vec<(usize, VariableSize)> = structure
+            .fields
+            .iter()
+            .flat_map(|field| {
+                let size = field.size();
+                (0..field.length / size.value())
+                    .map(move |i| (field.offset + i * size.value(), size))
+            })
+            .collect();
+        let (offset, size) = elements[index % elements.len()];
+        (index / elements.len() * structure.size + offset, size)
+    }
+
+    // `point.y`, `p1.y` or `.y`: the field, and the variable when the path starts at an instance.
+    // A bare `.y` must belong to exactly one structure.
+    pub fn field<'a>(&'a self, path: &'a str) -> Option<(Option<&'a str>, &'a Field)> {
+        let (base, name) = path.split_once('.')?;
+        let find = |structure: &'a Struct| structure.fields.iter().find(|field| field.name == name);
+        if base.is_empty() {
+            let mut found = self.structs.values().filter_map(find);
+            let field = found.next()?;
+            return found.next().is_none().then_some((None, field));
+        }
+        if let Some(structure) = self.structs.get(base) {
+            return find(structure).map(|field| (None, field));
+        }
+        let structure = self.structs.get(self.variable_types.get(base)?)?;
+        find(structure).map(|field| (Some(base), field))
+    }
+
+    // Where a jump or call to `name` lands
+    pub fn code_address(&self, name: &str) -> Option<usize> {
+        self.labels
+            .get(name)
+            .copied()
+            .or_else(|| self.procs.get(name).map(|(start, _)| *start))
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '@' | '$' | '?' | '.')
+}
+
+// TASM and NASM identifiers: letters, digits and `_ @ $ ? .`, not starting with a digit.
+// `$` and `?` alone are the location counter and an uninitialised value.
+pub fn is_valid_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|first| !first.is_ascii_digit() && is_name_char(first))
+        && chars.all(is_name_char)
+        && text != "$"
+        && text != "?"
+}
+
+// `@@name` labels (TASM) last until the next regular label, and `.name` labels (NASM) belong
+// to the regular label above them. Both are renamed after that label, to `scope@@name` and
+// `scope.name`, wherever their scope uses them.
+fn scope_local_labels(statements: &mut [(usize, Vec<String>)], options: LexerOptions) {
+    // GAS's `.L` labels are ordinary names
+    let is_local = |name: &str| {
+        name.starts_with("@@") || (name.starts_with('.') && options.dialect != Dialect::Att)
+    };
+    let mut scopes = Vec::with_capacity(statements.len());
+    let mut defined = HashSet::new();
+    let mut scope = String::new();
+    for (_, parts) in statements.iter() {
+        match parts.as_slice() {
+            [label] if label.ends_with(':') => {
+                let name = &label[..label.len() - 1];
+                if is_local(name) {
+                    defined.insert(format!("{scope}{name}"));
+                } else {
+                    scope = name.to_string();
+                }
+            }
+            _ => {
+                if let Some(ProcDirective::Proc { name, .. }) = proc_directive(parts) {
+                    scope = name.to_string();
+                }
+            }
+        }
+        scopes.push(scope.clone());
+    }
+
+    for ((_, parts), scope) in statements.iter_mut().zip(scopes) {
+        for part in parts.iter_mut() {
+            let Ok(tokens) = tokenize(part, 0, options) else {
+                continue;
+            };
+            let mut renamed = String::new();
+            let mut copied = 0;
+            for token in tokens.iter().filter(|token| token.kind == TokenKind::Word) {
+                let name = &part[token.start..token.end];
+                let scoped = format!("{scope}{name}");
+                if is_local(name) && defined.contains(&scoped) {
+                    renamed.push_str(&part[copied..token.start]);
+                    renamed.push_str(&scoped);
+                    copied = token.end;
+                }
+            }
+            if copied > 0 {
+                renamed.push_str(&part[copied..]);
+                *part = renamed;
+            }
+        }
+    }
+}
+
+// The largest definition, a 64K segment
+const MAX_DATA_LENGTH: usize = 0x10000;
+// TASM's stack when `.stack` gives no size
+const DEFAULT_STACK_SIZE: usize = 0x400;
+
+// Bytes per element of a data directive
+fn define_width(directive: &str) -> Option<usize> {
+    match directive.to_lowercase().as_str() {
+        "db" => Some(1),
+        "dw" => Some(2),
+        "dd" => Some(4),
+        "dq" => Some(8),
+        "dt" => Some(10),
+        _ => None,
+    }
+}
+
+// dq and dt elements don't fit a register, so they are stored byte by byte
+fn element_size(width: usize) -> VariableSize {
+    VariableSize::from_usize(width).unwrap_or(VariableSize::Byte)
+}
+
+// A line that lays out data
+enum DataDirective<'a> {
+    Define {
+        name: &'a str,
+        width: usize,
+        values: &'a [String],
+    },
+    // `name LABEL WORD`: a name for the data that follows
+    Label {
+        name: &'a str,
+        size: VariableSize,
+    },
+    Align(&'a str),
+    // `p1 point <3, 4>`: variables of a STRUC type
+    Instance {
+        name: &'a str,
+        type_name: String,
+        initializers: String,
+    },
+}
+
+fn data_directive<'a>(
+    parts: &'a [String],
+    structs: &HashMap<String, Struct>,
+) -> Option<DataDirective<'a>> {
+    if let [name, _, ..] = parts {
+        // The operands split on the commas inside <3, 4>, so they are put back together
+        let text = parts[1..].join(", ");
+        let end = text
+            .find(|c: char| c.is_whitespace() || c == ',')
+            .unwrap_or(text.len());
+        if is_valid_name(name) && structs.contains_key(&text[..end]) {
+            return Some(DataDirective::Instance {
+                name,
+                type_name: text[..end].to_string(),
+                initializers: text[end..]
+                    .trim_start()
+                    .trim_start_matches(',')
+                    .trim()
+                    .to_string(),
+            });
+        }
+    }
+    match parts {
+        [directive] if directive.eq_ignore_ascii_case("even") => Some(DataDirective::Align("2")),
+        [directive, alignment] if directive.eq_ignore_ascii_case("align") => {
+            Some(DataDirective::Align(alignment))
+        }
+        [name, directive, values @ ..]
+            if is_valid_name(name) && define_width(directive).is_some() =>
+        {
+            Some(DataDirective::Define {
+                name,
+                width: define_width(directive).expect("Checked by the match guard."),
+                values,
+            })
+        }
+        [name, declaration] if is_valid_name(name) => {
+            let (keyword, kind) = declaration.split_once(char::is_whitespace)?;
+            if !keyword.eq_ignore_ascii_case("label") {
+                return None;
+            }
+            let size = match kind.trim().to_uppercase().as_str() {
+                "BYTE" => VariableSize::Byte,
+                "WORD" => VariableSize::Word,
+                "DWORD" => VariableSize::DoubleWord,
+                _ => return None,
+            };
+            Some(DataDirective::Label { name, size })
+        }
+        _ => None,
+    }
+}
+
+// `name EQU expression` or `name = expression`, as (name, redefinable, expression)
+pub fn constant_definition(parts: &[String]) -> Option<(String, bool, String)> {
+    // `len = 5` splits after the name, `len=5` stays in one piece
+    let text = match parts {
+        [first] => first.clone(),
+        [first, second] => format!("{first} {second}"),
+        _ => return None,
+    };
+    let (name, redefinable, expression) = match text.split_once('=') {
+        Some((name, expression)) => (name.trim(), true, expression),
+        None => {
+            let (name, rest) = text.split_once(' ')?;
+            let (keyword, expression) = rest.split_once(char::is_whitespace)?;
+            if !keyword.eq_ignore_ascii_case("equ") {
+                return None;
+            }
+            (name, false, expression)
+        }
+    };
+    is_valid_name(name).then(|| (name.to_string(), redefinable, expression.trim().to_string()))
+}
+
+// TASM program structure: `.model small`, `.stack 100h`, `.data`, `.code`,
+// `name SEGMENT` ... `name ENDS`, `assume cs:code` and `end start`. `global _start` starts
+// the program at `_start`, as a linker would, and other GLOBAL and EXTERN names do nothing.
+enum Structure<'a> {
+    Model(&'a str),
+    Stack(Option<&'a str>),
+    Section,
+    Segment(&'a str),
+    Struc(&'a str),
+    Ends(&'a str),
+    Assume(&'a [String]),
+    End(Option<&'a str>),
+    Global(&'a [String]),
+}
+
+const MEMORY_MODELS: [&str; 7] = [
+    "tiny", "small", "compact", "medium", "large", "huge", "flat",
+];
+pub(crate) const SEGMENT_REGISTERS: [&str; 6] = ["cs", "ds", "es", "ss", "fs", "gs"];
+
+fn structure_directive(parts: &[String]) -> Option<Structure<'_>> {
+    let [first, rest @ ..] = parts else {
+        return None;
+    };
+    match (first.to_lowercase().as_str(), rest) {
+        (".model", [model, ..]) => Some(Structure::Model(model)),
+        (".stack", []) => Some(Structure::Stack(None)),
+        (".stack", [size]) => Some(Structure::Stack(Some(size))),
+        (".data" | ".data?" | ".const" | ".code", []) => Some(Structure::Section),
+        ("assume", operands) => Some(Structure::Assume(operands)),
+        ("global" | "extern" | "extrn" | "public", names) => Some(Structure::Global(names)),
+        ("end", []) => Some(Structure::End(None)),
+        ("end", [label]) => Some(Structure::End(Some(label))),
+        (_, [second]) if is_valid_name(first) => {
+            let keyword = second.split_whitespace().next().unwrap_or_default();
+            if keyword.eq_ignore_ascii_case("segment") {
+                Some(Structure::Segment(first))
+            } else if second.eq_ignore_ascii_case("struc") || second.eq_ignore_ascii_case("struct")
+            {
+                Some(Structure::Struc(first))
+            } else if second.eq_ignore_ascii_case("ends") {
+                Some(Structure::Ends(first))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// `name PROC [NEAR|FAR] [USES regs]`, and `name ENDP` or the older `name END`.
+// Memory is flat, so NEAR and FAR procs are called and return the same way.
+enum ProcDirective<'a> {
+    Proc { name: &'a str, uses: Vec<&'a str> },
+    Endp(&'a str),
+}
+
+fn proc_directive(parts: &[String]) -> Option<ProcDirective<'_>> {
+    let [name, rest, more @ ..] = parts else {
+        return None;
+    };
+    let mut words = rest
+        .split_whitespace()
+        .chain(more.iter().flat_map(|part| part.split_whitespace()));
+    let keyword = words.next()?;
+    if keyword.eq_ignore_ascii_case("endp") || keyword.eq_ignore_ascii_case("end") {
+        return (words.next().is_none() && more.is_empty()).then_some(ProcDirective::Endp(name));
+    }
+    if !keyword.eq_ignore_ascii_case("proc") || !is_valid_name(name) {
+        return None;
+    }
+    let mut words = words.peekable();
+    words.next_if(|word| word.eq_ignore_ascii_case("near") || word.eq_ignore_ascii_case("far"));
+    let uses = match words.next() {
+        None => Vec::new(),
+        Some(word) if word.eq_ignore_ascii_case("uses") => words.collect(),
+        Some(_) => return None,
+    };
+    Some(ProcDirective::Proc { name, uses })
+}
+
+// Lines inside a proc that declare its arguments, locals and saved registers
+const FRAME_DIRECTIVES: [&str; 3] = ["arg", "local", "uses"];
+
+fn is_frame_directive(parts: &[String]) -> bool {
+    parts
+        .first()
+        .is_some_and(|first| FRAME_DIRECTIVES.contains(&first.to_lowercase().as_str()))
+}
+
+// `name:type` from ARG or LOCAL, as the name and its size in bytes
+fn frame_variable(item: &str) -> Result<(&str, VariableSize), String> {
+    let (name, size) = match item.split_once(':') {
+        Some((name, kind)) => {
+            let size = match kind.trim().to_uppercase().as_str() {
+                "BYTE" => VariableSize::Byte,
+                "WORD" => VariableSize::Word,
+                "DWORD" => VariableSize::DoubleWord,
+                kind => return Err(format!("{kind} is not BYTE, WORD or DWORD")),
+            };
+            (name.trim(), size)
+        }
+        None => (item.trim(), VariableSize::Word),
+    };
+    if !is_valid_name(name) {
+        return Err(format!("{name} is not a valid name"));
+    }
+    Ok((name, size))
+}
+
+// Procs that declare ARG, LOCAL or USES get a BP frame: `enter` and the pushes start the
+// proc, and every `ret` in it pops the registers and leaves first. Arguments and locals are
+// replaced by BP-relative operands in the body: the first argument is `[BP+4]`, above the
+// saved BP and the return address, and locals count down from `[BP-1]`. A declaration that
+// fails goes to `errors` and is left out.
+fn add_frames(
+    lines: Vec<SourceLine>,
+    files: &[PathBuf],
+    options: LexerOptions,
+    errors: &mut Vec<ErrorCode>,
+) -> Vec<SourceLine> {
+    let parts: Vec<Option<Vec<String>>> = lines
+        .iter()
+        .map(|line| split_line(&line.text, line.line, options).ok().flatten())
+        .collect();
+    let mut output = Vec::with_capacity(lines.len());
+    let mut index = 0;
+    while index < lines.len() {
+        let Some(ProcDirective::Proc { uses, .. }) =
+            parts[index].as_deref().and_then(proc_directive)
+        else {
+            output.push(lines[index].clone());
+            index += 1;
+            continue;
+        };
+        let start = index;
+        index += 1;
+        while index < lines.len() && parts[index].as_deref().and_then(proc_directive).is_none() {
+            index += 1;
+        }
+        // Nested and unclosed procs are reported by collect_symbols
+        if !matches!(
+            parts
+                .get(index)
+                .and_then(Option::as_deref)
+                .and_then(proc_directive),
+            Some(ProcDirective::Endp(_))
+        ) {
+            output.extend_from_slice(&lines[start..index]);
+            continue;
+        }
+
+        let fail = |line: &SourceLine, message: String| {
+            let error = ErrorCode::SyntaxError {
+                file: None,
+                line: line.line + 1,
+                column: 1,
+                message,
+            };
+            match line.file {
+                0 => error,
+                file => in_file(error, &files[file]),
+            }
+        };
+        let mut registers: Vec<String> = uses
+            .iter()
+            .map(|register| register.to_uppercase())
+            .collect();
+        let mut replacements: HashMap<String, (&str, String)> = HashMap::new();
+        let mut argument = 4;
+        let mut frame = 0;
+        let mut has_frame = false;
+        for line in start + 1..index {
+            let Some([keyword, items @ ..]) = parts[line].as_deref() else {
+                continue;
+            };
+            let keyword = keyword.to_lowercase();
+            if keyword == "uses" {
+                registers.extend(
+                    items
+                        .iter()
+                        .flat_map(|item| item.split_whitespace())
+                        .map(str::to_uppercase),
+                );
+                continue;
+            } else if keyword != "arg" && keyword != "local" {
+                continue;
+            }
+            has_frame = true;
+            for item in items {
+                let (name, size) = match frame_variable(item) {
+                    Ok(variable) => variable,
+                    Err(message) => {
+                        errors.push(fail(&lines[line], message));
+                        continue;
+                    }
+                };
+                let pointer = match size {
+                    VariableSize::Byte => "BYTE",
+                    VariableSize::Word => "WORD",
+                    VariableSize::DoubleWord => "DWORD",
+                };
+                let operand = if keyword == "arg" {
+                    // Arguments are pushed a word at a time, so a byte is the low half of its word
+                    let offset = argument + usize::from(size == VariableSize::Byte);
+                    argument += size.value().max(2);
+                    format!("[BP+{offset}]")
+                } else {
+                    frame += size.value();
+                    format!("[BP-{frame}]")
+                };
+                if replacements
+                    .insert(name.to_string(), (pointer, operand))
+                    .is_some()
+                {
+                    errors.push(fail(&lines[line], format!("{name} is declared twice")));
+                }
+            }
+        }
+        for register in registers
+            .iter()
+            .filter(|register| !RegisterName::is_valid_name(register))
+        {
+            errors.push(fail(
+                &lines[start],
+                format!("USES expects registers, not {register}"),
+            ));
+        }
+        registers.retain(|register| RegisterName::is_valid_name(register));
+
+        let generated = |line: &SourceLine, text: String| SourceLine {
+            text,
+            ..line.clone()
+        };
+        output.push(lines[start].clone());
+        if has_frame {
+            output.push(generated(
+                &lines[start],
+                format!("enter {}, 0", frame.next_multiple_of(2)),
+            ));
+        }
+        for register in &registers {
+            output.push(generated(&lines[start], format!("push {register}")));
+        }
+        for line in start + 1..index {
+            let returns = parts[line]
+                .as_ref()
+                .is_some_and(|parts| parts[0].eq_ignore_ascii_case("ret"));
+            if returns {
+                for register in registers.iter().rev() {
+                    output.push(generated(&lines[line], format!("pop {register}")));
+                }
+                if has_frame {
+                    output.push(generated(&lines[line], "leave".to_string()));
+                }
+            }
+            let text = replace_frame_names(&lines[line].text, &replacements, options);
+            output.push(generated(&lines[line], text));
+        }
+        output.push(lines[index].clone());
+        index += 1;
+    }
+    output
+}
+
+// Replaces every argument and local in `text` by its operand. `[name]` is the same as `name`,
+// and `DWORD PTR name` overrides the declared size.
+fn replace_frame_names(
+    text: &str,
+    replacements: &HashMap<String, (&str, String)>,
+    options: LexerOptions,
+) -> String {
+    if replacements.is_empty() {
+        return text.to_string();
+    }
+    let Ok(tokens) = tokenize(text, 0, options) else {
+        return text.to_string();
+    };
+    let mut replaced = String::new();
+    let mut copied = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        let Some((pointer, operand)) = replacements
+            .get(&text[token.start..token.end])
+            .filter(|_| token.kind == TokenKind::Word)
+        else {
+            continue;
+        };
+        let (mut start, mut end) = (token.start, token.end);
+        let before = index.checked_sub(1).and_then(|before| tokens.get(before));
+        if let (Some(open), Some(close)) = (before, tokens.get(index + 1)) {
+            if open.kind == TokenKind::Punct('[') && close.kind == TokenKind::Punct(']') {
+                (start, end) = (open.start, close.end);
+            }
+        }
+        if start < copied {
+            continue;
+        }
+        let sized =
+            before.is_some_and(|before| text[before.start..before.end].eq_ignore_ascii_case("ptr"));
+        replaced.push_str(&text[copied..start]);
+        if !sized {
+            replaced.push_str(pointer);
+            replaced.push_str(" PTR ");
+        }
+        replaced.push_str(operand);
+        copied = end;
+    }
+    replaced.push_str(&text[copied..]);
+    replaced
+}
+
+pub fn assemble(lines: &[String], options: LexerOptions) -> Result<Program, ErrorCode> {
+    assemble_file(lines, options, &PreprocessOptions::default())
+}
+
+// Assembles `lines` as the contents of `files.main`, which INCLUDE paths are relative to
+pub fn assemble_file(
+    lines: &[String],
+    options: LexerOptions,
+    files: &PreprocessOptions,
+) -> Result<Program, ErrorCode> {
+    load(lines, options, files).map_err(|error| error.error)
+}
+
+/// As `assemble_file`, telling which line a load error comes from.
+pub fn load(
+    lines: &[String],
+    options: LexerOptions,
+    files: &PreprocessOptions,
+) -> Result<Program, LoadError> {
+    load_with(lines, options, files, false).map(|(program, _)| program)
+}
+
+/// As `load`, but goes on past the errors that stop a program from loading and returns them
+/// with what could be loaded: a bad line or macro call is left out, and a proc without ENDP
+/// runs to the end.
+pub fn load_all(
+    lines: &[String],
+    options: LexerOptions,
+    files: &PreprocessOptions,
+) -> (Program, Vec<LoadError>) {
+    load_with(lines, options, files, true).unwrap_or_else(|error| (Program::default(), vec![error]))
+}
+
+// Stops at the first error unless `recover`
+fn load_with(
+    lines: &[String],
+    options: LexerOptions,
+    files: &PreprocessOptions,
+    recover: bool,
+) -> Result<(Program, Vec<LoadError>), LoadError> {
+    let mut errors: Vec<LoadError> = Vec::new();
+    let stop = |errors: &mut Vec<LoadError>| {
+        if recover || errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.remove(0))
+        }
+    };
+    let Source { files, lines } = if recover {
+        let (source, found) = preprocess_all(lines, options, files)?;
+        errors.extend(found.into_iter().map(LoadError::from));
+        source
+    } else {
+        preprocess(lines, options, files)?
+    };
+    let mut found = Vec::new();
+    let lines = add_frames(lines, &files, options, &mut found);
+    errors.extend(found.into_iter().map(LoadError::from));
+    stop(&mut errors)?;
+    let mut statements: Vec<(usize, Vec<String>)> = Vec::new();
+    for (line, source) in lines.iter().enumerate() {
+        let parts = match (split_line(&source.text, source.line, options), source.file) {
+            (Ok(parts), _) => parts,
+            (Err(error), 0) => {
+                errors.push(error.into());
+                None
+            }
+            (Err(error), file) => {
+                errors.push(in_file(error, &files[file]).into());
+                None
+            }
+        };
+        stop(&mut errors)?;
+        if let Some(parts) = parts {
+            // Everything after `end` is ignored
+            let end = matches!(structure_directive(&parts), Some(Structure::End(_)));
+            statements.push((line, parts));
+            if end {
+                break;
+            }
+        }
+    }
+
+    scope_local_labels(&mut statements, options);
+
+    let mut program = Program {
+        files,
+        ..Program::default()
+    };
+    let locate = |program: &Program, (line, error): (usize, ErrorCode)| LoadError {
+        error,
+        location: Some((program.files[lines[line].file].clone(), lines[line].line)),
+    };
+    let mut found = Vec::new();
+    collect_symbols(&mut program, &statements, &mut found);
+    errors.extend(found.drain(..).map(|error| locate(&program, error)));
+    stop(&mut errors)?;
+    collect_data(&mut program, &statements, options, &mut found);
+    errors.extend(found.into_iter().map(|error| locate(&program, error)));
+    stop(&mut errors)?;
+
+    // Bytes of data laid out so far
+    let mut location = 0;
+    let mut in_struct = false;
+    for (line, parts) in statements {
+        let data_location = program.data_location(location);
+        let structure = structure_directive(&parts);
+        match structure {
+            Some(Structure::Struc(_)) => in_struct = true,
+            Some(Structure::Ends(name)) if program.structs.contains_key(name) => in_struct = false,
+            _ => {}
+        }
+        let instruction = match constant_definition(&parts) {
+            // Fields only describe the structure, and store nothing
+            _ if in_struct && structure.is_none() => DecodedInstruction {
+                mnemonic: Mnemonic::Directive,
+                operands: Vec::new(),
+                name: None,
+                line,
+                file: lines[line].file,
+                source_line: lines[line].line,
+                text: parts,
+                error: None,
+            },
+            Some((name, _, expression)) => {
+                let scope = Scope {
+                    program: &program,
+                    options,
+                    here: data_location,
+                };
+                let value = evaluate(&expression, &scope);
+                let mut instruction = DecodedInstruction {
+                    mnemonic: Mnemonic::Equ,
+                    operands: Vec::new(),
+                    name: Some(name.clone()),
+                    line,
+                    file: lines[line].file,
+                    source_line: lines[line].line,
+                    text: parts,
+                    error: None,
+                };
+                match value {
+                    Ok(value) => {
+                        program.constants.insert(name, value);
+                    }
+                    Err(error) => instruction.error = Some(error),
+                }
+                instruction
+            }
+            None => {
+                let here = if data_directive(&parts, &program.structs).is_some() {
+                    data_location
+                } else {
+                    Some(Value::number(line as i64))
+                };
+                let scope = Scope {
+                    program: &program,
+                    options,
+                    here,
+                };
+                let instruction = decode(&scope, line, &lines[line], parts);
+                match (instruction.mnemonic, instruction.operands.as_slice()) {
+                    (Mnemonic::Define(_), _) => {
+                        if let Some(metadata) = instruction
+                            .name
+                            .as_ref()
+                            .and_then(|name| program.variables.get(name))
+                        {
+                            location = metadata.start_index + metadata.length;
+                        }
+                    }
+                    (Mnemonic::Align, [Operand::Immediate(alignment)]) => {
+                        location = location.next_multiple_of(*alignment as usize);
+                    }
+                    _ => {}
+                }
+                instruction
+            }
+        };
+        program.instructions.push(instruction);
+    }
+
+    let mut index = program.instructions.len();
+    program.next_instruction = vec![index; lines.len() + 1];
+    for line in (0..lines.len()).rev() {
+        if index > 0 && program.instructions[index - 1].line == line {
+            index -= 1;
+        }
+        program.next_instruction[line] = index;
+    }
+    Ok((program, errors))
+}
+
+// First pass: labels and procs, so everything after can refer to code further down
+// Errors come with the line of the statement at fault, and the pass goes on past them
+fn collect_symbols(
+    program: &mut Program,
+    statements: &[(usize, Vec<String>)],
+    errors: &mut Vec<(usize, ErrorCode)>,
+) {
+    let mut current_proc: Option<(String, usize)> = None;
+    let mut open_segments: Vec<(&str, usize)> = Vec::new();
+    let mut entry = None;
+    for (line, parts) in statements {
+        match structure_directive(parts) {
+            Some(Structure::Segment(name)) => {
+                program.segments.insert(name.to_string());
+                open_segments.push((name, *line));
+                continue;
+            }
+            Some(Structure::Struc(name)) => {
+                open_segments.push((name, *line));
+                continue;
+            }
+            Some(Structure::Ends(name)) => {
+                if open_segments.pop().map(|(open, _)| open) != Some(name) {
+                    errors.push((
+                        *line,
+                        ErrorCode::InvalidOpcode(format!(
+                            "{name} ENDS doesn't close an open {name} SEGMENT or STRUC"
+                        )),
+                    ));
+                }
+                continue;
+            }
+            Some(Structure::End(label)) => entry = label.map(|label| (label, *line)),
+            Some(Structure::Global(names)) if names.iter().any(|name| name == "_start") => {
+                entry = Some(("_start", *line))
+            }
+            _ => {}
+        }
+        match proc_directive(parts) {
+            Some(ProcDirective::Proc { name, .. }) => {
+                if program.procs.contains_key(name) {
+                    errors.push((
+                        *line,
+                        ErrorCode::LabelAlreadyExists(format!("Proc {name} already exists")),
+                    ));
+                }
+                // The open proc ends where the next one starts
+                if let Some((open, start)) = current_proc.take() {
+                    errors.push((
+                        *line,
+                        ErrorCode::InvalidOpcode(format!(
+                        "Cannot start proc {name} inside proc {open}, which has no ENDP before it."
+                    )),
+                    ));
+                    program.procs.entry(open).or_insert((start + 1, *line));
+                }
+                current_proc = Some((name.to_string(), *line));
+                continue;
+            }
+            Some(ProcDirective::Endp(name)) => {
+                let Some((open, start)) = current_proc.take() else {
+                    errors.push((
+                        *line,
+                        ErrorCode::InvalidOpcode(format!("{name} ENDP is outside of a proc.")),
+                    ));
+                    continue;
+                };
+                if open != name {
+                    errors.push((
+                        *line,
+                        ErrorCode::InvalidOpcode(format!("{name} ENDP doesn't close proc {open}.")),
+                    ));
+                }
+                program.procs.entry(open).or_insert((start + 1, line + 1));
+                continue;
+            }
+            None => {}
+        }
+        if current_proc.is_none() && is_frame_directive(parts) {
+            errors.push((
+                *line,
+                ErrorCode::InvalidOpcode(format!(
+                    "{} is only allowed inside a proc.",
+                    parts[0].to_uppercase()
+                )),
+            ));
+            continue;
+        }
+        let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+        match parts.as_slice() {
+            [label] if label.ends_with(':') && is_valid_name(&label[..label.len() - 1]) => {
+                let name = &label[..label.len() - 1];
+                if program.labels.contains_key(name) {
+                    errors.push((
+                        *line,
+                        ErrorCode::LabelAlreadyExists(format!("Label {name} already exists")),
+                    ));
+                } else {
+                    program.labels.insert(name.to_string(), *line);
+                }
+            }
+            [op, ..] if INTERRUPT_MNEMONICS.contains(op) => program.uses_interrupts = true,
+            _ => {}
+        }
+    }
+    // A proc without ENDP runs to the end of the program
+    if let Some((name, line)) = current_proc {
+        errors.push((
+            line,
+            ErrorCode::InvalidOpcode(format!("Proc {name} has no ENDP")),
+        ));
+        let end = statements.last().map_or(line, |(last, _)| *last) + 1;
+        program.procs.entry(name).or_insert((line + 1, end));
+    }
+    for (name, line) in open_segments.into_iter().rev() {
+        errors.push((
+            line,
+            ErrorCode::InvalidOpcode(format!("{name} has no ENDS")),
+        ));
+    }
+    // `end main` may name a proc, and starts at its body
+    if let Some((label, line)) = entry {
+        let address = program
+            .labels
+            .get(label)
+            .or_else(|| program.procs.get(label).map(|(body, _)| body));
+        match address {
+            Some(address) => program.entry = Some(*address),
+            None => errors.push((
+                line,
+                ErrorCode::InvalidOpcode(format!(
+                    "END names {label}, which is not a label or proc"
+                )),
+            )),
+        }
+    }
+}
+
+// Data and constants are laid out in source order: `=` can redefine a constant, `$` counts
+// the data above it and a DUP count can use the constants above it.
+fn collect_data(
+    program: &mut Program,
+    statements: &[(usize, Vec<String>)],
+    options: LexerOptions,
+    errors: &mut Vec<(usize, ErrorCode)>,
+) {
+    let already_exists =
+        |name: &str| ErrorCode::LabelAlreadyExists(format!("Symbol {name} already exists"));
+    // Names defined with EQU, which can't be defined again
+    let mut fixed = HashSet::new();
+    let mut location = 0;
+    // The STRUC being declared, whose data lines are fields rather than variables
+    let mut open_struct: Option<(&str, Struct)> = None;
+    for (line, parts) in statements {
+        match structure_directive(parts) {
+            Some(Structure::Struc(name)) => {
+                if open_struct.is_some() {
+                    errors.push((
+                        *line,
+                        ErrorCode::InvalidOpcode(format!(
+                            "STRUC {name} can't be declared inside another"
+                        )),
+                    ));
+                    continue;
+                }
+                if program.structs.contains_key(name) || program.variables.contains_key(name) {
+                    errors.push((*line, already_exists(name)));
+                }
+                open_struct = Some((name, Struct::default()));
+                continue;
+            }
+            Some(Structure::Ends(name))
+                if open_struct.as_ref().is_some_and(|(open, _)| *open == name) =>
+            {
+                if let Some((name, structure)) = open_struct.take() {
+                    program.structs.entry(name.to_string()).or_insert(structure);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let scope = Scope {
+            program,
+            options,
+            here: program.data_location(location),
+        };
+        if let Some((type_name, structure)) = &mut open_struct {
+            let Some(DataDirective::Define {
+                name,
+                width,
+                values,
+            }) = data_directive(parts, &program.structs)
+            else {
+                errors.push((
+                    *line,
+                    ErrorCode::InvalidOpcode(format!(
+                        "STRUC {type_name} can only hold data definitions, not {}",
+                        parts.join(" ")
+                    )),
+                ));
+                continue;
+            };
+            if structure.fields.iter().any(|field| field.name == name) {
+                errors.push((*line, already_exists(&format!("{type_name}.{name}"))));
+                continue;
+            }
+            let items: Vec<&str> = values.iter().map(String::as_str).collect();
+            let length = match count_data(&scope, &items) {
+                Ok(count) => count * width,
+                Err(error) => {
+                    errors.push((*line, error));
+                    continue;
+                }
+            };
+            structure.fields.push(Field {
+                name: name.to_string(),
+                offset: structure.size,
+                width,
+                length,
+                defaults: values.to_vec(),
+            });
+            structure.size += length;
+            continue;
+        }
+        // Definitions that don't evaluate report their error when the line runs
+        if let Some(directive) = data_directive(parts, &program.structs) {
+            let (name, metadata) = match directive {
+                DataDirective::Define {
+                    name,
+                    width,
+                    values,
+                } => {
+                    let values: Vec<&str> = values.iter().map(String::as_str).collect();
+                    let length = count_data(&scope, &values).unwrap_or(0) * width;
+                    let metadata = VariableMetadata {
+                        element_size: width,
+                        ..VariableMetadata::new(location, length, element_size(width))
+                    };
+                    (name, metadata)
+                }
+                DataDirective::Instance {
+                    name,
+                    type_name,
+                    initializers,
+                } => {
+                    let size = program.structs[&type_name].size;
+                    let items = split_items(&initializers, options);
+                    let length = count_instances(&scope, &items).unwrap_or(0) * size;
+                    program.variable_types.insert(name.to_string(), type_name);
+                    let metadata = VariableMetadata {
+                        element_size: size.max(1),
+                        ..VariableMetadata::new(location, length, VariableSize::Byte)
+                    };
+                    (name, metadata)
+                }
+                DataDirective::Label { name, size } => {
+                    (name, VariableMetadata::new(location, 0, size))
+                }
+                DataDirective::Align(alignment) => {
+                    location =
+                        location.next_multiple_of(decode_alignment(&scope, alignment).unwrap_or(1));
+                    continue;
+                }
+            };
+            if program.constants.contains_key(name) || program.structs.contains_key(name) {
+                errors.push((*line, already_exists(name)));
+                continue;
+            }
+            location += metadata.length;
+            program.variables.insert(name.to_string(), metadata);
+            continue;
+        }
+
+        let Some((name, redefinable, expression)) = constant_definition(parts) else {
+            continue;
+        };
+        if fixed.contains(&name)
+            || (!redefinable && program.constants.contains_key(&name))
+            || program.variables.contains_key(&name)
+            || program.code_address(&name).is_some()
+        {
+            errors.push((*line, already_exists(&name)));
+            continue;
+        }
+        if !redefinable {
+            fixed.insert(name.clone());
+        }
+        if let Ok(value) = evaluate(&expression, &scope) {
+            program.constants.insert(name, value);
+        }
+    }
+}
+
+// Second pass: one line into one instruction
+fn decode(
+    scope: &Scope,
+    line: usize,
+    source: &SourceLine,
+    text: Vec<String>,
+) -> DecodedInstruction {
+    let program = scope.program;
+    let mut instruction = DecodedInstruction {
+        mnemonic: Mnemonic::Unknown,
+        operands: Vec::new(),
+        name: None,
+        line,
+        file: source.file,
+        source_line: source.line,
+        text,
+        error: None,
+    };
+    if let Some(structure) = structure_directive(&instruction.text) {
+        let result = decode_structure(scope, structure);
+        instruction.mnemonic = match result {
+            Ok(Some(_)) => Mnemonic::Stack,
+            _ => Mnemonic::Directive,
+        };
+        match result {
+            Ok(size) => instruction.operands = size.into_iter().collect(),
+            Err(error) => instruction.error = Some(error),
+        }
+        return instruction;
+    }
+    let parts: Vec<&str> = instruction.text.iter().map(String::as_str).collect();
+    let proc_line = proc_directive(&instruction.text);
+    let result = match (
+        data_directive(&instruction.text, &program.structs),
+        parts.as_slice(),
+    ) {
+        (Some(DataDirective::Define { name, width, .. }), [_, _, values @ ..]) => {
+            instruction.mnemonic = Mnemonic::Define(element_size(width));
+            instruction.name = Some(name.to_string());
+            decode_data(scope, values, width)
+        }
+        (Some(DataDirective::Label { name, size }), _) => {
+            instruction.mnemonic = Mnemonic::Define(size);
+            instruction.name = Some(name.to_string());
+            Ok(Vec::new())
+        }
+        (
+            Some(DataDirective::Instance {
+                name,
+                type_name,
+                initializers,
+            }),
+            _,
+        ) => {
+            instruction.mnemonic = Mnemonic::Define(VariableSize::Byte);
+            instruction.name = Some(name.to_string());
+            let items = split_items(&initializers, scope.options);
+            decode_instances(scope, &program.structs[&type_name], &items)
+        }
+        (Some(DataDirective::Align(alignment)), _) => {
+            instruction.mnemonic = Mnemonic::Align;
+            decode_alignment(scope, alignment)
+                .map(|alignment| vec![Operand::Immediate(alignment as i64)])
+        }
+        (_, [label]) if label.ends_with(':') => {
+            let name = &label[..label.len() - 1];
+            instruction.mnemonic = Mnemonic::Label;
+            instruction.name = Some(name.to_string());
+            if program.labels.contains_key(name) {
+                Ok(Vec::new())
+            } else {
+                Err(ErrorCode::InvalidOpcode(format!(
+                    "Unknown instruction: {:?}.\nPerhaps you misspelt the label name?",
+                    label
+                )))
+            }
+        }
+        _ if matches!(proc_line, Some(ProcDirective::Endp(_))) => {
+            instruction.name = parts.first().map(|name| name.to_string());
+            instruction.mnemonic = Mnemonic::EndProc;
+            Err(ErrorCode::InvalidOpcode("Must return in proc.".to_string()))
+        }
+        (_, [name, ..]) if proc_line.is_some() => {
+            instruction.name = Some(name.to_string());
+            instruction.mnemonic = Mnemonic::Proc;
+            match program.procs.get(*name) {
+                Some((_, end)) => Ok(vec![Operand::Label(Label {
+                    name: name.to_string(),
+                    address: *end,
+                })]),
+                None => Err(ErrorCode::InvalidOpcode(format!(
+                    "Unknown instruction: {:?}.\nPerhaps you misspelt the proc name?",
+                    name
+                ))),
+            }
+        }
+        // ARG, LOCAL and USES were turned into the proc's frame
+        _ if is_frame_directive(&instruction.text) => {
+            instruction.mnemonic = Mnemonic::Directive;
+            Ok(Vec::new())
+        }
+        // Memory is flat, so `mov ds, ax` has nothing to change
+        (_, ["mov", segment, _])
+            if SEGMENT_REGISTERS.contains(&segment.to_lowercase().as_str()) =>
+        {
+            instruction.mnemonic = Mnemonic::Directive;
+            Ok(Vec::new())
+        }
+        (_, [op, rest @ ..]) => match Mnemonic::parse(op) {
+            Some(mnemonic) => {
+                instruction.mnemonic = mnemonic;
+                decode_operands(scope, mnemonic, rest).and_then(|operands| {
+                    if !has_valid_operands(mnemonic, &operands) {
+                        return Err(ErrorCode::InvalidOpcode(instruction.text.join(", ")));
+                    }
+                    check_immediate_range(&operands, &instruction.text)?;
+                    Ok(operands)
+                })
+            }
+            None => Err(ErrorCode::InvalidOpcode(format!(
+                "Unknown instruction: {:?}",
+                instruction.text
+            ))),
+        },
+        (_, []) => Ok(Vec::new()),
+    };
+    match result {
+        Ok(operands) => instruction.operands = operands,
+        Err(error) => instruction.error = Some(error),
+    }
+    instruction
+}
+
+// Checks a structure directive. Gives the stack size for `.stack`.
+fn decode_structure(scope: &Scope, structure: Structure) -> Result<Option<Operand>, ErrorCode> {
+    match structure {
+        Structure::Model(model) => {
+            if !MEMORY_MODELS.contains(&model.to_lowercase().as_str()) {
+                return Err(ErrorCode::InvalidValue(format!(
+                    "Unknown memory model {model}"
+                )));
+            }
+        }
+        Structure::Stack(size) => {
+            let size = match size {
+                Some(size) => evaluate(size, scope)
+                    .ok()
+                    .filter(|size| size.variable.is_none())
+                    .and_then(|size| usize::try_from(size.constant).ok())
+                    .filter(|size| (2..=MAX_DATA_LENGTH).contains(size))
+                    .ok_or_else(|| {
+                        ErrorCode::InvalidValue(format!(
+                            "Stack size {size} must be a number from 2 to {MAX_DATA_LENGTH}"
+                        ))
+                    })?,
+                None => DEFAULT_STACK_SIZE,
+            };
+            return Ok(Some(Operand::Immediate(size as i64)));
+        }
+        Structure::Assume(operands) => {
+            for operand in operands {
+                let known = operand.split_once(':').is_some_and(|(register, segment)| {
+                    let segment = segment.trim();
+                    SEGMENT_REGISTERS.contains(&register.trim().to_lowercase().as_str())
+                        && (scope.program.segments.contains(segment)
+                            || ["nothing", "@data", "@code", "@stack"]
+                                .contains(&segment.to_lowercase().as_str()))
+                });
+                if !known {
+                    return Err(ErrorCode::InvalidValue(format!(
+                        "ASSUME expects register:segment, not {operand}"
+                    )));
+                }
+            }
+        }
+        Structure::Section
+        | Structure::Segment(_)
+        | Structure::Struc(_)
+        | Structure::Ends(_)
+        | Structure::End(_)
+        | Structure::Global(_) => {}
+    }
+    Ok(None)
+}
+
+// `count DUP(items)`, as the count and the items
+fn split_dup(item: &str, options: LexerOptions) -> Option<(&str, Vec<&str>)> {
+    let tokens = tokenize(item, 0, options).ok()?;
+    let dup = tokens.iter().position(|token| {
+        token.kind == TokenKind::Word && item[token.start..token.end].eq_ignore_ascii_case("dup")
+    })?;
+    let open = tokens
+        .get(dup + 1)
+        .filter(|token| token.kind == TokenKind::Punct('('))?;
+    let close = tokens
+        .last()
+        .filter(|token| token.kind == TokenKind::Punct(')'))?;
+    let inner = &item[open.end..close.start];
+    Some((
+        item[..tokens[dup].start].trim(),
+        split_items(inner, options),
+    ))
+}
+
+// Splits on the commas outside strings, parentheses and <...>
+fn split_items(text: &str, options: LexerOptions) -> Vec<&str> {
+    let Ok(tokens) = tokenize(text, 0, options) else {
+        return vec![text.trim()];
+    };
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for token in tokens {
+        match token.kind {
+            TokenKind::Punct('(' | '<') => depth += 1,
+            TokenKind::Punct(')' | '>') => depth -= 1,
+            TokenKind::Punct(',') if depth == 0 => {
+                items.push(text[start..token.start].trim());
+                start = token.end;
+            }
+            _ => {}
+        }
+    }
+    items.push(text[start..].trim());
+    items
+}
+
+fn dup_count(scope: &Scope, text: &str) -> Result<usize, ErrorCode> {
+    evaluate(text, scope)
+        .ok()
+        .filter(|count| count.variable.is_none())
+        .and_then(|count| usize::try_from(count.constant).ok())
+        .ok_or_else(|| {
+            ErrorCode::InvalidValue(format!("DUP count {text} must be a positive number"))
+        })
+}
+
+// The number of elements a data definition lays out
+fn count_data(scope: &Scope, items: &[&str]) -> Result<usize, ErrorCode> {
+    let mut count: usize = 0;
+    for item in items {
+        let elements = if let Some((times, inner)) = split_dup(item, scope.options) {
+            dup_count(scope, times)?.saturating_mul(count_data(scope, &inner)?)
+        } else if let Some(string) = unquote(item, scope.options) {
+            string.chars().count()
+        } else {
+            1
+        };
+        count = count.saturating_add(elements);
+    }
+    Ok(count)
+}
+
+// The number of structures `<3, 4>, ?, 2 DUP(<>)` lays out
+fn count_instances(scope: &Scope, items: &[&str]) -> Result<usize, ErrorCode> {
+    let mut count: usize = 0;
+    for item in items {
+        let instances = match split_dup(item, scope.options) {
+            Some((times, inner)) => {
+                dup_count(scope, times)?.saturating_mul(count_instances(scope, &inner)?)
+            }
+            None => 1,
+        };
+        count = count.saturating_add(instances);
+    }
+    Ok(count)
+}
+
+// Every element of the structures, field by field. Fields left out or given as `?` keep the
+// values the STRUC declared, and a string shorter than its field is padded with zeros.
+fn decode_instances(
+    scope: &Scope,
+    structure: &Struct,
+    items: &[&str],
+) -> Result<Vec<Operand>, ErrorCode> {
+    let length = count_instances(scope, items)?.saturating_mul(structure.size);
+    if length > MAX_DATA_LENGTH {
+        return Err(ErrorCode::NotEnoughSpace(format!(
+            "{length} bytes of data don't fit in a {MAX_DATA_LENGTH}-byte segment"
+        )));
+    }
+    let mut operands = Vec::new();
+    for &item in items {
+        if let Some((times, inner)) = split_dup(item, scope.options) {
+            let once = decode_instances(scope, structure, &inner)?;
+            for _ in 0..dup_count(scope, times)? {
+                operands.extend(once.iter().cloned());
+            }
+            continue;
+        }
+        let values = match item
+            .strip_prefix('<')
+            .and_then(|rest| rest.strip_suffix('>'))
+        {
+            Some(inner) => split_items(inner, scope.options),
+            None if item == "?" => Vec::new(),
+            None => {
+                return Err(ErrorCode::InvalidValue(format!(
+                    "A structure is initialised with <...> or ?, not {item}"
+                )))
+            }
+        };
+        if values.len() > structure.fields.len() {
+            return Err(ErrorCode::InvalidValue(format!(
+                "{item} has {} values for {} fields",
+                values.len(),
+                structure.fields.len()
+            )));
+        }
+        for (i, field) in structure.fields.iter().enumerate() {
+            let mut elements = Vec::new();
+            match values
+                .get(i)
+                .filter(|value| !value.is_empty() && **value != "?")
+            {
+                Some(value) => expand_data(scope, &[value], field.width, &mut elements)?,
+                None => {
+                    let defaults: Vec<&str> = field.defaults.iter().map(String::as_str).collect();
+                    expand_data(scope, &defaults, field.width, &mut elements)?
+                }
+            }
+            let count = field.length / field.size().value();
+            if elements.len() > count {
+                return Err(ErrorCode::InvalidValue(format!(
+                    "{} doesn't fit in field {}",
+                    values[i], field.name
+                )));
+            }
+            elements.resize(count, Operand::Immediate(0));
+            operands.extend(elements);
+        }
+    }
+    Ok(operands)
+}
+
+fn decode_alignment(scope: &Scope, text: &str) -> Result<usize, ErrorCode> {
+    evaluate(text, scope)
+        .ok()
+        .filter(|alignment| alignment.variable.is_none())
+        .and_then(|alignment| usize::try_from(alignment.constant).ok())
+        .filter(|alignment| alignment.is_power_of_two())
+        .ok_or_else(|| ErrorCode::InvalidValue(format!("Alignment {text} must be a power of two")))
+}
+
+// The values of a data definition: expressions, `?`, DUP and strings stored one character
+// per element. dq and dt elements are spread over their bytes, most significant first.
+fn decode_data(scope: &Scope, items: &[&str], width: usize) -> Result<Vec<Operand>, ErrorCode> {
+    let length = count_data(scope, items)?.saturating_mul(width);
+    if length > MAX_DATA_LENGTH {
+        return Err(ErrorCode::NotEnoughSpace(format!(
+            "{length} bytes of data don't fit in a {MAX_DATA_LENGTH}-byte segment"
+        )));
+    }
+    let mut operands = Vec::new();
+    expand_data(scope, items, width, &mut operands)?;
+    Ok(operands)
+}
+
+fn expand_data(
+    scope: &Scope,
+    items: &[&str],
+    width: usize,
+    operands: &mut Vec<Operand>,
+) -> Result<(), ErrorCode> {
+    for &item in items {
+        if let Some((times, inner)) = split_dup(item, scope.options) {
+            let times = dup_count(scope, times)?;
+            let mut once = Vec::new();
+            expand_data(scope, &inner, width, &mut once)?;
+            for _ in 0..times {
+                operands.extend(once.iter().cloned());
+            }
+        } else if item == "?" {
+            // Uninitialised, which reads as zero
+            push_element(operands, item, width, Value::number(0))?;
+        } else if let Some(string) = unquote(item, scope.options) {
+            for c in string.chars() {
+                push_element(operands, item, width, Value::number(c as i64))?;
+            }
+        } else {
+            push_element(operands, item, width, evaluate(item, scope)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn push_element(
+    operands: &mut Vec<Operand>,
+    text: &str,
+    width: usize,
+    value: Value,
+) -> Result<(), ErrorCode> {
+    let size = element_size(width);
+    if width == size.value() {
+        if value.variable.is_none() && !fits_in(value.constant as i128, size) {
+            return Err(out_of_range(text, size));
+        }
+        operands.push(Operand::from_value(value));
+    } else {
+        if value.variable.is_some() {
+            return Err(ErrorCode::InvalidValue(format!(
+                "{text} is an address, which can't be stored in a {}-bit element",
+                width * 8
+            )));
+        }
+        let constant = value.constant as i128;
+        operands.extend(
+            (0..width)
+                .rev()
+                .map(|byte| Operand::Immediate((constant >> (byte * 8) & 0xFF) as i64)),
+        );
+    }
+    Ok(())
+}
+
+fn decode_operands(
+    scope: &Scope,
+    mnemonic: Mnemonic,
+    operands: &[&str],
+) -> Result<Vec<Operand>, ErrorCode> {
+    let options = scope.options;
+    let mut decoded = operands
+        .iter()
+        .map(|text| match text.strip_prefix("char ") {
+            Some(rest) if mnemonic == Mnemonic::Print => {
+                Ok(Operand::Char(Box::new(decode_operand(scope, rest.trim())?)))
+            }
+            _ if mnemonic == Mnemonic::Push && sized_constant(text).is_some() => {
+                let (size, rest) = sized_constant(text).expect("Checked by the match guard.");
+                match decode_operand(scope, rest)? {
+                    Operand::Immediate(value) if !fits_in(value as i128, size) => {
+                        Err(out_of_range(text, size))
+                    }
+                    operand @ (Operand::Immediate(_) | Operand::Address(_)) => {
+                        Ok(Operand::Sized(Box::new(operand), size))
+                    }
+                    _ => Err(ErrorCode::InvalidValue(format!(
+                        "{text} must be a constant"
+                    ))),
+                }
+            }
+            _ => match decode_operand(scope, text)? {
+                // Outside print, a quoted operand is a character immediate: mov AL, 'A'
+                Operand::Text(_) if mnemonic != Mnemonic::Print => unquote(text, options)
+                    .and_then(|string| char_immediate(&string, options.dialect))
+                    .filter(|value| fits_in(*value, VariableSize::DoubleWord))
+                    .map(|value| Operand::Immediate(value as i64))
+                    .ok_or_else(|| {
+                        ErrorCode::InvalidValue(format!(
+                            "{text} must hold 1 to 4 characters to be used as a number"
+                        ))
+                    }),
+                operand => Ok(operand),
+            },
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // `lea DX, msg` is `lea DX, [msg]`, as in TASM
+    if let (Mnemonic::Lea, [_, source]) = (mnemonic, decoded.as_mut_slice()) {
+        if let Operand::Address(memory) = source {
+            let memory = memory.clone();
+            *source = Operand::Memory(memory);
+        }
+    }
+    // NASM variables have no size, so `mov bx, [row+2]` reads as many bytes as the register holds
+    let sized = operands.iter().any(|text| text.contains("PTR"));
+    if options.dialect == Dialect::Nasm
+        && !sized
+        && matches!(
+            mnemonic,
+            Mnemonic::Mov | Mnemonic::Add | Mnemonic::Sub | Mnemonic::Cmp
+        )
+    {
+        if let [Operand::Memory(memory), Operand::Register(register)]
+        | [Operand::Register(register), Operand::Memory(memory)] = decoded.as_mut_slice()
+        {
+            memory.size = Some(get_register_size(register));
+        }
+    }
+    Ok(decoded)
+}
+
+// `WORD 5` as its size and constant. `WORD PTR [x]` is memory.
+fn sized_constant(text: &str) -> Option<(VariableSize, &str)> {
+    let (keyword, rest) = text.split_once(char::is_whitespace)?;
+    let size = match keyword {
+        "BYTE" => VariableSize::Byte,
+        "WORD" => VariableSize::Word,
+        "DWORD" => VariableSize::DoubleWord,
+        _ => return None,
+    };
+    let rest = rest.trim();
+    (!rest.starts_with("PTR")).then_some((size, rest))
+}
+
+fn out_of_range(text: &str, size: VariableSize) -> ErrorCode {
+    ErrorCode::InvalidValue(format!(
+        "Value {text} is out of range for a {}-bit operand",
+        size.value() * 8
+    ))
+}
+
+// Constants must fit the register or memory operand they are combined with
+fn check_immediate_range(operands: &[Operand], text: &[String]) -> Result<(), ErrorCode> {
+    let size = match operands.first() {
+        Some(Operand::Register(register)) => get_register_size(register),
+        Some(Operand::Memory(memory)) => memory.size(),
+        _ => return Ok(()),
+    };
+    for (operand, text) in operands.iter().zip(&text[1..]).skip(1) {
+        if let Operand::Immediate(value) = operand {
+            if !fits_in(*value as i128, size) {
+                return Err(out_of_range(text, size));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn decode_operand(scope: &Scope, text: &str) -> Result<Operand, ErrorCode> {
+    let program = scope.program;
+    for (keyword, size) in [
+        ("BYTE", VariableSize::Byte),
+        ("WORD", VariableSize::Word),
+        ("DWORD", VariableSize::DoubleWord),
+    ] {
+        let Some(rest) = text.strip_prefix(keyword) else {
+            continue;
+        };
+        if let Some(pointer) = rest.trim_start().strip_prefix("PTR") {
+            let pointer = pointer.trim();
+            if !(rest.starts_with(char::is_whitespace) && pointer.starts_with('[')) {
+                return Err(ErrorCode::InvalidPointer("Invalid Syntax.".to_string()));
+            }
+            return decode_memory(scope, pointer, Some(size)).map(Operand::Memory);
+        }
+    }
+
+    if unquote(text, scope.options).is_some() {
+        Ok(Operand::Text(text.to_string()))
+    } else if text.starts_with('[') {
+        decode_memory(scope, text, None).map(Operand::Memory)
+    } else if let Ok(register) = RegisterName::from_str_to_reg_name(text) {
+        Ok(Operand::Register(register))
+    } else if let Some(address) = program.code_address(text) {
+        Ok(Operand::Label(Label {
+            name: text.to_string(),
+            address,
+        }))
+    } else {
+        let value = evaluate(text, scope)?;
+        // Nothing is wider than 32 bits yet
+        if value.variable.is_none() && !fits_in(value.constant as i128, VariableSize::DoubleWord) {
+            return Err(out_of_range(text, VariableSize::DoubleWord));
+        }
+        Ok(Operand::from_value(value))
+    }
+}
+
+fn decode_memory(
+    scope: &Scope,
+    text: &str,
+    size: Option<VariableSize>,
+) -> Result<MemoryOperand, ErrorCode> {
+    let program = scope.program;
+    // `[bx].y` adds the offset of field y and reads at its size
+    if let Some(end) = text.rfind("].") {
+        let mut memory = decode_memory(scope, &text[..=end], size)?;
+        let path = &text[end + 1..];
+        let (_, field) = program.field(path).ok_or_else(|| {
+            ErrorCode::InvalidValue(format!("{path} is not a field of exactly one STRUC"))
+        })?;
+        memory.disp += field.offset as i64;
+        memory.size = size.or(Some(field.size()));
+        return Ok(memory);
+    }
+    let Some(expression) = text
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+    else {
+        return Err(ErrorCode::InvalidPointer(
+            "Memory Operand must be enveloped in []".to_string(),
+        ));
+    };
+    let invalid_mode = || ErrorCode::InvalidPointer("Invalid Addressing Mode.".to_string());
+
+    // Split into signed terms, leaving parenthesised expressions whole
+    let mut terms: Vec<(bool, &str)> = Vec::new();
+    let mut negative = false;
+    let mut start = 0;
+    let mut depth = 0;
+    for (i, c) in expression.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 && (c == '+' || c == '-') {
+            terms.push((negative, expression[start..i].trim()));
+            negative = c == '-';
+            start = i + 1;
+        }
+    }
+    terms.push((negative, expression[start..].trim()));
+    // A leading sign, as in [-4]
+    if terms.len() > 1 && terms[0].1.is_empty() {
+        terms.remove(0);
+    }
+
+    let mut memory = MemoryOperand {
+        base: None,
+        index: None,
+        scale: 1,
+        disp: 0,
+        size,
+        variable: None,
+    };
+    let mut lone_label = false;
+    for &(negative, term) in &terms {
+        let sign = if negative { -1 } else { 1 };
+        let scaled = term.split_once('*').and_then(|(left, right)| {
+            let (left, right) = (left.trim(), right.trim());
+            match RegisterName::from_str_to_reg_name(left) {
+                Ok(register) => Some((register, right)),
+                Err(_) => RegisterName::from_str_to_reg_name(right)
+                    .ok()
+                    .map(|register| (register, left)),
+            }
+        });
+        if let Some((register, scale_part)) = scaled {
+            let scale = evaluate(scale_part, scope)
+                .ok()
+                .filter(|scale| scale.variable.is_none())
+                .and_then(|scale| u32::try_from(scale.constant).ok())
+                .ok_or(ErrorCode::InvalidValue(format!(
+                    "Invalid scale factor: {}",
+                    scale_part
+                )))?;
+            if negative || memory.index.is_some() {
+                return Err(invalid_mode());
+            }
+            memory.index = Some(register);
+            memory.scale = scale;
+        } else if let Ok(register) = RegisterName::from_str_to_reg_name(term) {
+            if negative {
+                return Err(invalid_mode());
+            }
+            if memory.base.is_none() {
+                memory.base = Some(register);
+            } else if memory.index.is_none() {
+                memory.index = Some(register);
+            } else {
+                return Err(invalid_mode());
+            }
+        } else if let Some(address) = program.code_address(term) {
+            memory.disp += sign * address as i64;
+            lone_label = terms.len() == 1;
+        } else {
+            // A name that is nothing else was probably meant to be a register
+            let value = evaluate(term, scope).map_err(|error| {
+                if is_valid_name(term) {
+                    ErrorCode::InvalidRegister(term.to_string())
+                } else {
+                    error
+                }
+            })?;
+            if let Some(variable) = value.variable {
+                if negative || memory.variable.is_some() {
+                    return Err(invalid_mode());
+                }
+                memory.variable = Some(variable);
+            }
+            memory.disp += sign * value.constant;
+        }
+    }
+
+    // [p1.y] reads as the field's size, [var] as the variable's declared size, [label] as a word
+    let field = terms.iter().find_map(|(_, term)| program.field(term));
+    if memory.size.is_none() {
+        if let Some((_, field)) = field {
+            memory.size = Some(field.size());
+        } else if let (Some(variable), 1) = (&memory.variable, terms.len()) {
+            memory.size = program
+                .variables
+                .get(variable)
+                .map(|metadata| metadata.size);
+        } else if lone_label {
+            memory.size = Some(VariableSize::Word);
+        }
+    }
+    Ok(memory)
+}
+
+// The operand shapes each instruction accepts. Sizes are checked when it executes.
+fn has_valid_operands(mnemonic: Mnemonic, operands: &[Operand]) -> bool {
+    use Operand::{Char, Immediate, Label, Memory, Register};
+    let port_registers = [RegisterName::AL, RegisterName::AX];
+    let is_port = |operand: &Operand| match operand {
+        Register(RegisterName::DX) => true,
+        Immediate(port) => (0..=u8::MAX as i64).contains(port),
+        _ => false,
+    };
+    match mnemonic {
+        Mnemonic::Inc | Mnemonic::Dec => matches!(operands, [Register(_) | Memory(_)]),
+        Mnemonic::Lea => matches!(operands, [Register(_), Memory(_)]),
+        Mnemonic::Mov | Mnemonic::Add | Mnemonic::Sub => {
+            matches!(operands, [Register(_) | Memory(_), source] if source.is_value())
+        }
+        Mnemonic::Mul | Mnemonic::Imul | Mnemonic::Div | Mnemonic::Idiv | Mnemonic::Push => {
+            matches!(operands, [source] if source.is_value())
+        }
+        Mnemonic::Pop => matches!(operands, [Register(_) | Memory(_)]),
+        Mnemonic::Shl | Mnemonic::Shr => matches!(
+            operands,
+            [Register(_) | Memory(_), count]
+                if count.is_register(&[RegisterName::CL]) || matches!(count, Immediate(_))
+        ),
+        Mnemonic::Cmp => {
+            matches!(operands, [first, second] if first.is_value() && second.is_value())
+        }
+        Mnemonic::Int => matches!(operands, [vector] if vector.is_value()),
+        mnemonic if mnemonic.is_jump() || mnemonic == Mnemonic::Call => {
+            matches!(
+                operands,
+                [Label(_) | Register(_) | Immediate(_) | Memory(_)]
+            )
+        }
+        Mnemonic::Enter => {
+            matches!(operands, [Immediate(size), Immediate(0)] if (0..=u16::MAX as i64).contains(size))
+        }
+        Mnemonic::Ret
+        | Mnemonic::Leave
+        | Mnemonic::Int3
+        | Mnemonic::Into
+        | Mnemonic::Iret
+        | Mnemonic::Cli
+        | Mnemonic::Sti
+        | Mnemonic::Hlt => operands.is_empty(),
+        Mnemonic::In => {
+            matches!(operands, [dest, port] if dest.is_register(&port_registers) && is_port(port))
+        }
+        Mnemonic::Out => {
+            matches!(operands, [port, source] if is_port(port) && source.is_register(&port_registers))
+        }
+        Mnemonic::Print => match operands {
+            [Operand::Text(_)] => true,
+            [Char(value)] => value.is_value(),
+            [value] => value.is_value(),
+            [count, Memory(_)] => count.is_value(),
+            [count, Char(memory)] => count.is_value() && matches!(**memory, Memory(_)),
+            _ => false,
+        },
+        _ => true,
+    }
+}
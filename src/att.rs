@@ -0,0 +1,332 @@
+//! The AT&T dialect, as GAS and `gcc -S -m32` write it. Files are rewritten line for line
+//! into the TASM syntax the rest of the assembler reads, so both dialects produce the same
+//! program:
+//!
+//! * `movl $5, -4(%ebp)` becomes `mov DWORD PTR [BP-4], 5`: registers lose their `%`,
+//!   constants their `$`, the size suffix becomes a PTR and the operands swap around.
+//! * `disp(base, index, scale)` becomes `[base + index*scale + disp]`.
+//! * `.text`, `.data`, `.bss` and `.section` pick a section, `.globl _start` the entry point.
+//! * `name:` followed by `.long`, `.byte`, `.string` and the like is a variable.
+//!
+//! The simulator's stack is 16 bits wide: ESP and EBP are SP and BP, calls push a word and
+//! so does `pushl %ebp`. Arguments at `8(%ebp)` and above are moved down by 4 bytes to match.
+
+use crate::{
+    assembler::Mnemonic,
+    lexer::{tokenize, LexerOptions, TokenKind},
+    utils::parse_integer,
+};
+
+// Directives that only describe the object file
+const IGNORED: [&str; 11] = [
+    ".file",
+    ".type",
+    ".size",
+    ".ident",
+    ".loc",
+    ".weak",
+    ".hidden",
+    ".local",
+    ".code16",
+    ".code32",
+    ".att_syntax",
+];
+const SUFFIXES: [(char, &str); 3] = [('b', "BYTE"), ('w', "WORD"), ('l', "DWORD")];
+
+// A translated line, or data that takes its name from its label or the one above it
+enum Line {
+    Text(String),
+    Data {
+        name: Option<String>,
+        define: &'static str,
+        values: String,
+    },
+}
+
+pub fn translate(lines: &[String], options: LexerOptions) -> Vec<String> {
+    let mut output: Vec<String> = Vec::with_capacity(lines.len());
+    // The line of the last variable and its directive, while more data can be added to it
+    let mut open: Option<(usize, &str)> = None;
+    for line in lines {
+        let (name, define, values) = match translate_line(line, options) {
+            Line::Text(text) => {
+                if !text.trim().is_empty() {
+                    open = None;
+                }
+                output.push(text);
+                continue;
+            }
+            Line::Data {
+                name,
+                define,
+                values,
+            } => (name, define, values),
+        };
+        if let Some(name) = name {
+            output.push(format!("{name} {define}, {values}"));
+            open = Some((output.len() - 1, define));
+            continue;
+        }
+        let previous = output.iter().rposition(|line| !line.trim().is_empty());
+        let label = previous.and_then(|index| {
+            let text = output[index].trim();
+            let name = text.strip_suffix(':')?;
+            name.chars()
+                .all(|c| c.is_alphanumeric() || "_.$@?".contains(c))
+                .then(|| name.to_string())
+        });
+        match (previous, label, open) {
+            (Some(index), Some(name), _) => {
+                output[index] = String::new();
+                output.push(format!("{name} {define}, {values}"));
+                open = Some((output.len() - 1, define));
+            }
+            // `.long 1` under `.long 0` adds to the same array
+            (Some(index), None, Some((line, open_define)))
+                if index == line && open_define == define =>
+            {
+                output[index].push_str(&format!(", {values}"));
+                output.push(String::new());
+            }
+            _ => output.push(format!("{define}, {values}")),
+        }
+    }
+    output
+}
+
+fn translate_line(line: &str, options: LexerOptions) -> Line {
+    let text = line[..comment_start(line).unwrap_or(line.len())].trim();
+    // A label keeps its line, and the instruction after it is translated on its own
+    if let Some(colon) = label_end(text, options) {
+        let rest = text[colon + 1..].trim();
+        if rest.is_empty() {
+            return Line::Text(text.to_string());
+        }
+        return match translate_line(rest, options) {
+            Line::Text(rest) => Line::Text(format!("{} {rest}", &text[..=colon])),
+            Line::Data { define, values, .. } => Line::Data {
+                name: Some(text[..colon].trim().to_string()),
+                define,
+                values,
+            },
+        };
+    }
+    let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let word = word.to_lowercase();
+    let rest = rest.trim();
+    if word.starts_with('.') {
+        return directive(&word, rest);
+    }
+    if word.is_empty() {
+        return Line::Text(String::new());
+    }
+
+    let (mnemonic, size) = split_suffix(&word);
+    let jump = Mnemonic::parse(&mnemonic)
+        .is_some_and(|parsed| parsed.is_jump() || parsed == Mnemonic::Call);
+    let mut operands: Vec<String> = split_operands(rest)
+        .iter()
+        .map(|operand| translate_operand(operand, size, jump, mnemonic == "push"))
+        .collect();
+    // `enter` keeps the Intel order
+    if mnemonic != "enter" {
+        operands.reverse();
+    }
+    if operands.is_empty() {
+        Line::Text(mnemonic)
+    } else {
+        Line::Text(format!("{mnemonic} {}", operands.join(", ")))
+    }
+}
+
+fn directive(word: &str, rest: &str) -> Line {
+    let text = |text: String| Line::Text(text);
+    let data = |define, values: String| Line::Data {
+        name: None,
+        define,
+        values,
+    };
+    match word {
+        ".text" => text(".code".to_string()),
+        ".data" => text(".data".to_string()),
+        ".bss" => text(".data?".to_string()),
+        ".section" => {
+            let name = rest.split(',').next().unwrap_or_default().trim();
+            let section = if name.starts_with(".text") {
+                ".code"
+            } else if name.starts_with(".rodata") {
+                ".const"
+            } else if name.starts_with(".bss") {
+                ".data?"
+            } else {
+                ".data"
+            };
+            text(section.to_string())
+        }
+        ".globl" | ".global" => text(format!("global {rest}")),
+        ".set" | ".equ" => match rest.split_once(',') {
+            Some((name, value)) => text(format!("{} equ {}", name.trim(), value.trim())),
+            None => text(format!("{word} {rest}")),
+        },
+        ".align" | ".balign" => text(format!(
+            "align {}",
+            rest.split(',').next().unwrap_or_default().trim()
+        )),
+        ".p2align" => {
+            let power = rest
+                .split(',')
+                .next()
+                .and_then(|power| power.trim().parse::<u32>().ok());
+            match power.and_then(|power| 1_u64.checked_shl(power)) {
+                Some(alignment) => text(format!("align {alignment}")),
+                None => text(format!("{word} {rest}")),
+            }
+        }
+        ".byte" | ".ascii" => data("db", rest.to_string()),
+        ".asciz" | ".string" => data("db", format!("{rest}, 0")),
+        ".word" | ".short" | ".value" | ".hword" => data("dw", rest.to_string()),
+        ".long" | ".int" => data("dd", rest.to_string()),
+        ".quad" => data("dq", rest.to_string()),
+        ".zero" | ".skip" | ".space" => {
+            let count = rest.split(',').next().unwrap_or_default().trim();
+            data("db", format!("{count} DUP(0)"))
+        }
+        _ if IGNORED.contains(&word) || word.starts_with(".cfi_") => text(String::new()),
+        _ => text(format!("{word} {rest}")),
+    }
+}
+
+// `movl` is `mov` on dwords. Mnemonics that end in a suffix letter anyway, as `shl` and
+// `mul` do, are left alone.
+fn split_suffix(word: &str) -> (String, Option<&'static str>) {
+    if Mnemonic::parse(word).is_none() {
+        for (suffix, size) in SUFFIXES {
+            if let Some(mnemonic) = word
+                .strip_suffix(suffix)
+                .filter(|mnemonic| Mnemonic::parse(mnemonic).is_some())
+            {
+                return (mnemonic.to_string(), Some(size));
+            }
+        }
+    }
+    (word.to_string(), None)
+}
+
+fn translate_operand(operand: &str, size: Option<&str>, jump: bool, push: bool) -> String {
+    // `call *%eax` and `jmp *table(,%eax,4)` are indirect
+    let operand = operand.strip_prefix('*').unwrap_or(operand).trim();
+    if let Some(register) = operand.strip_prefix('%') {
+        return register_name(register);
+    }
+    if let Some(constant) = operand.strip_prefix('$') {
+        return match size {
+            Some(size) if push => format!("{size} {}", constant.trim()),
+            _ => constant.trim().to_string(),
+        };
+    }
+    let (displacement, inside) = match operand.find('(') {
+        Some(open) if operand.ends_with(')') => (
+            operand[..open].trim(),
+            &operand[open + 1..operand.len() - 1],
+        ),
+        _ if jump => return operand.to_string(),
+        _ => (operand, ""),
+    };
+
+    let mut fields = inside.split(',').map(str::trim);
+    let base = fields.next().unwrap_or_default();
+    let index = fields.next().unwrap_or_default();
+    let scale = fields.next().unwrap_or_default();
+    let mut terms: Vec<String> = Vec::new();
+    if let Some(base) = base.strip_prefix('%') {
+        terms.push(register_name(base));
+    }
+    if let Some(index) = index.strip_prefix('%') {
+        match scale {
+            "" | "1" => terms.push(register_name(index)),
+            scale => terms.push(format!("{}*{scale}", register_name(index))),
+        }
+    }
+    let mut address = terms.join(" + ");
+    let mut displacement = displacement.to_string();
+    // Above the saved EBP and the return address, which take a word each here instead of a dword
+    if terms.first().is_some_and(|base| base == "BP") && index.is_empty() {
+        if let Some(offset) = parse_integer(&displacement).filter(|offset| *offset >= 8) {
+            displacement = (offset - 4).to_string();
+        }
+    }
+    match displacement.strip_prefix('-') {
+        Some(magnitude) if !address.is_empty() => address.push_str(&format!("-{magnitude}")),
+        _ if displacement.is_empty() => {}
+        _ if address.is_empty() => address = displacement,
+        _ => address.push_str(&format!("+{displacement}")),
+    }
+    match size {
+        Some(size) => format!("{size} PTR [{address}]"),
+        None => format!("[{address}]"),
+    }
+}
+
+fn register_name(register: &str) -> String {
+    match register.trim().to_lowercase().as_str() {
+        "esp" | "sp" => "SP".to_string(),
+        "ebp" | "bp" => "BP".to_string(),
+        register => register.to_uppercase(),
+    }
+}
+
+// Splits on the commas outside `(...)` and quotes
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                operands.push(text[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if !text[start..].trim().is_empty() {
+        operands.push(text[start..].trim());
+    }
+    operands
+}
+
+/// Where the `#` comment starts, outside quotes.
+pub(crate) fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(open), _) if c == open && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return Some(index),
+            _ => {}
+        }
+        escaped = false;
+    }
+    None
+}
+
+// The colon of a `name:` at the start of the line
+fn label_end(text: &str, options: LexerOptions) -> Option<usize> {
+    let tokens = tokenize(text, 0, options).ok()?;
+    let [name, colon, ..] = tokens.as_slice() else {
+        return None;
+    };
+    (name.kind == TokenKind::Word && colon.kind == TokenKind::Punct(':') && colon.start == name.end)
+        .then_some(colon.start)
+}
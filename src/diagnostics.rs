@@ -0,0 +1,380 @@
+//! `assembly check`: loads a program without running it, and reports every line that would
+//! fail with its position, the line with a caret under the problem and, for misspelt
+//! labels, variables, registers and mnemonics, the name that was probably meant:
+//!
+//! ```text
+//! prog.asm:3:5: error: Invalid Value: Parameter EXA could not be parsed.
+//! mov EXA, 5
+//!     ^^^
+//! help: did you mean `EAX`?
+//! ```
+//!
+//! Errors that would stop the program from loading, such as a PROC without ENDP or a bad
+//! macro call, don't stop the check: the line is left out, or the proc runs to the end, and
+//! the rest is still checked. Labels nothing refers to are warnings.
+
+use crate::{
+    assembler::{
+        load_all, DecodedInstruction, LoadError, Mnemonic, Program, MNEMONICS, SEGMENT_REGISTERS,
+    },
+    error_code::ErrorCode,
+    lexer::{tokenize, LexerOptions, TokenKind},
+    preprocessor::PreprocessOptions,
+    register::RegisterName,
+    utils::{parse_integer, read_lines_from_file},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+// Operators and keywords that may appear in operands
+const KEYWORDS: [&str; 30] = [
+    "ptr", "byte", "word", "dword", "qword", "offset", "seg", "sizeof", "lengthof", "length",
+    "size", "type", "dup", "near", "far", "short", "char", "mod", "shl", "shr", "and", "or", "xor",
+    "not", "eq", "ne", "lt", "le", "gt", "ge",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: String,
+    // Counted from 1. Both are 0 for problems that aren't on a line.
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    // The line as written, and how many characters the caret covers
+    pub source: String,
+    pub width: usize,
+    // The name that was probably meant
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: {}: {}", self.file, self.severity, self.message);
+        }
+        writeln!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file, self.line, self.column, self.severity, self.message
+        )?;
+        writeln!(f, "{}", self.source)?;
+        // Tabs before the caret are kept, so it lines up however wide they are drawn
+        let padding: String = self
+            .source
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{padding}{}", "^".repeat(self.width.max(1)))?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\nhelp: did you mean `{suggestion}`?")?;
+        }
+        Ok(())
+    }
+}
+
+/// Every problem in `lines`, the contents of `files.main`, in source order.
+pub fn check(
+    lines: &[String],
+    options: LexerOptions,
+    files: &PreprocessOptions,
+) -> Vec<Diagnostic> {
+    let main = files
+        .main
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("<input>"));
+    let mut sources = Sources {
+        main: (main, lines),
+        included: HashMap::new(),
+    };
+    let (program, errors) = load_all(lines, options, files);
+    let mut diagnostics: Vec<Diagnostic> = errors
+        .into_iter()
+        .map(|error| sources.load_error(error))
+        .collect();
+    let names = Names::new(&program);
+    for instruction in &program.instructions {
+        let path = &program.files[instruction.file];
+        // ENDP only fails when a proc runs into it without returning
+        if let (Some(error), false) = (
+            &instruction.error,
+            instruction.mnemonic == Mnemonic::EndProc,
+        ) {
+            let (word, candidates) = names.culprit(instruction, options);
+            let suggestion = word.as_deref().and_then(|word| closest(word, &candidates));
+            diagnostics.push(sources.diagnostic(
+                Severity::Error,
+                path,
+                instruction.source_line,
+                word.as_deref(),
+                error.to_string(),
+                suggestion,
+            ));
+        } else if instruction.mnemonic == Mnemonic::Label {
+            let name = instruction.name.as_deref().unwrap_or_default();
+            if !names.used.contains(name) && program.entry != Some(instruction.line) {
+                diagnostics.push(sources.diagnostic(
+                    Severity::Warning,
+                    path,
+                    instruction.source_line,
+                    Some(name),
+                    format!("Label {name} is never used"),
+                    None,
+                ));
+            }
+        }
+    }
+    // Load errors come first, so they are merged back in by file and line
+    let main = sources.main.0.display().to_string();
+    diagnostics.sort_by_key(|diagnostic| {
+        (
+            diagnostic.file != main,
+            diagnostic.file.clone(),
+            diagnostic.line,
+        )
+    });
+    diagnostics
+}
+
+// The text of the files a program was loaded from, read once each
+struct Sources<'a> {
+    main: (PathBuf, &'a [String]),
+    included: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Sources<'_> {
+    fn line(&mut self, path: &Path, line: usize) -> String {
+        let lines = if path == self.main.0 || path.as_os_str().is_empty() {
+            self.main.1
+        } else {
+            self.included.entry(path.to_path_buf()).or_insert_with(|| {
+                read_lines_from_file(&path.to_string_lossy()).unwrap_or_default()
+            })
+        };
+        lines.get(line).cloned().unwrap_or_default()
+    }
+
+    fn load_error(&mut self, error: LoadError) -> Diagnostic {
+        match (error.error, error.location) {
+            (
+                ErrorCode::SyntaxError {
+                    file,
+                    line,
+                    column,
+                    message,
+                },
+                _,
+            ) => {
+                let path = file.map_or_else(|| self.main.0.clone(), PathBuf::from);
+                let source = self.line(&path, line - 1);
+                Diagnostic {
+                    severity: Severity::Error,
+                    file: path.display().to_string(),
+                    line,
+                    column,
+                    message,
+                    source,
+                    width: 1,
+                    suggestion: None,
+                }
+            }
+            (error, Some((path, line))) => {
+                self.diagnostic(Severity::Error, &path, line, None, error.to_string(), None)
+            }
+            (error, None) => Diagnostic {
+                severity: Severity::Error,
+                file: self.main.0.display().to_string(),
+                line: 0,
+                column: 0,
+                message: error.to_string(),
+                source: String::new(),
+                width: 0,
+                suggestion: None,
+            },
+        }
+    }
+
+    // A diagnostic for `line` of `path`, counted from 0. The caret goes under `word` when the
+    // line has it, and under the whole statement otherwise.
+    fn diagnostic(
+        &mut self,
+        severity: Severity,
+        path: &Path,
+        line: usize,
+        word: Option<&str>,
+        message: String,
+        suggestion: Option<String>,
+    ) -> Diagnostic {
+        let source = self.line(path, line);
+        let (column, width) = match word.and_then(|word| find_word(&source, word)) {
+            Some(start) => (start, word.unwrap_or_default().chars().count()),
+            None => {
+                let code = source.split(';').next().unwrap_or_default();
+                let start = code.len() - code.trim_start().len();
+                (start, code.trim().chars().count())
+            }
+        };
+        Diagnostic {
+            severity,
+            file: path.display().to_string(),
+            line: line + 1,
+            column: source[..column].chars().count() + 1,
+            message,
+            source,
+            width,
+            suggestion,
+        }
+    }
+}
+
+// What a program defines, and the names its statements use
+struct Names<'a> {
+    program: &'a Program,
+    used: HashSet<String>,
+}
+
+impl<'a> Names<'a> {
+    fn new(program: &'a Program) -> Self {
+        let mut used = HashSet::new();
+        for instruction in &program.instructions {
+            if instruction.mnemonic == Mnemonic::Label {
+                continue;
+            }
+            for part in &instruction.text {
+                let words = part.split(|c: char| !(c.is_alphanumeric() || "_@$?.".contains(c)));
+                used.extend(words.map(String::from));
+            }
+        }
+        Names { program, used }
+    }
+
+    fn is_known(&self, word: &str) -> bool {
+        let program = self.program;
+        let lowercase = word.to_lowercase();
+        parse_integer(word).is_some()
+            || RegisterName::is_valid_name(word)
+            || SEGMENT_REGISTERS.contains(&lowercase.as_str())
+            || KEYWORDS.contains(&lowercase.as_str())
+            || ["$", "?"].contains(&word)
+            // Fields, as in `point.x` and `[BX].next`
+            || word.contains('.')
+            || program.labels.contains_key(word)
+            || program.procs.contains_key(word)
+            || program.variables.contains_key(word)
+            || program.constants.contains_key(word)
+            || program.structs.contains_key(word)
+            || program.segments.contains(word)
+    }
+
+    // The word a failing statement most likely gets wrong, and what it could have meant
+    fn culprit(
+        &self,
+        instruction: &DecodedInstruction,
+        options: LexerOptions,
+    ) -> (Option<String>, Vec<String>) {
+        let program = self.program;
+        let Some(first) = instruction.text.first() else {
+            return (None, Vec::new());
+        };
+        if instruction.mnemonic == Mnemonic::Unknown
+            && !first.ends_with(':')
+            && Mnemonic::parse(first).is_none()
+        {
+            let mnemonics = MNEMONICS.iter().map(|(name, _)| name.to_string()).collect();
+            return (Some(first.clone()), mnemonics);
+        }
+        let mut candidates: Vec<String> = Vec::new();
+        // Jumps go to labels and procs, anything else more likely names a register or variable
+        if !(instruction.mnemonic.is_jump() || instruction.mnemonic == Mnemonic::Call) {
+            candidates.extend(RegisterName::NAMES.iter().map(|name| name.to_string()));
+            candidates.extend(program.variables.keys().cloned());
+            candidates.extend(program.constants.keys().cloned());
+        }
+        candidates.extend(program.labels.keys().cloned());
+        candidates.extend(program.procs.keys().cloned());
+        candidates.sort();
+
+        let unknown = instruction.text[1..].iter().find_map(|operand| {
+            let tokens = tokenize(operand, 0, options).ok()?;
+            tokens
+                .iter()
+                .filter(|token| token.kind == TokenKind::Word)
+                .map(|token| &operand[token.start..token.end])
+                .find(|word| !self.is_known(word))
+                .map(String::from)
+        });
+        (unknown, candidates)
+    }
+}
+
+// The closest name to `word`, ignoring case, when it is close enough to be a typo: one edit
+// for every 3 characters, so names of 1 or 2 can only differ in case
+fn closest(word: &str, candidates: &[String]) -> Option<String> {
+    let lowercase = word.to_lowercase();
+    let limit = word.chars().count() / 3;
+    candidates
+        .iter()
+        .filter(|candidate| candidate.as_str() != word)
+        .map(|candidate| (distance(&lowercase, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.clone())
+}
+
+// Edits from `a` to `b`: inserting, removing or replacing a character, or swapping two
+// neighbours, so `EXA` is one edit from `EAX`
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in table.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in table[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (table[i - 1][j] + 1)
+                .min(table[i][j - 1] + 1)
+                .min(table[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(table[i - 2][j - 2] + 1);
+            }
+            table[i][j] = best;
+        }
+    }
+    table[a.len()][b.len()]
+}
+
+// The byte offset of `word` in `line`, as a whole name
+fn find_word(line: &str, word: &str) -> Option<usize> {
+    let is_name = |c: char| c.is_alphanumeric() || "_@$?.".contains(c);
+    line.match_indices(word)
+        .map(|(start, _)| start)
+        .find(|start| {
+            let before = line[..*start].chars().next_back();
+            let after = line[start + word.len()..].chars().next();
+            !before.is_some_and(is_name) && !after.is_some_and(is_name)
+        })
+}
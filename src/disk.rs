@@ -0,0 +1,189 @@
+//! A 1.44 MB floppy image served through the BIOS disk services (`int 13h`).
+//! Transfers use CHS addressing and the buffer at BX, which stands in for ES:BX.
+
+use crate::{
+    error_code::ErrorCode,
+    flag::Flag,
+    memory_manager::{get_register_value, MemoryManager},
+    register::Register,
+    status::Status,
+    RegisterName,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+pub const DISK_VECTOR: u8 = 0x13;
+
+pub const SECTOR_SIZE: usize = 512;
+pub const CYLINDERS: usize = 80;
+pub const HEADS: usize = 2;
+pub const SECTORS_PER_TRACK: usize = 18;
+pub const IMAGE_SIZE: usize = CYLINDERS * HEADS * SECTORS_PER_TRACK * SECTOR_SIZE;
+
+/// Boot sectors are loaded at 0000:7C00 and must end with 55h AAh.
+pub const BOOT_ADDRESS: usize = 0x7C00;
+pub const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+// BIOS disk status codes, returned in AH
+const STATUS_OK: u8 = 0x00;
+const STATUS_INVALID_COMMAND: u8 = 0x01;
+const STATUS_WRITE_PROTECTED: u8 = 0x03;
+const STATUS_SECTOR_NOT_FOUND: u8 = 0x04;
+const STATUS_NO_DRIVE: u8 = 0x80;
+
+pub struct FloppyDisk {
+    file: File,
+    read_only: bool,
+    last_status: u8,
+}
+
+impl FloppyDisk {
+    /// Opens an image file. Images shorter than 1.44 MB read as zero-padded.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let (file, read_only) = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => (file, false),
+            Err(_) => (File::open(path)?, true),
+        };
+        if file.metadata()?.len() as usize > IMAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path} is larger than a 1.44 MB floppy image"),
+            ));
+        }
+        Ok(Self {
+            file,
+            read_only,
+            last_status: STATUS_OK,
+        })
+    }
+
+    pub fn read_sector(&mut self, lba: usize) -> io::Result<[u8; SECTOR_SIZE]> {
+        let mut sector = [0; SECTOR_SIZE];
+        self.file
+            .seek(SeekFrom::Start((lba * SECTOR_SIZE) as u64))?;
+        let mut filled = 0;
+        while filled < SECTOR_SIZE {
+            match self.file.read(&mut sector[filled..])? {
+                0 => break,
+                count => filled += count,
+            }
+        }
+        Ok(sector)
+    }
+
+    pub fn write_sector(&mut self, lba: usize, sector: &[u8; SECTOR_SIZE]) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start((lba * SECTOR_SIZE) as u64))?;
+        self.file.write_all(sector)?;
+        self.file.flush()
+    }
+}
+
+/// Sectors are numbered from 1; cylinders and heads from 0.
+pub fn chs_to_lba(cylinder: usize, head: usize, sector: usize) -> Option<usize> {
+    if cylinder >= CYLINDERS || head >= HEADS || sector == 0 || sector > SECTORS_PER_TRACK {
+        return None;
+    }
+    Some((cylinder * HEADS + head) * SECTORS_PER_TRACK + sector - 1)
+}
+
+pub fn disk_service(
+    memory: &mut MemoryManager,
+    registers: &mut [Register; 10],
+    disk: Option<&mut FloppyDisk>,
+) -> Result<Status, ErrorCode> {
+    let ah = get_register_value(registers, &RegisterName::AH);
+    let al = get_register_value(registers, &RegisterName::AL) as usize;
+    let bx = get_register_value(registers, &RegisterName::BX) as usize;
+    let ch = get_register_value(registers, &RegisterName::CH) as usize;
+    let cl = get_register_value(registers, &RegisterName::CL) as usize;
+    let dh = get_register_value(registers, &RegisterName::DH) as usize;
+    let dl = get_register_value(registers, &RegisterName::DL);
+
+    // Only drive A: (DL = 0) exists
+    let Some(disk) = disk.filter(|_| dl == 0) else {
+        finish(registers, STATUS_NO_DRIVE, 0);
+        return Ok(Status::Ok);
+    };
+
+    // CL bits 6-7 are bits 8-9 of the cylinder number
+    let cylinder = ch | ((cl & 0xC0) << 2);
+    let first_sector = cl & 0x3F;
+
+    let (status, transferred) = match ah {
+        // Reset the disk system
+        0x00 => (STATUS_OK, 0),
+        // Status of the last operation
+        0x01 => (disk.last_status, 0),
+        // Read AL sectors into BX / write AL sectors from BX
+        0x02 | 0x03 => {
+            let write = ah == 0x03;
+            let mut transferred = 0;
+            let mut status = STATUS_OK;
+            if write && disk.read_only {
+                status = STATUS_WRITE_PROTECTED;
+            }
+            while status == STATUS_OK && transferred < al {
+                let Some(lba) = chs_to_lba(cylinder, dh, first_sector + transferred) else {
+                    status = STATUS_SECTOR_NOT_FOUND;
+                    break;
+                };
+                let address = bx + transferred * SECTOR_SIZE;
+                memory.check_memory_address(address + SECTOR_SIZE - 1)?;
+                if write {
+                    let mut sector = [0; SECTOR_SIZE];
+                    for (i, byte) in sector.iter_mut().enumerate() {
+                        *byte = memory.get_byte(address + i)?;
+                    }
+                    if disk.write_sector(lba, &sector).is_err() {
+                        status = STATUS_WRITE_PROTECTED;
+                        break;
+                    }
+                } else {
+                    let Ok(sector) = disk.read_sector(lba) else {
+                        status = STATUS_SECTOR_NOT_FOUND;
+                        break;
+                    };
+                    for (i, byte) in sector.iter().enumerate() {
+                        memory.set_byte(address + i, *byte)?;
+                    }
+                }
+                transferred += 1;
+            }
+            (status, transferred)
+        }
+        // Drive parameters: BL type, CH/CL last cylinder and sectors per track, DH last head
+        0x08 => {
+            let last_cylinder = CYLINDERS - 1;
+            registers[RegisterName::EBX.to_index()].load_byte(0x04, false);
+            registers[RegisterName::ECX.to_index()].load_byte(last_cylinder as u8, true);
+            registers[RegisterName::ECX.to_index()].load_byte(
+                (SECTORS_PER_TRACK | ((last_cylinder >> 2) & 0xC0)) as u8,
+                false,
+            );
+            registers[RegisterName::EDX.to_index()].load_byte((HEADS - 1) as u8, true);
+            registers[RegisterName::EDX.to_index()].load_byte(1, false);
+            (STATUS_OK, 0)
+        }
+        _ => (STATUS_INVALID_COMMAND, 0),
+    };
+    disk.last_status = status;
+    finish(registers, status, transferred as u8);
+    Ok(Status::Ok)
+}
+
+// AH = status, AL = sectors transferred, CF set on error
+fn finish(registers: &mut [Register; 10], status: u8, transferred: u8) {
+    let ax = &mut registers[RegisterName::AX.to_index()];
+    ax.load_byte(status, true);
+    ax.load_byte(transferred, false);
+    let flags = &mut registers[RegisterName::FLAG.to_index()];
+    let value = flags.get_word();
+    flags.load_word(if status == STATUS_OK {
+        value & !Flag::Carry.value()
+    } else {
+        value | Flag::Carry.value()
+    });
+}
@@ -0,0 +1,298 @@
+//! DOS services through `int 21h`, including handle-based file I/O sandboxed to a host directory.
+//! File functions report failure with CF set and a DOS error code in AX.
+
+use crate::{
+    error_code::ErrorCode,
+    flag::Flag,
+    host_io::HostIo,
+    memory_manager::{get_register_value, MemoryManager},
+    register::Register,
+    status::Status,
+    RegisterName,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+pub const DOS_VECTOR: u8 = 0x21;
+
+// DOS error codes
+const ERROR_INVALID_FUNCTION: u16 = 0x01;
+const ERROR_FILE_NOT_FOUND: u16 = 0x02;
+const ERROR_PATH_NOT_FOUND: u16 = 0x03;
+const ERROR_TOO_MANY_OPEN_FILES: u16 = 0x04;
+const ERROR_ACCESS_DENIED: u16 = 0x05;
+const ERROR_INVALID_HANDLE: u16 = 0x06;
+const ERROR_INVALID_ACCESS: u16 = 0x0C;
+
+// Handles 0-4 are the predefined stdin, stdout, stderr, aux and prn devices.
+const FIRST_FILE_HANDLE: u16 = 5;
+const MAX_OPEN_FILES: usize = 20;
+const MAX_PATH_LENGTH: usize = 128;
+
+/// Open files, and the host directory that DOS paths are resolved against.
+#[derive(Default)]
+pub struct DosFiles {
+    root: Option<PathBuf>,
+    handles: HashMap<u16, File>,
+}
+
+impl DosFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Roots the file services at `root`. Without a root every file function is denied.
+    pub fn set_root(&mut self, root: &Path) -> std::io::Result<()> {
+        self.root = Some(root.canonicalize()?);
+        Ok(())
+    }
+
+    /// Maps a DOS path onto the host, refusing anything that would leave the root.
+    /// `\` and `/` both separate components, and a leading separator means the root itself.
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, u16> {
+        let root = self.root.as_ref().ok_or(ERROR_ACCESS_DENIED)?;
+        if name.is_empty() || name.len() > MAX_PATH_LENGTH || name.contains(':') {
+            return Err(ERROR_PATH_NOT_FOUND);
+        }
+
+        let mut relative = PathBuf::new();
+        for component in Path::new(&name.replace('\\', "/")).components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir | Component::RootDir => {}
+                Component::ParentDir => {
+                    if !relative.pop() {
+                        return Err(ERROR_ACCESS_DENIED);
+                    }
+                }
+                Component::Prefix(_) => return Err(ERROR_ACCESS_DENIED),
+            }
+        }
+        let path = root.join(&relative);
+
+        // Follow symlinks before trusting the path: the file itself if it exists,
+        // otherwise the directory it would be created in. Links to nowhere are refused.
+        let resolved = if path.exists() {
+            path.canonicalize().map_err(|_| ERROR_ACCESS_DENIED)?
+        } else if path.symlink_metadata().is_ok() {
+            // A dangling link, which creating the file would follow out of the root
+            return Err(ERROR_ACCESS_DENIED);
+        } else {
+            let parent = path.parent().ok_or(ERROR_PATH_NOT_FOUND)?;
+            let file_name = path.file_name().ok_or(ERROR_PATH_NOT_FOUND)?;
+            parent
+                .canonicalize()
+                .map_err(|_| ERROR_PATH_NOT_FOUND)?
+                .join(file_name)
+        };
+        if !resolved.starts_with(root) || resolved == *root {
+            return Err(ERROR_ACCESS_DENIED);
+        }
+        Ok(resolved)
+    }
+
+    fn insert(&mut self, file: File) -> Result<u16, u16> {
+        if self.handles.len() >= MAX_OPEN_FILES {
+            return Err(ERROR_TOO_MANY_OPEN_FILES);
+        }
+        let handle = (FIRST_FILE_HANDLE..)
+            .find(|handle| !self.handles.contains_key(handle))
+            .ok_or(ERROR_TOO_MANY_OPEN_FILES)?;
+        self.handles.insert(handle, file);
+        Ok(handle)
+    }
+}
+
+pub fn dos_service(
+    memory: &mut MemoryManager,
+    registers: &mut [Register; 10],
+    host: &mut HostIo,
+    files: &mut DosFiles,
+    exit_code: &mut Option<u32>,
+) -> Result<Status, ErrorCode> {
+    let ah = get_register_value(registers, &RegisterName::AH);
+    let al = get_register_value(registers, &RegisterName::AL);
+    let bx = get_register_value(registers, &RegisterName::BX) as u16;
+    let cx = get_register_value(registers, &RegisterName::CX);
+    let dx = get_register_value(registers, &RegisterName::DX);
+
+    let result: Result<u16, u16> = match ah {
+        // Display the character in DL
+        0x02 => {
+            let _ = host.stdout.write_all(&[dx as u8]);
+            let _ = host.stdout.flush();
+            return Ok(Status::Ok);
+        }
+        // Display the '$'-terminated string at DX, or up to the end of memory without one
+        0x09 => {
+            let mut address = dx as usize;
+            let mut bytes = Vec::new();
+            while let Ok(byte) = memory.get_byte(address) {
+                if byte == b'$' {
+                    break;
+                }
+                bytes.push(byte);
+                address += 1;
+            }
+            let _ = host.stdout.write_all(&bytes);
+            let _ = host.stdout.flush();
+            return Ok(Status::Ok);
+        }
+        // Terminate with return code AL
+        0x4C => {
+            *exit_code = Some(al);
+            let _ = host.stdout.flush();
+            return Ok(Status::Halted);
+        }
+        // Create or truncate a file
+        0x3C => read_asciz(memory, dx as usize).and_then(|name| {
+            let path = files.resolve(&name)?;
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .map_err(|_| ERROR_ACCESS_DENIED)?;
+            files.insert(file)
+        }),
+        // Open an existing file, AL = 0 read, 1 write, 2 read/write
+        0x3D => read_asciz(memory, dx as usize).and_then(|name| {
+            let path = files.resolve(&name)?;
+            let mut options = OpenOptions::new();
+            match al & 0b111 {
+                0 => options.read(true),
+                1 => options.write(true),
+                2 => options.read(true).write(true),
+                _ => return Err(ERROR_INVALID_ACCESS),
+            };
+            if !path.is_file() {
+                return Err(ERROR_FILE_NOT_FOUND);
+            }
+            let file = options.open(path).map_err(|_| ERROR_ACCESS_DENIED)?;
+            files.insert(file)
+        }),
+        // Close handle BX
+        0x3E => files
+            .handles
+            .remove(&bx)
+            .map(|_| 0)
+            .ok_or(ERROR_INVALID_HANDLE),
+        // Read CX bytes from handle BX into DX
+        0x3F if memory.check_memory_range(dx as usize, cx as usize).is_err() => {
+            Err(ERROR_ACCESS_DENIED)
+        }
+        0x3F => {
+            let mut buffer = vec![0; cx as usize];
+            let count = match bx {
+                0 => host
+                    .stdin
+                    .read(&mut buffer)
+                    .map_err(|_| ERROR_ACCESS_DENIED),
+                _ => match files.handles.get_mut(&bx) {
+                    Some(file) => file.read(&mut buffer).map_err(|_| ERROR_ACCESS_DENIED),
+                    None => Err(ERROR_INVALID_HANDLE),
+                },
+            };
+            count.and_then(|count| {
+                for (i, byte) in buffer[..count].iter().enumerate() {
+                    memory
+                        .set_byte(dx as usize + i, *byte)
+                        .map_err(|_| ERROR_ACCESS_DENIED)?;
+                }
+                Ok(count as u16)
+            })
+        }
+        // Write CX bytes from DX to handle BX
+        0x40 if memory.check_memory_range(dx as usize, cx as usize).is_err() => {
+            Err(ERROR_ACCESS_DENIED)
+        }
+        0x40 => {
+            let bytes = (0..cx as usize)
+                .map(|i| memory.get_byte(dx as usize + i))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| ERROR_ACCESS_DENIED);
+            let stream: Option<&mut dyn Write> = match bx {
+                1 => Some(&mut host.stdout),
+                2 => Some(&mut host.stderr),
+                _ => files
+                    .handles
+                    .get_mut(&bx)
+                    .map(|file| file as &mut dyn Write),
+            };
+            match (stream, bytes) {
+                (_, Err(code)) => Err(code),
+                (Some(stream), Ok(bytes)) => stream
+                    .write_all(&bytes)
+                    .and_then(|_| stream.flush())
+                    .map(|_| cx as u16)
+                    .map_err(|_| ERROR_ACCESS_DENIED),
+                (None, _) => Err(ERROR_INVALID_HANDLE),
+            }
+        }
+        // Delete the file named at DX
+        0x41 => read_asciz(memory, dx as usize).and_then(|name| {
+            let path = files.resolve(&name)?;
+            if !path.is_file() {
+                return Err(ERROR_FILE_NOT_FOUND);
+            }
+            fs::remove_file(path)
+                .map(|_| 0)
+                .map_err(|_| ERROR_ACCESS_DENIED)
+        }),
+        // Move the file pointer of BX by CX:DX from AL = 0 start, 1 current, 2 end.
+        // The new position is returned in DX:AX.
+        0x42 => {
+            let offset = ((cx << 16) | dx) as i32 as i64;
+            let origin = match al {
+                0 => Some(SeekFrom::Start(offset.max(0) as u64)),
+                1 => Some(SeekFrom::Current(offset)),
+                2 => Some(SeekFrom::End(offset)),
+                _ => None,
+            };
+            match (files.handles.get_mut(&bx), origin) {
+                (None, _) => Err(ERROR_INVALID_HANDLE),
+                (Some(_), None) => Err(ERROR_INVALID_FUNCTION),
+                (Some(file), Some(origin)) => match file.seek(origin) {
+                    Ok(position) => {
+                        registers[RegisterName::DX.to_index()].load_word((position >> 16) as u16);
+                        Ok(position as u16)
+                    }
+                    Err(_) => Err(ERROR_ACCESS_DENIED),
+                },
+            }
+        }
+        _ => Err(ERROR_INVALID_FUNCTION),
+    };
+
+    let flags_index = RegisterName::FLAG.to_index();
+    let flags = registers[flags_index].get_word();
+    let (ax, carry) = match result {
+        Ok(value) => (value, false),
+        Err(code) => (code, true),
+    };
+    registers[RegisterName::AX.to_index()].load_word(ax);
+    registers[flags_index].load_word(if carry {
+        flags | Flag::Carry.value()
+    } else {
+        flags & !Flag::Carry.value()
+    });
+    Ok(Status::Ok)
+}
+
+// Reads a NUL-terminated file name, stopping at the DOS path limit.
+fn read_asciz(memory: &MemoryManager, address: usize) -> Result<String, u16> {
+    let mut name = String::new();
+    for i in 0..=MAX_PATH_LENGTH {
+        match memory.get_byte(address + i) {
+            Ok(0) => return Ok(name),
+            Ok(byte) => name.push(byte as char),
+            Err(_) => return Err(ERROR_PATH_NOT_FOUND),
+        }
+    }
+    Err(ERROR_PATH_NOT_FOUND)
+}
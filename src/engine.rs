@@ -1,21 +1,38 @@
 use crate::{
+    assembler::{assemble_file, MemoryOperand, Mnemonic, Operand, Program},
+    diagnostics::{self, Diagnostic},
+    disk::{disk_service, FloppyDisk, BOOT_ADDRESS, BOOT_SIGNATURE, DISK_VECTOR, SECTOR_SIZE},
+    dos::{dos_service, DosFiles, DOS_VECTOR},
     error_code::ErrorCode,
     flag::Flag,
+    host_io::HostIo,
     instruction::Instruction,
+    interrupt::{
+        exception_vector, InterruptController, Timer, BREAKPOINT_VECTOR, OVERFLOW_VECTOR,
+        TIMER_VECTOR,
+    },
+    lexer::{Dialect, LexerOptions},
     line_processor::LineProcessor,
     memory_manager::MemoryManager,
+    preprocessor::PreprocessOptions,
     register::{get_register_size, Register, RegisterName},
-    // status::Status,
-    utils::{parse_string_to_usize, read_lines_from_file},
+    serial::{Serial, SERIAL_VECTOR},
+    speaker::{Speaker, INSTRUCTIONS_PER_SECOND},
+    status::Status,
+    syscall::{linux_syscall, LINUX_SYSCALL_VECTOR},
+    utils::read_lines_from_file,
     variable_metadata::{
         // VariableMetadata,
         VariableSize,
     },
+    vga::{video_service, Vga, VIDEO_VECTOR},
 };
 use std::fmt;
 use std::io::{self, stdin, Write};
+use std::rc::Rc;
 
 const MEMORY_SIZE: usize = 1024 * 16; // 16 KB
+const BOOT_MEMORY_SIZE: usize = 1024 * 64; // One real-mode segment, room for 0000:7C00
 
 fn skip_lines(lines_to_skip: usize) -> io::Result<()> {
     let mut stdout = io::stdout();
@@ -67,46 +84,44 @@ fn back_to_str(vec: &[String]) -> String {
     ret[..ret.len() - f].to_string()
 }
 
-fn combine_parts(vec: &Vec<String>) -> Vec<String> {
-    let mut result: Vec<String> = Vec::new();
-    let mut combined: String = String::new();
-    let mut in_quotes: bool = false;
-
-    for item in vec {
-        if item.starts_with('\'') && item.ends_with('\'') {
-            result.push(format!("\'{}\'", item.trim_matches('\'')));
-        } else if item.starts_with('\'') {
-            combined.push_str(&format!("\'{}", item.trim_matches('\'')));
-            in_quotes = true;
-        } else if item.ends_with('\'') {
-            combined.push_str(", ");
-            combined.push_str(&format!("{}\'", item.trim_matches('\'')));
-            result.push(combined.clone());
-            combined.clear();
-            in_quotes = false;
-        } else if in_quotes {
-            combined.push_str(", ");
-            combined.push_str(item);
-        } else {
-            result.push(item.to_string());
-        }
-    }
-
-    // If there's a trailing combined string without a closing quote
-    if !combined.is_empty() {
-        result.push(combined);
-    }
-
-    result
+// The error reported when a VGA dump or speaker recording can't be written.
+fn dump_error(path: &std::path::Path, error: io::Error) -> ErrorCode {
+    ErrorCode::InvalidValue(format!("Could not write {}: {error}", path.display()))
 }
+
 #[allow(unused_assignments)]
 pub struct Engine {
-    pub lines: LineProcessor,      // lines of source code (.txt)
+    pub lines: LineProcessor, // lines of source code (.txt)
+    program: Rc<Program>,     // the lines, decoded by `execute`
+    lexer_options: LexerOptions,
+    // The file `lines` came from and the -I directories its INCLUDEs search
+    preprocess_options: PreprocessOptions,
     pub registers: [Register; 10], // A-D, ESI, EDI, P
     memory_manager: MemoryManager, // 16 KB bytes of memory
-                                   // mode: bool, // false = reading data, true = reading code
-                                   // status: Status, // status: ok, error, halted,
-                                   // interrupts: Vec<Interrupt>
+    interrupts: InterruptController,
+    // CPU faults vector through the IVT instead of ending the run
+    exception_mode: bool,
+    // CS, as pushed by INT and restored by IRET
+    code_segment: u16,
+    // Instructions executed so far
+    pub instruction_count: usize,
+    // Streams used by the emulated OS services
+    host_io: HostIo,
+    // Open files of the int 21h file services
+    dos_files: DosFiles,
+    // Floppy image behind int 13h
+    disk: Option<FloppyDisk>,
+    // Mode 13h display state behind int 10h and the VGA ports
+    vga: Vga,
+    // PIT channel 2 and the speaker gate
+    speaker: Speaker,
+    // COM1, when bridged
+    serial: Option<Serial>,
+    // Set when the program ends through an exit service
+    pub exit_code: Option<u32>,
+    // Debug view state: rows printed below the dump, and the last executed line
+    lines_to_skip: usize,
+    previous_line: Option<(usize, String)>,
 }
 
 impl Engine {
@@ -128,12 +143,168 @@ impl Engine {
         let ds = 0_usize; // DATA SEGMENT starts at 0
         let cs = 1024 * 3_usize; // CODE SEGMENT starts at 3072
         let ss: usize = MEMORY_SIZE - 1024; // STACK SEGMENT, starts at 15360 (1024*15)
+        let memory_manager = MemoryManager::new(MEMORY_SIZE, [ds, cs, ss]);
         Ok(Self {
             lines: LineProcessor::new(file_lines),
+            program: Rc::new(Program::default()),
+            lexer_options: LexerOptions {
+                dialect: Dialect::from_path(file_name),
+                ..LexerOptions::default()
+            },
+            preprocess_options: PreprocessOptions {
+                main: Some(file_name.into()),
+                ..PreprocessOptions::default()
+            },
             registers: my_registers,
-            memory_manager: MemoryManager::new(MEMORY_SIZE, [ds, cs, ss]),
+            code_segment: memory_manager.get_code_segment(),
+            memory_manager,
+            interrupts: InterruptController::new(),
+            exception_mode: false,
+            instruction_count: 0,
+            host_io: HostIo::default(),
+            dos_files: DosFiles::new(),
+            disk: None,
+            vga: Vga::new(),
+            speaker: Speaker::new(),
+            serial: None,
+            exit_code: None,
+            lines_to_skip: 1,
+            previous_line: None,
         })
     }
+
+    // In exception mode #DE, #UD, INT 3 and INTO run the program's IVT handler when one is installed.
+    pub fn set_exception_mode(&mut self, enabled: bool) {
+        self.exception_mode = enabled;
+    }
+
+    // Resolve C-style escapes (\n, \t, \xHH, ...) in string literals.
+    pub fn set_escapes(&mut self, enabled: bool) {
+        self.lexer_options.escapes = enabled;
+    }
+
+    // The syntax the program is written in, when its extension doesn't say
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.lexer_options.dialect = dialect;
+    }
+
+    // Search `directory` for INCLUDE files not found next to the including file.
+    pub fn add_include_path(&mut self, directory: &str) {
+        self.preprocess_options.search_paths.push(directory.into());
+    }
+
+    // Define `name` as `value` before the first line, as `name EQU value` would.
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.preprocess_options
+            .defines
+            .push((name.to_string(), value.to_string()));
+    }
+
+    // `file:line` of the instruction executing now, or last executed
+    pub fn source_location(&self) -> Option<String> {
+        let instruction = self.program.at(self.lines.get_current_ip())?;
+        let file = self.program.files.get(instruction.file)?;
+        Some(format!(
+            "{}:{}",
+            file.display(),
+            instruction.source_line + 1
+        ))
+    }
+
+    // Line in its file, counted from 1, of the instruction executing now, or last executed
+    pub fn source_line(&self) -> Option<usize> {
+        let instruction = self.program.at(self.lines.get_current_ip())?;
+        Some(instruction.source_line + 1)
+    }
+
+    // Every error and warning in the program, without running it
+    pub fn check(&self) -> Vec<Diagnostic> {
+        diagnostics::check(
+            self.lines.lines(),
+            self.lexer_options,
+            &self.preprocess_options,
+        )
+    }
+
+    // Redirect the stdin/stdout/stderr seen by the emulated OS services.
+    pub fn set_host_io(&mut self, host_io: HostIo) {
+        self.host_io = host_io;
+    }
+
+    // Root the int 21h file services at `root`. Paths that escape it are refused.
+    pub fn set_file_root(&mut self, root: &str) -> io::Result<()> {
+        self.dos_files.set_root(std::path::Path::new(root))
+    }
+
+    // Serve int 13h from a 1.44 MB floppy image.
+    pub fn attach_disk(&mut self, image_path: &str) -> io::Result<()> {
+        self.disk = Some(FloppyDisk::open(image_path)?);
+        Ok(())
+    }
+
+    // Load sector 0 of the attached disk to 0000:7C00 with DL = 0, as the BIOS does before
+    // jumping there. Nothing jumps here: the simulator executes source, not machine code, so the
+    // sector is data and the source file still runs.
+    pub fn load_boot_sector(&mut self) -> Result<(), ErrorCode> {
+        let Some(disk) = self.disk.as_mut() else {
+            return Err(ErrorCode::InvalidValue(
+                "No disk image is attached.".to_string(),
+            ));
+        };
+        let sector = disk.read_sector(0).map_err(|error| {
+            ErrorCode::InvalidValue(format!("Could not read sector 0: {error}"))
+        })?;
+        if sector[SECTOR_SIZE - 2..] != BOOT_SIGNATURE {
+            return Err(ErrorCode::InvalidValue(
+                "Sector 0 is missing the 55h AAh boot signature.".to_string(),
+            ));
+        }
+        self.memory_manager.grow(BOOT_MEMORY_SIZE);
+        for (i, byte) in sector.iter().enumerate() {
+            self.memory_manager.set_byte(BOOT_ADDRESS + i, *byte)?;
+        }
+        self.registers[RegisterName::DX.to_index()].load_byte(0, false);
+        Ok(())
+    }
+
+    // Write the mode 13h screen to `path` at exit, and every `every` frames when set.
+    // A frame lasts vga::INSTRUCTIONS_PER_FRAME instructions.
+    pub fn set_vga_output(&mut self, path: &str, every: Option<usize>) {
+        self.vga.set_output(std::path::Path::new(path), every);
+    }
+
+    // Write the mode 13h screen to `path` now, as PNG for a .png extension and PPM otherwise.
+    pub fn dump_framebuffer(&self, path: &str) -> io::Result<()> {
+        let Some(framebuffer) = self.memory_manager.framebuffer() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "There is no framebuffer: the program has not set mode 13h",
+            ));
+        };
+        self.vga
+            .write_image(std::path::Path::new(path), framebuffer)
+    }
+
+    // Render the PC speaker to a WAV file at exit.
+    pub fn set_speaker_output(&mut self, path: &str) {
+        self.speaker.set_output(std::path::Path::new(path));
+    }
+
+    // Connect COM1 (3F8h, IRQ4 on vector 0Ch) to a bridge, see Serial::open.
+    pub fn attach_serial(&mut self, serial: Serial) {
+        self.serial = Some(serial);
+    }
+
+    // Virtual time: the instruction clock at speaker::INSTRUCTIONS_PER_SECOND.
+    pub fn virtual_time(&self) -> std::time::Duration {
+        let micros = self.instruction_count as u128 * 1_000_000 / INSTRUCTIONS_PER_SECOND as u128;
+        std::time::Duration::from_micros(micros as u64)
+    }
+
+    // Raise IRQ0 every `period` executed instructions. Delivered while IF is set.
+    pub fn set_timer(&mut self, period: usize) {
+        self.interrupts.timer = Some(Timer::new(period));
+    }
     // Only used for tests
     pub fn get_memory(&self, amount: usize) -> Vec<u8> {
         self.memory_manager._get_memory(0, amount)
@@ -156,9 +327,10 @@ impl Engine {
     }
 
     fn set_flags(&mut self, result: usize, size: VariableSize, overflowed: bool) {
-        let _ = self.set_register_value(&RegisterName::FLAG, 0);
+        let control = self.get_register_value(&RegisterName::FLAG) as u16 & Flag::control_mask();
+        let _ = self.set_register_value(&RegisterName::FLAG, control as u32);
 
-        if result.count_ones() % 2 == 0 {
+        if result.count_ones().is_multiple_of(2) {
             self.set_flag(Flag::Parity, true);
         }
 
@@ -180,1412 +352,1163 @@ impl Engine {
         }
     }
 
-    // Also trims the parameter if it's a pointer (removes BYTE/WORD/DWORD PTR)
-    fn get_argument_size<'a>(&self, argument: &'a str) -> (Option<VariableSize>, &'a str) {
-        let parts: Vec<&str> = argument.split_whitespace().collect();
-        match parts.as_slice() {
-            ["BYTE", "PTR", arg] => (Some(VariableSize::Byte), *arg),
-            ["WORD", "PTR", arg] => (Some(VariableSize::Word), *arg),
-            ["DWORD", "PTR", arg] => (Some(VariableSize::DoubleWord), *arg),
-            [arg] => {
-                if let Ok((_, size)) = self.parse_value_from_parameter(arg, None) {
-                    (Some(size), *arg)
-                } else {
-                    (None, *arg)
-                }
-            }
-            _ => (None, argument),
+    // Constants are sized by their value: -128..255 is a byte, -32768..65535 a word.
+    fn immediate_size(value: i64) -> VariableSize {
+        if (i8::MIN.into()..=u8::MAX.into()).contains(&value) {
+            VariableSize::Byte
+        } else if (i16::MIN.into()..=u16::MAX.into()).contains(&value) {
+            VariableSize::Word
+        } else {
+            VariableSize::DoubleWord
         }
     }
 
-    pub fn parse_value_from_parameter(
-        &self,
-        parameter: &str,
-        mut size: Option<VariableSize>,
-    ) -> Result<(u32, VariableSize), ErrorCode> {
-        if let Some(value) = parse_string_to_usize(parameter) {
-            // Handle constant values
-            let vi32 = value as i32;
-            let assumed_size = if vi32 >= i8::MIN.into() && vi32 <= u8::MAX.into() {
-                VariableSize::Byte
-            } else if vi32 >= i16::MIN.into() && vi32 <= u16::MAX.into() {
-                VariableSize::Word
-            } else {
-                VariableSize::DoubleWord
-            };
-            // else {
-            //     return Err(ErrorCode::InvalidValue(format!(
-            //         "Value {} is outside of allowed range. 0-u32::MAX",
-            //         value
-            //     )));
-            // };
-            return Ok((value, assumed_size));
+    fn operand_size(&self, operand: &Operand) -> VariableSize {
+        match operand {
+            Operand::Register(reg_name) => get_register_size(reg_name),
+            Operand::Immediate(value) => Self::immediate_size(*value),
+            Operand::Address(_) => self
+                .read_operand(operand)
+                .map_or(VariableSize::Byte, |(_, size)| size),
+            Operand::Memory(memory) => memory.size(),
+            Operand::Sized(_, size) => *size,
+            _ => VariableSize::Byte,
         }
+    }
 
-        if let Ok(reg_name) = RegisterName::from_str_to_reg_name(parameter) {
-            // Handle register values
-            let assumed_size = get_register_size(&reg_name);
-            let value = self.get_register_value(&reg_name);
-            return Ok((value, assumed_size));
+    // Base + (Index * Scale) + Displacement, plus the address of the variable the operand names
+    fn effective_address(&self, memory: &MemoryOperand) -> Result<usize, ErrorCode> {
+        let mut address = memory.disp;
+        if let Some(base) = &memory.base {
+            address += self.get_register_value(base) as i64;
+        }
+        if let Some(index) = &memory.index {
+            address += self.get_register_value(index) as i64 * memory.scale as i64;
+        }
+        if let Some(variable) = &memory.variable {
+            let metadata = self
+                .memory_manager
+                .get_variable(variable)
+                .ok_or(ErrorCode::UnknownVariable)?;
+            address += metadata.start_index as i64;
         }
+        Ok(address as usize)
+    }
 
-        if let Ok(parsed_address) =
-            self.memory_manager
-                .calculate_effective_address(parameter, &self.registers, true)
-        {
-            // Handle memory addresses
-            let memory_address_str = parameter.to_string();
-            if memory_address_str.len() < 3 {
-                return Err(ErrorCode::InvalidPointer(
-                    "Pointer length must be more than 3 including []".to_string(),
-                ));
-            }
-            // No assumed size. If the parameter is a variable [var], then get var's size at declaration
-            // Example: var dw 5
-            // mov eax, [var] ; [var] is assumed as a word pointer
-
-            if size.is_none() {
-                if let Some(metadata) = self
-                    .memory_manager
-                    .get_variable(&memory_address_str[1..memory_address_str.len() - 1])
-                {
-                    size = Some(metadata.size);
-                } else if self
-                    .memory_manager
-                    .labels
-                    .contains_key(&memory_address_str[1..memory_address_str.len() - 1])
-                {
-                    size = Some(VariableSize::Word);
-                }
+    fn read_memory(&self, address: usize, size: VariableSize) -> Result<u32, ErrorCode> {
+        match size {
+            VariableSize::Byte => Ok(self.memory_manager.get_byte(address)? as u32),
+            VariableSize::Word => Ok(self.memory_manager.get_word(address)? as u32),
+            VariableSize::DoubleWord => self.memory_manager.get_dword(address),
+        }
+    }
+
+    fn write_memory(
+        &mut self,
+        address: usize,
+        size: VariableSize,
+        value: u32,
+    ) -> Result<(), ErrorCode> {
+        match size {
+            VariableSize::Byte => self.memory_manager.set_byte(address, value as u8),
+            VariableSize::Word => self.memory_manager.set_word(address, value as u16),
+            VariableSize::DoubleWord => self.memory_manager.set_dword(address, value),
+        }
+    }
+
+    // Value and size of a register, constant or memory operand
+    pub fn read_operand(&self, operand: &Operand) -> Result<(u32, VariableSize), ErrorCode> {
+        match operand {
+            Operand::Immediate(value) => Ok((*value as u32, Self::immediate_size(*value))),
+            Operand::Register(reg_name) => Ok((
+                self.get_register_value(reg_name),
+                get_register_size(reg_name),
+            )),
+            Operand::Memory(memory) => {
+                let address = self.effective_address(memory)?;
+                Ok((self.read_memory(address, memory.size())?, memory.size()))
             }
-            // If we still have no assumed size, the pointer defaults to a byte pointer.
-            match size.unwrap_or(VariableSize::Byte) {
-                VariableSize::Byte => {
-                    let a = self.memory_manager.get_byte(parsed_address)?;
-                    Ok((a as u32, VariableSize::Byte))
-                }
-                VariableSize::Word => {
-                    let a = self.memory_manager.get_word(parsed_address)?;
-                    Ok((a as u32, VariableSize::Word))
-                }
-                VariableSize::DoubleWord => {
-                    let a = self.memory_manager.get_dword(parsed_address)?;
-                    Ok((a, VariableSize::DoubleWord))
-                }
+            Operand::Address(memory) => {
+                let address = self.effective_address(memory)? as i64;
+                Ok((address as u32, Self::immediate_size(address)))
             }
-        } else {
-            Err(ErrorCode::InvalidValue(format!(
-                "Parameter {} could not be parsed.",
-                parameter
-            )))
+            Operand::Sized(operand, size) => Ok((self.read_operand(operand)?.0, *size)),
+            _ => Err(ErrorCode::InvalidValue(format!(
+                "Parameter {:?} could not be parsed.",
+                operand
+            ))),
         }
     }
     pub fn is_valid_register(name: &str) -> bool {
         RegisterName::from_str_to_reg_name(name).is_ok()
     }
     pub fn execute(&mut self, debug: bool) -> Result<(), ErrorCode> {
-        // Go over code to get all the labels.
-        let mut in_proc = false;
-        let mut current_proc_start_ip: usize = 0;
-        let mut current_proc_name = String::new();
-        let mut lines_to_skip = 1;
-        let ip_index = RegisterName::IP.to_index();
-        loop {
-            let line_option = self.lines.next_line();
-            if line_option.is_none() {
-                break;
-            }
-            if let Some(line) = line_option {
-                let proc_string = "PROC".to_string();
-                let end_proc_string = "END".to_string();
-                match line.as_slice() {
-                    [label]
-                        if label.ends_with(':')
-                            && self
-                                .memory_manager
-                                .is_valid_variable_name(&label[..label.len() - 1]) =>
-                    {
-                        let no_colon = label[..label.len() - 1].to_string();
-                        if self.memory_manager.labels.contains_key(&no_colon) {
-                            return Err(ErrorCode::LabelAlreadyExists(format!(
-                                "Label {no_colon} already exists"
-                            )));
-                        }
-                        self.memory_manager.save_label(
-                            no_colon,
-                            self.get_register_value(&RegisterName::IP) as usize,
-                        )?
-                    }
-                    [proc_name, proc] if proc == &proc_string => {
-                        if self.memory_manager.procs.contains_key(proc_name) {
-                            return Err(ErrorCode::LabelAlreadyExists(format!(
-                                "Proc {proc_name} already exists"
-                            )));
-                        }
-                        if in_proc {
-                            return Err(ErrorCode::InvalidOpcode(
-                                "Cannot start new proc while in another.".to_string(),
-                            ));
-                        }
-                        in_proc = true;
-                        current_proc_name.clone_from(proc_name);
-                        current_proc_start_ip = self.get_register_value(&RegisterName::IP) as usize;
-                    }
+        self.assemble_program()?;
 
-                    [proc_name, proc] if proc == &end_proc_string => {
-                        if self.memory_manager.procs.contains_key(proc_name) {
-                            return Err(ErrorCode::LabelAlreadyExists(format!(
-                                "Proc {proc_name} already exists"
-                            )));
-                        }
-                        if !in_proc {
-                            return Err(ErrorCode::InvalidOpcode(
-                                "Cannot end proc outside of a proc.".to_string(),
-                            ));
-                        }
-                        in_proc = false;
-                        let current_proc_end_ip =
-                            self.get_register_value(&RegisterName::IP) as usize;
-                        self.memory_manager.procs.insert(
-                            current_proc_name.clone(),
-                            (current_proc_start_ip + 1, current_proc_end_ip + 1),
-                        );
-                    }
-                    _ => {}
-                }
-            }
-            self.lines.update_ip_register(&mut self.registers[ip_index]);
-        }
-
-        self.lines.set_ip(0);
+        let ip_index = RegisterName::IP.to_index();
+        // `end start` names where the program begins
+        self.lines.set_ip(self.program.entry.unwrap_or(0));
         self.lines.update_ip_register(&mut self.registers[ip_index]);
-        let mut buffer: Option<(usize, String)> = None;
+        let stack_pointer = self.registers[RegisterName::SP.to_index()].get_word();
+        self.sync_stack_pointer(stack_pointer)?;
         if debug {
             let _ = clear_screen(100);
         }
         loop {
-            let line_option = self.lines.next_line();
-            self.lines.update_ip_register(&mut self.registers[ip_index]);
-            let ip: usize = self.get_register_value(&RegisterName::IP) as usize;
-
-            let Some(line) = line_option else {
-                break;
-            };
-            
-            if debug {
-                let _ = clear_screen(14);
-                if let Some((prev_ip, prev_line)) = buffer {
-                    println!("[{}]: {}", prev_ip, prev_line); // Previous
-                } else {
-                    println!();
-                }
-                println!("[{}]: {} <- YOU ARE HERE", ip, back_to_str(&line)); // Current
-
-                if let Some((next_line, next_ip)) = self.lines.peak() {
-                    println!("[{}]: {}", next_ip, back_to_str(&next_line)); // Next
-                    buffer = Some((ip, back_to_str(&line)));
-                } else {
-                    println!();
-                    buffer = None;
-                }
-
-                println!("{}", self);
-
-                pause();
+            match self.step(debug) {
+                Status::Ok => {}
+                Status::Halted => break,
+                Status::Error(error) => return Err(error),
             }
+        }
+        if let Some(output) = self.vga.output() {
+            if self.memory_manager.framebuffer().is_some() {
+                self.dump_framebuffer(&output.to_string_lossy())
+                    .map_err(|error| dump_error(output, error))?;
+            }
+        }
+        if let Some(output) = self.speaker.output() {
+            self.speaker
+                .write_wav(output, self.instruction_count)
+                .map_err(|error| dump_error(output, error))?;
+        }
+        for _ in 0..self.lines_to_skip {
+            println!();
+        }
+        Ok(())
+    }
 
-            let combining_inside_quotes: Vec<String> = combine_parts(&line);
-            let line_str: Vec<&str> = combining_inside_quotes.iter().map(|s| &**s).collect();
-            match line_str.as_slice() {
-                // INC DEC
-                [op @ ("inc" | "dec"), register] if RegisterName::is_valid_name(register) => {
-                    let register = &RegisterName::from_str_to_reg_name(register).expect("The register should have been already checked to be a valid RegisterName.");
-                    let inc = *op == "inc";
-                    let (result, overflowed) = match get_register_size(register) {
-                        VariableSize::Byte => {
-                            let (value, overflowing) = if inc {
-                                (self.get_register_value(register) as u8).overflowing_add(1)
-                            } else {
-                                (self.get_register_value(register) as u8).overflowing_sub(1)
-                            };
+    // Decode the whole program before the first instruction runs.
+    fn assemble_program(&mut self) -> Result<(), ErrorCode> {
+        let program = assemble_file(
+            self.lines.lines(),
+            self.lexer_options,
+            &self.preprocess_options,
+        )?;
+        // Faults read their vectors, which data at address 0 would be mistaken for
+        if program.uses_interrupts || self.interrupts.timer.is_some() || self.exception_mode {
+            self.memory_manager.reserve_ivt();
+        }
+        self.program = Rc::new(program);
+        self.load_data()
+    }
 
-                            self.set_register_value(register, value as u32)?;
-                            (value as u32, overflowing)
-                        }
-                        VariableSize::Word => {
-                            let (value, overflowing) = if inc {
-                                (self.get_register_value(register) as u16).overflowing_add(1)
-                            } else {
-                                (self.get_register_value(register) as u16).overflowing_sub(1)
-                            };
-
-                            self.set_register_value(register, value as u32)?;
-                            (value as u32, overflowing)
-                        }
-                        VariableSize::DoubleWord => {
-                            let (value, overflowing) = if inc {
-                                self.get_register_value(register).overflowing_add(1)
-                            } else {
-                                self.get_register_value(register).overflowing_sub(1)
-                            };
-
-                            self.set_register_value(register, value)?;
-                            (value, overflowing)
-                        }
+    // Lays out every variable before the first instruction runs, as an assembler would,
+    // so data can sit anywhere in the file and its lines do nothing when reached.
+    fn load_data(&mut self) -> Result<(), ErrorCode> {
+        let program = Rc::clone(&self.program);
+        for instruction in &program.instructions {
+            match (instruction.mnemonic, instruction.operands.as_slice()) {
+                (Mnemonic::Define(_), _) => {
+                    let name = instruction.name.clone().unwrap_or_default();
+                    // A definition that didn't decode keeps its room, so the layout still matches `$`
+                    let Some(layout) = program.variables.get(&name) else {
+                        continue;
                     };
-                    self.set_flags(result as usize, get_register_size(register), overflowed);
-                }
-                [op @ ("inc" | "dec"), memory_address]
-                    if self.memory_manager.is_memory_operand(memory_address) =>
-                {
-                    // Case with WORD/BYTE PTR
-
-                    let (size_option, memory_address_str) = self.get_argument_size(memory_address);
-                    let inc = *op == "inc";
-                    let size = size_option.unwrap_or(VariableSize::Byte);
-                    // Calculate effective address
-                    match self.memory_manager.calculate_effective_address(
-                        memory_address_str,
-                        &self.registers,
-                        true,
-                    ) {
-                        Ok(parsed_address) => {
-                            let (current, overflowed) = match size {
-                                VariableSize::Byte => {
-                                    if inc {
-                                        let (cu8, o) = self
-                                            .memory_manager
-                                            .get_byte(parsed_address)?
-                                            .overflowing_add(1);
-                                        (cu8 as u32, o)
-                                    } else {
-                                        let (cu8, o) = self
-                                            .memory_manager
-                                            .get_byte(parsed_address)?
-                                            .overflowing_sub(1);
-                                        (cu8 as u32, o)
-                                    }
-                                }
-                                VariableSize::Word => {
-                                    if inc {
-                                        let (cu16, o) = self
-                                            .memory_manager
-                                            .get_word(parsed_address)?
-                                            .overflowing_add(1);
-                                        (cu16 as u32, o)
-                                    } else {
-                                        let (cu16, o) = self
-                                            .memory_manager
-                                            .get_word(parsed_address)?
-                                            .overflowing_sub(1);
-                                        (cu16 as u32, o)
-                                    }
-                                }
-                                VariableSize::DoubleWord => {
-                                    if inc {
-                                        let (cu16, o) = self
-                                            .memory_manager
-                                            .get_dword(parsed_address)?
-                                            .overflowing_add(1);
-                                        (cu16, o)
-                                    } else {
-                                        let (cu16, o) = self
-                                            .memory_manager
-                                            .get_dword(parsed_address)?
-                                            .overflowing_sub(1);
-                                        (cu16, o)
-                                    }
-                                }
-                            };
-
-                            self.set_flags(current as usize, size, overflowed);
-
-                            match size {
-                                VariableSize::Byte => self
-                                    .memory_manager
-                                    .set_byte(parsed_address, current as u8)?,
-                                VariableSize::Word => self
-                                    .memory_manager
-                                    .set_word(parsed_address, current as u16)?,
-                                VariableSize::DoubleWord => {
-                                    self.memory_manager.set_dword(parsed_address, current)?
-                                }
-                            };
-                        }
-                        Err(error) => return Err(error),
-                    }
-                }
-                ["inc", _rest @ ..] => {
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
-                    }
-                    println!("{}", Instruction::get_help_string(Instruction::Inc));
-                    return Err(ErrorCode::InvalidOpcode(line.join(", ")));
-                }
-                ["dec", _rest @ ..] => {
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
-                    }
-                    println!("{}", Instruction::get_help_string(Instruction::Dec));
-                    return Err(ErrorCode::InvalidOpcode(line.join(", ")));
-                }
-                // LEA
-                ["lea", register, memory_address]
-                    if RegisterName::is_valid_name(register)
-                        && self.memory_manager.is_memory_operand(memory_address) =>
-                {
-                    let register = &RegisterName::from_str_to_reg_name(register).expect("The register should have been already checked to be a valid RegisterName.");
-                    match self.memory_manager.calculate_effective_address(
-                        memory_address,
-                        &self.registers,
-                        true,
-                    ) {
-                        Ok(parsed_address) => {
-                            // Determine the register size
-                            match get_register_size(register) {
-                                VariableSize::Byte => {
-                                    return Err(ErrorCode::NotEnoughSpace(format!(
-                                        "Cannot store pointer in 1-byte register: {:?}.",
-                                        register
-                                    )));
-                                }
-                                VariableSize::Word | VariableSize::DoubleWord => {
-                                    self.mov_reg_const(register, parsed_address as u32)?;
-                                }
-                            }
-                        }
-                        Err(error) => return Err(error),
-                    }
+                    self.memory_manager.reserve_variable(name, *layout)?;
                 }
-                ["lea", _rest @ ..] => {
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
-                    }
-                    println!("{}", Instruction::get_help_string(Instruction::Lea));
-                    return Err(ErrorCode::InvalidOpcode(line.join(", ")));
+                (Mnemonic::Align, [Operand::Immediate(alignment)]) => {
+                    self.memory_manager.align_next(*alignment as usize)
                 }
-                // MOV Instructions
-                // OP    REG      MEM/REG/CONST
-                ["mov", reg, parameter] if RegisterName::is_valid_name(reg) => {
-                    let register = &RegisterName::from_str_to_reg_name(reg).expect("The register should have been already checked to be a valid RegisterName.");
-                    let (size_option, memory_address) = self.get_argument_size(parameter);
-                    let (constant, assumed_size) =
-                        self.parse_value_from_parameter(memory_address, size_option)?;
-
-                    let is_immediate: bool = parse_string_to_usize(parameter).is_some();
-
-                    let reg_size = get_register_size(register);
-
-                    let assumed_size_v = assumed_size.value();
-                    let reg_size_v = reg_size.value();
-                    let invalid = if is_immediate {
-                        assumed_size_v > reg_size_v
-                    } else {
-                        assumed_size_v != reg_size_v
-                    };
-                    if invalid {
-                        return Err(ErrorCode::InvalidValue(format!("Source {parameter} of size ({assumed_size_v}) bytes bytes and destination {reg} of size ({reg_size_v}) bytes are not compatible")));
-                    }
-                    self.mov_reg_const(register, constant)?;
+                (Mnemonic::Stack, [Operand::Immediate(size)]) => {
+                    self.memory_manager.set_stack_size(*size as usize)?
                 }
-                // OP       MEM               REG/CONST
-                ["mov", memory_address, parameter] => {
-                    if self.memory_manager.is_memory_operand(parameter) {
-                        return Err(ErrorCode::InvalidValue(
-                            "Direct memory transfer is not supported.".to_string(),
-                        ));
-                    }
-                    let (size_option_src, _) = self.get_argument_size(parameter);
-
-                    let (size_option_dst, sliced_memory_string) =
-                        self.get_argument_size(memory_address);
-
-                    let is_immediate = parse_string_to_usize(parameter).is_some();
-                    if let Some(size_dst) = size_option_dst {
-                        if let Some(size_src) = size_option_src {
-                            let message = format!("Source {parameter} of size ({}) bytes bytes and destination {memory_address} of size ({}) bytes are not compatible", size_src.value(), size_dst.value());
-                            let is_invalid = if is_immediate {
-                                size_dst.value() < size_src.value()
-                            } else {
-                                size_dst != size_src
-                            };
-                            if is_invalid {
-                                return Err(ErrorCode::InvalidValue(message));
-                            }
-                        }
-                    };
+                _ => {}
+            }
+        }
 
-                    // Calculate effective address of destination
-                    match self.memory_manager.calculate_effective_address(
-                        sliced_memory_string,
-                        &self.registers,
-                        true,
-                    ) {
-                        // Destination is valid address
-                        Ok(parsed_address) => {
-                            // Get value and size of memory to mov into destination address
-                            let (constant, _) = {
-                                let (v, s) =
-                                    self.parse_value_from_parameter(parameter, size_option_src)?;
-                                (v, size_option_src.unwrap_or(s))
-                            };
-                            let (_, assumed_size_memory) = self.parse_value_from_parameter(
-                                sliced_memory_string,
-                                size_option_dst,
-                            )?;
-
-                            match assumed_size_memory {
-                                VariableSize::Byte => self
-                                    .memory_manager
-                                    .set_byte(parsed_address, constant as u8)?,
-                                VariableSize::Word => self
-                                    .memory_manager
-                                    .set_word(parsed_address, constant as u16)?,
-                                VariableSize::DoubleWord => {
-                                    self.memory_manager.set_dword(parsed_address, constant)?
-                                }
-                            };
-                        }
-                        // Destination is not valid address
-                        Err(error) => return Err(error),
-                    }
-                }
-                // HELP COMMAND
-                ["mov", _rest @ ..] => {
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
-                    }
-                    println!("{}", Instruction::get_help_string(Instruction::Mov));
-                    return Err(ErrorCode::InvalidOpcode(line.join(", ")));
-                }
-                // ADD/SUB Instructions
-                //         OP              REG          MEM/REG/CONST
-                [op @ ("add" | "sub"), register, parameter]
-                    if RegisterName::is_valid_name(register) =>
-                {
-                    // Parse the register name
-                    let register = &RegisterName::from_str_to_reg_name(register).expect("The register should have been already checked to be a valid RegisterName.");
+        // Values may be the address of a variable further down, so they are stored last
+        for instruction in &program.instructions {
+            let (Mnemonic::Define(size), None, Some(name)) =
+                (instruction.mnemonic, &instruction.error, &instruction.name)
+            else {
+                continue;
+            };
+            let start = self
+                .memory_manager
+                .get_variable(name)
+                .ok_or(ErrorCode::UnknownVariable)?
+                .start_index;
+            for (i, value) in instruction.operands.iter().enumerate() {
+                let (value, _) = self.read_operand(value)?;
+                let (offset, size) = program.element_layout(name, size, i);
+                self.write_memory(start + offset, size, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the next instruction, delivering a pending interrupt first if IF is set.
+    /// `execute` must have assembled the program before stepping.
+    pub fn step(&mut self, debug: bool) -> Status {
+        if let Err(error) = self.dispatch_pending_interrupt() {
+            return Status::Error(error);
+        }
+        let stack_pointer = self.registers[RegisterName::SP.to_index()].get_word();
+        let result = self
+            .execute_instruction(debug)
+            .and_then(|status| self.sync_stack_pointer(stack_pointer).map(|_| status));
+        let result = match result {
+            Err(error) if self.exception_mode => self.raise_exception(error),
+            result => result,
+        };
+        match result {
+            Ok(Status::Ok) => match self.tick() {
+                Ok(()) => Status::Ok,
+                Err(error) => Status::Error(error),
+            },
+            Ok(status) => status,
+            Err(error) => Status::Error(error),
+        }
+    }
+
+    // Prints the syntax of a misused instruction
+    fn print_help(&mut self, mnemonic: Mnemonic, debug: bool) {
+        if let Some(help) = mnemonic.help() {
+            if debug {
+                self.lines_to_skip += 1;
+                let _ = skip_lines(self.lines_to_skip);
+            }
+            println!("{}", Instruction::get_help_string(help));
+        }
+    }
 
-                    // Determine if the operation is addition or subtraction
-                    let is_addition = *op == "add";
-                    // Check if the parameter is an immediate value
-                    let is_immediate = parse_string_to_usize(parameter).is_some();
+    // AT&T programs move the stack through SP, as `movl %ebp, %esp` does. The stack is tracked
+    // by its depth in SI, so a write to SP moves the top there, and SP follows SI otherwise.
+    // The other dialects leave SP alone.
+    fn sync_stack_pointer(&mut self, before: u16) -> Result<(), ErrorCode> {
+        if self.lexer_options.dialect != Dialect::Att {
+            return Ok(());
+        }
+        let sp = self.registers[RegisterName::SP.to_index()].get_word();
+        let si = &mut self.registers[RegisterName::SI.to_index()];
+        if sp != before {
+            // Addresses are 16 bits, so SP = 0 is the end of a 64 KB memory
+            let address = if sp == 0 { 0x10000 } else { sp as usize };
+            return self.memory_manager.set_stack_pointer(address, si);
+        }
+        // SI may be holding data rather than the depth of the stack, and SP keeps its value then
+        if let Ok(top) = self.memory_manager.stack_pointer(si) {
+            self.registers[RegisterName::SP.to_index()].load_word(top as u16);
+        }
+        Ok(())
+    }
 
-                    // Get the argument size and trimmed parameter
-                    let (size_option, trimmed_parameter) = self.get_argument_size(parameter);
+    fn execute_instruction(&mut self, debug: bool) -> Result<Status, ErrorCode> {
+        let ip_index = RegisterName::IP.to_index();
+        let program = Rc::clone(&self.program);
+        let instruction = program.at(self.lines.get_ip());
+        if let Some(instruction) = instruction {
+            self.lines.fetch(instruction.line);
+        }
+        self.lines.update_ip_register(&mut self.registers[ip_index]);
+        let ip: usize = self.get_register_value(&RegisterName::IP) as usize;
 
-                    // Parse the value from the parameter and get its size
-                    let (constant, assumed_size) =
-                        self.parse_value_from_parameter(trimmed_parameter, size_option)?;
+        let Some(instruction) = instruction else {
+            return Ok(Status::Halted);
+        };
+        let text = &instruction.text;
 
-                    // Get the size of the register
-                    let reg_size = get_register_size(register);
+        if debug {
+            let _ = clear_screen(14);
+            if let Some((prev_ip, prev_line)) = self.previous_line.take() {
+                println!("[{}]: {}", prev_ip, prev_line); // Previous
+            } else {
+                println!();
+            }
+            // Lines from an included file name where they were written
+            let location = match instruction.file {
+                0 => String::new(),
+                file => format!(
+                    " ({}:{})",
+                    program.files[file].display(),
+                    instruction.source_line + 1
+                ),
+            };
+            println!(
+                "[{}]: {} <- YOU ARE HERE{}",
+                ip,
+                back_to_str(text),
+                location
+            ); // Current
+
+            if let Some(next) = program.at(self.lines.get_ip()) {
+                println!("[{}]: {}", next.line + 1, back_to_str(&next.text)); // Next
+                self.previous_line = Some((ip, back_to_str(text)));
+            } else {
+                println!();
+            }
 
-                    // Convert sizes to their numerical values for comparison
-                    let assumed_size_v = assumed_size.value();
-                    let reg_size_v = reg_size.value();
+            println!("{}", self);
 
-                    // Check for size compatibility between the source and destination
-                    let invalid = if is_immediate {
-                        assumed_size_v > reg_size_v
-                    } else {
-                        assumed_size_v != reg_size_v
-                    };
+            pause();
+        }
 
-                    // Return an error if sizes are incompatible
-                    if invalid {
-                        return Err(ErrorCode::InvalidValue(format!(
-                            "Source {} of size ({}) bytes and destination {:?} of size ({}) bytes are not compatible",
-                            parameter, assumed_size_v, register, reg_size_v)));
-                    }
+        if let Some(error) = &instruction.error {
+            self.print_help(instruction.mnemonic, debug);
+            return Err(error.clone());
+        }
 
-                    // Perform the add or sub operation
-                    self.add_or_sub_reg_const(register, constant, is_addition)?;
-                }
-                //         OP               MEM          REG/CONST
-                [op @ ("add" | "sub"), memory_address, parameter] => {
-                    let is_addition = *op == "add";
-                    let is_immediate: bool = parse_string_to_usize(parameter).is_some();
-
-                    if self.memory_manager.is_memory_operand(parameter) {
-                        return Err(ErrorCode::InvalidValue(
-                            "Direct memory transfer is not supported.".to_string(),
-                        ));
+        use Operand::{Memory, Register};
+        match (instruction.mnemonic, instruction.operands.as_slice()) {
+            // INC DEC
+            (op @ (Mnemonic::Inc | Mnemonic::Dec), [Register(register)]) => {
+                let inc = op == Mnemonic::Inc;
+                let (result, overflowed) = match get_register_size(register) {
+                    VariableSize::Byte => {
+                        let (value, overflowing) = if inc {
+                            (self.get_register_value(register) as u8).overflowing_add(1)
+                        } else {
+                            (self.get_register_value(register) as u8).overflowing_sub(1)
+                        };
+
+                        self.set_register_value(register, value as u32)?;
+                        (value as u32, overflowing)
                     }
-                    // Throwing away the second value because we don't need to trim it.
-                    // The second parameter cannot be a memory operand anyway.
-                    let (size_option_src, _) = self.get_argument_size(parameter);
-                    let (size_option_dest, memory_address_str_dest) =
-                        self.get_argument_size(memory_address);
-
-                    // Validate size compatibility if both source and destination sizes are specified
-                    if let (Some(src_size), Some(dest_size)) = (size_option_src, size_option_dest) {
-                        let src_size_val = src_size.value();
-                        let dest_size_val = dest_size.value();
-
-                        let invalid = if is_immediate {
-                            src_size_val > dest_size_val
+                    VariableSize::Word => {
+                        let (value, overflowing) = if inc {
+                            (self.get_register_value(register) as u16).overflowing_add(1)
                         } else {
-                            src_size_val != dest_size_val
+                            (self.get_register_value(register) as u16).overflowing_sub(1)
                         };
 
-                        if invalid {
-                            return Err(ErrorCode::InvalidValue(format!(
-                                "Source {parameter} of size ({src_size_val}) bytes and destination {memory_address} of size ({dest_size_val}) bytes are not compatible"
-                            )));
-                        }
+                        self.set_register_value(register, value as u32)?;
+                        (value as u32, overflowing)
                     }
+                    VariableSize::DoubleWord => {
+                        let (value, overflowing) = if inc {
+                            self.get_register_value(register).overflowing_add(1)
+                        } else {
+                            self.get_register_value(register).overflowing_sub(1)
+                        };
 
-                    // Calculate effective address of the destination
-                    match self.memory_manager.calculate_effective_address(
-                        memory_address_str_dest,
-                        &self.registers,
-                        true,
-                    ) {
-                        // If the destination is a valid address
-                        Ok(parsed_address) => {
-                            // Parse the value from the parameter and get its size
-                            let (constant, assumed_size) =
-                                self.parse_value_from_parameter(parameter, size_option_src)?;
-
-                            // Save the current value of EAX
-                            let eax: u32 = self.get_register_value(&RegisterName::EAX);
-                            // Load the constant into EAX
-                            self.registers[RegisterName::EAX.to_index()].load_dword(constant);
-
-                            // Perform the add or sub operation based on the size
-                            match assumed_size {
-                                VariableSize::Byte => self.add_or_sub_mem_reg(
-                                    parsed_address,
-                                    &RegisterName::AL,
-                                    is_addition,
-                                )?,
-                                VariableSize::Word => self.add_or_sub_mem_reg(
-                                    parsed_address,
-                                    &RegisterName::AX,
-                                    is_addition,
-                                )?,
-                                VariableSize::DoubleWord => self.add_or_sub_mem_reg(
-                                    parsed_address,
-                                    &RegisterName::EAX,
-                                    is_addition,
-                                )?,
-                            };
-
-                            // Restore the original value of EAX
-                            self.registers[RegisterName::EAX.to_index()].load_dword(eax);
+                        self.set_register_value(register, value)?;
+                        (value, overflowing)
+                    }
+                };
+                self.set_flags(result as usize, get_register_size(register), overflowed);
+            }
+            (op @ (Mnemonic::Inc | Mnemonic::Dec), [Memory(memory)]) => {
+                let inc = op == Mnemonic::Inc;
+                let size = memory.size();
+                let parsed_address = self.effective_address(memory)?;
+                let current = self.read_memory(parsed_address, size)?;
+                let (current, overflowed) = match size {
+                    VariableSize::Byte => {
+                        let (cu8, o) = if inc {
+                            (current as u8).overflowing_add(1)
+                        } else {
+                            (current as u8).overflowing_sub(1)
+                        };
+                        (cu8 as u32, o)
+                    }
+                    VariableSize::Word => {
+                        let (cu16, o) = if inc {
+                            (current as u16).overflowing_add(1)
+                        } else {
+                            (current as u16).overflowing_sub(1)
+                        };
+                        (cu16 as u32, o)
+                    }
+                    VariableSize::DoubleWord => {
+                        if inc {
+                            current.overflowing_add(1)
+                        } else {
+                            current.overflowing_sub(1)
                         }
-                        // If the destination is not a valid address, return the error
-                        Err(error) => return Err(error),
                     }
-                }
-                ["add", _rest @ ..] => {
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
+                };
+
+                self.set_flags(current as usize, size, overflowed);
+                self.write_memory(parsed_address, size, current)?;
+            }
+            // LEA
+            (Mnemonic::Lea, [Register(register), Memory(memory)]) => {
+                let parsed_address = self.effective_address(memory)?;
+                // Determine the register size
+                match get_register_size(register) {
+                    VariableSize::Byte => {
+                        return Err(ErrorCode::NotEnoughSpace(format!(
+                            "Cannot store pointer in 1-byte register: {:?}.",
+                            register
+                        )));
                     }
-                    println!("{}", Instruction::get_help_string(Instruction::Add));
-                    return Err(ErrorCode::InvalidOpcode(line.join(", ")));
-                }
-                ["sub", _rest @ ..] => {
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
+                    VariableSize::Word | VariableSize::DoubleWord => {
+                        self.mov_reg_const(register, parsed_address as u32)?;
                     }
-                    println!("{}", Instruction::get_help_string(Instruction::Sub));
-                    return Err(ErrorCode::InvalidOpcode(line.join(", ")));
                 }
-                // MULL / IMUL
-                [op @ ("mul" | "imul"), parameter] => {
-                    // Determine size of the operand
-                    let (size_option_src, memory_address_str_src) =
-                        self.get_argument_size(parameter);
+            }
+            // MOV Instructions
+            // OP    REG      MEM/REG/CONST
+            (Mnemonic::Mov, [Register(register), source]) => {
+                let (constant, assumed_size) = self.read_operand(source)?;
 
-                    let (src_value, backup_size) =
-                        self.parse_value_from_parameter(memory_address_str_src, None)?;
+                let is_immediate = source.is_constant();
 
-                    let size = size_option_src.unwrap_or(backup_size);
+                let reg_size = get_register_size(register);
 
-                    self.mul_value(src_value, size, *op == "imul")?;
+                let assumed_size_v = assumed_size.value();
+                let reg_size_v = reg_size.value();
+                let invalid = if is_immediate {
+                    assumed_size_v > reg_size_v
+                } else {
+                    assumed_size_v != reg_size_v
+                };
+                if invalid {
+                    return Err(ErrorCode::InvalidValue(format!("Source {} of size ({assumed_size_v}) bytes bytes and destination {} of size ({reg_size_v}) bytes are not compatible", text[2], text[1])));
                 }
-                [op @ ("mul" | "imul"), _rest @ ..] => {
-                    // Determine if the operation is signed multiplication (IMUL) or unsigned multiplication (MUL)
-                    let signed = *op == "imul";
-
-                    // Printing purposes
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
-                    }
-
-                    // Print the appropriate help string based on the operation
-                    if signed {
-                        println!("{}", Instruction::get_help_string(Instruction::Imul));
-                    } else {
-                        println!("{}", Instruction::get_help_string(Instruction::Mul));
-                    }
-
-                    // Return an error indicating the invalid opcode
-                    return Err(ErrorCode::InvalidOpcode(line.join(", ")));
+                self.mov_reg_const(register, constant)?;
+            }
+            // OP       MEM               REG/CONST
+            (Mnemonic::Mov, [Memory(memory), source]) => {
+                if let Memory(_) = source {
+                    return Err(ErrorCode::InvalidValue(
+                        "Direct memory transfer is not supported.".to_string(),
+                    ));
                 }
-                // DIV / IDIV Instructions
-                [op @ ("div" | "idiv"), parameter] => {
-                    // Determine size of the operand
-                    let (size_option_src, memory_address_str_src) =
-                        self.get_argument_size(parameter);
-
-                    let (src_value, backup_size) =
-                        self.parse_value_from_parameter(memory_address_str_src, None)?;
+                let size_src = self.operand_size(source);
+                let size_dst = memory.size();
 
-                    let size = size_option_src.unwrap_or(backup_size);
-
-                    self.div_value(src_value, size, *op == "idiv")?;
+                let is_immediate = source.is_constant();
+                let is_invalid = if is_immediate {
+                    size_dst.value() < size_src.value()
+                } else {
+                    size_dst != size_src
+                };
+                if is_invalid {
+                    return Err(ErrorCode::InvalidValue(format!("Source {} of size ({}) bytes bytes and destination {} of size ({}) bytes are not compatible", text[2], size_src.value(), text[1], size_dst.value())));
                 }
-                [op @ ("div" | "idiv"), _rest @ ..] => {
-                    // Determine if the operation is signed (IDIV) or unsigned (DIV)
-                    let signed = *op == "idiv";
-
-                    // Skip the current line as it's not valid
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
-                    }
 
-                    // Print the appropriate help string based on the operation
-                    if signed {
-                        println!("{}", Instruction::get_help_string(Instruction::Idiv));
-                    } else {
-                        println!("{}", Instruction::get_help_string(Instruction::Div));
-                    }
+                let parsed_address = self.effective_address(memory)?;
+                let (constant, _) = self.read_operand(source)?;
+                self.write_memory(parsed_address, size_dst, constant)?;
+            }
+            // ADD/SUB Instructions
+            //         OP              REG          MEM/REG/CONST
+            (op @ (Mnemonic::Add | Mnemonic::Sub), [Register(register), source]) => {
+                // Determine if the operation is addition or subtraction
+                let is_addition = op == Mnemonic::Add;
+                // Check if the parameter is an immediate value
+                let is_immediate = source.is_constant();
+
+                // Parse the value from the parameter and get its size
+                let (constant, assumed_size) = self.read_operand(source)?;
+
+                // Get the size of the register
+                let reg_size = get_register_size(register);
+
+                // Convert sizes to their numerical values for comparison
+                let assumed_size_v = assumed_size.value();
+                let reg_size_v = reg_size.value();
+
+                // Check for size compatibility between the source and destination
+                let invalid = if is_immediate {
+                    assumed_size_v > reg_size_v
+                } else {
+                    assumed_size_v != reg_size_v
+                };
 
-                    // Return an error indicating the invalid opcode
-                    return Err(ErrorCode::InvalidOpcode(line.join(", ")));
+                // Return an error if sizes are incompatible
+                if invalid {
+                    return Err(ErrorCode::InvalidValue(format!(
+                        "Source {} of size ({}) bytes and destination {:?} of size ({}) bytes are not compatible",
+                        text[2], assumed_size_v, register, reg_size_v)));
                 }
-                // Handle SHR and SHL instructions
-                //         OP              REG          PARAMETER
-                [op @ ("shr" | "shl"), register, parameter]
-                    if RegisterName::is_valid_name(register) =>
-                {
-                    // Determine if the shift amount is 'CL' (using the count in CL register) or an immediate value
-                    let shift_amount = if *parameter == "CL" {
-                        // Use the value from CL register if shift count is 'CL'
-                        self.get_register_value(&RegisterName::CL) as u8
-                    } else if let Some(result) = parse_string_to_usize(parameter) { // Parse the immediate value
-                        result as u8
-                    } else {
-                        return Err(ErrorCode::InvalidValue(format!(
-                            "Parameter {} can only be an immediate value, or 'CL'",
-                            parameter
-                        )));
-                    };
 
-                    // Get the register name from the string
-                    let register = &RegisterName::from_str_to_reg_name(register).expect("The register should have been already checked to be a valid RegisterName.");
-
-                    // Get the size of the register operand
-                    let register_size = get_register_size(register);
-
-                    // Determine if it's a shift right (SHR) or shift left (SHL)
-                    let is_shr = *op == "shr";
-
-                    // Perform the shift operation based on the register size
-                    match register_size {
-                        VariableSize::Byte => {
-                            let value_masked = shift_amount & 0b111; // Mask to 3 bits (0-7)
-                            if shift_amount > 7 {
-                                println!(
-                                    "[WARNING] Shift Amount is truncated to 3 bits: ({}).",
-                                    value_masked
-                                );
-                            }
-                            let top = register.is_top().expect("Register should have been verified to be VariableSize::Byte by match arm.");
-                            let current_value: u8 =
-                                self.registers[register.to_index()].get_byte(top);
-                            let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
-
-                            let new_value = if is_shr {
-                                current_value >> value_masked
-                            } else {
-                                current_value << value_masked
-                            };
-
-                            self.registers[register.to_index()].load_byte(new_value, top);
-                            self.set_flags(new_value as usize, VariableSize::Byte, false);
-                            self.set_flag(Flag::Carry, carry_flag == 1);
-                        }
-                        VariableSize::Word => {
-                            let value_masked = shift_amount & 0b11111; // Mask to 5 bits (0-31)
-                            if shift_amount > 31 {
-                                println!(
-                                    "[WARNING] Shift Amount is truncated to 5 bits: ({}).",
-                                    value_masked
-                                );
-                            }
-
-                            let current_value: u16 = self.registers[register.to_index()].get_word();
-                            let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
-
-                            let new_value = if is_shr {
-                                current_value >> value_masked
-                            } else {
-                                current_value << value_masked
-                            };
-
-                            self.registers[register.to_index()].load_word(new_value);
-                            self.set_flags(new_value as usize, VariableSize::Word, false);
-                            self.set_flag(Flag::Carry, carry_flag == 1);
-                        }
-                        VariableSize::DoubleWord => {
-                            let value_masked = shift_amount & 0b11111; // Mask to 5 bits (0-31)
-                            if shift_amount > 31 {
-                                println!(
-                                    "[WARNING] Shift Amount is truncated to 5 bits: ({}).",
-                                    value_masked
-                                );
-                            }
-
-                            let current_value: u32 =
-                                self.registers[register.to_index()].get_dword();
-                            let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
-
-                            let new_value = if is_shr {
-                                current_value >> value_masked
-                            } else {
-                                current_value << value_masked
-                            };
-
-                            self.registers[register.to_index()].load_dword(new_value);
-                            self.set_flags(new_value as usize, VariableSize::DoubleWord, false);
-                            self.set_flag(Flag::Carry, carry_flag == 1);
-                        }
-                    }
+                // Perform the add or sub operation
+                self.add_or_sub_reg_const(register, constant, is_addition)?;
+            }
+            //         OP               MEM          REG/CONST
+            (op @ (Mnemonic::Add | Mnemonic::Sub), [Memory(memory), source]) => {
+                let is_addition = op == Mnemonic::Add;
+                let is_immediate = source.is_constant();
+
+                if let Memory(_) = source {
+                    return Err(ErrorCode::InvalidValue(
+                        "Direct memory transfer is not supported.".to_string(),
+                    ));
                 }
-                // Handle SHR and SHL instructions with memory address operand
-                [op @ ("shr" | "shl"), memory_address, parameter] => {
-                    // Determine if the shift amount is 'CL' (using the count in CL register) or an immediate value
-                    let shift_amount = if *parameter == "CL" {
-                        // Use the value from CL register if shift count is 'CL'
-                        self.get_register_value(&RegisterName::CL) as u8
-                    } else if let Some(result) = parse_string_to_usize(parameter) { // Parse the immediate value
-                        result as u8
-                    } else {
-                        return Err(ErrorCode::InvalidValue(format!(
-                            "Parameter {} can only be an immediate value, or 'CL'",
-                            parameter
-                        )));
-                    };
 
-                    // Get the size of the destination operand
-                    let (size_option_dst, trimmed_dst) = self.get_argument_size(memory_address);
-                    let (memory_value, size_dst) =
-                        self.parse_value_from_parameter(trimmed_dst, size_option_dst)?;
-
-                    // Calculate the effective address of the destination memory operand
-                    let destination = self.memory_manager.calculate_effective_address(
-                        trimmed_dst,
-                        &self.registers,
-                        true,
-                    )?;
-
-                    // Determine if it's a shift right (SHR) or shift left (SHL)
-                    let is_shr = *op == "shr";
-
-                    // Perform the shift operation based on the size of the destination operand
-                    match size_dst {
-                        VariableSize::Byte => {
-                            let value_masked = shift_amount & 0b111; // Mask to 3 bits (0-7)
-                            if shift_amount > 7 {
-                                println!(
-                                    "[WARNING] Shift Amount is truncated to 3 bits: ({}).",
-                                    value_masked
-                                );
-                            }
-
-                            let current_value = memory_value as u8;
-                            let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
-
-                            let new_value = if is_shr {
-                                current_value >> value_masked
-                            } else {
-                                current_value << value_masked
-                            };
-
-                            // Update the memory with the shifted value
-                            self.memory_manager.set_byte(destination, new_value)?;
-                            self.set_flags(new_value as usize, VariableSize::Byte, false);
-                            self.set_flag(Flag::Carry, carry_flag == 1);
-                        }
-                        VariableSize::Word => {
-                            let value_masked = shift_amount & 0b11111; // Mask to 5 bits (0-31)
-                            if shift_amount > 31 {
-                                println!(
-                                    "[WARNING] Shift Amount is truncated to 5 bits: ({}).",
-                                    value_masked
-                                );
-                            }
-
-                            let current_value = memory_value as u16;
-                            let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
-
-                            let new_value = if is_shr {
-                                current_value >> value_masked
-                            } else {
-                                current_value << value_masked
-                            };
-
-                            // Update the memory with the shifted value
-                            self.memory_manager.set_word(destination, new_value)?;
-                            self.set_flags(new_value as usize, VariableSize::Word, false);
-                            self.set_flag(Flag::Carry, carry_flag == 1);
-                        }
-                        VariableSize::DoubleWord => {
-                            let value_masked = shift_amount & 0b11111; // Mask to 5 bits (0-31)
-                            if shift_amount > 31 {
-                                println!(
-                                    "[WARNING] Shift Amount is truncated to 5 bits: ({}).",
-                                    value_masked
-                                );
-                            }
-
-                            let current_value = memory_value;
-                            let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
-
-                            let new_value = if is_shr {
-                                current_value >> value_masked
-                            } else {
-                                current_value << value_masked
-                            };
-
-                            // Update the memory with the shifted value
-                            self.memory_manager.set_dword(destination, new_value)?;
-                            self.set_flag(Flag::Carry, carry_flag == 1);
-                        }
-                    }
+                // Validate size compatibility between source and destination
+                let src_size_val = self.operand_size(source).value();
+                let dest_size_val = memory.size().value();
+                let invalid = if is_immediate {
+                    src_size_val > dest_size_val
+                } else {
+                    src_size_val != dest_size_val
+                };
+                if invalid {
+                    return Err(ErrorCode::InvalidValue(format!(
+                        "Source {} of size ({src_size_val}) bytes and destination {} of size ({dest_size_val}) bytes are not compatible",
+                        text[2], text[1]
+                    )));
                 }
-                // PRINT  Instructions
-                ["print", parameter] => {
-                    let args: Vec<&str> = parameter.split_whitespace().collect();
-                    let trimmed_parameter = if args[0] == "char" {
-                        args[1..].join(" ")
-                    } else {
-                        args.join(" ")
-                    };
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
-                    }
-                    if let Some((start_char, end_char)) =
-                        parameter.chars().next().zip(parameter.chars().next_back())
-                    {
-                        if start_char == '\'' && end_char == '\'' {
-                            println!("[PRINT]@[IP={ip}]:\t{parameter}\n");
-                            continue;
-                        }
-                    }
 
-                    let (size, memory_address_str_src) = self.get_argument_size(&trimmed_parameter);
-                    let (src_value, _) =
-                        self.parse_value_from_parameter(memory_address_str_src, size)?;
-                    if args.into_iter().nth(2) == Some("char") {
-                        if let Some(src_value_char) = std::char::from_u32(src_value) {
-                            println!("[PRINT]@[IP={ip}] {parameter}: {0}\n", src_value_char);
+                // Calculate effective address of the destination
+                let parsed_address = self.effective_address(memory)?;
+                // Parse the value from the parameter and get its size
+                let (constant, assumed_size) = self.read_operand(source)?;
+
+                // Save the current value of EAX
+                let eax: u32 = self.get_register_value(&RegisterName::EAX);
+                // Load the constant into EAX
+                self.registers[RegisterName::EAX.to_index()].load_dword(constant);
+
+                // Perform the add or sub operation based on the size
+                let accumulator = match assumed_size {
+                    VariableSize::Byte => RegisterName::AL,
+                    VariableSize::Word => RegisterName::AX,
+                    VariableSize::DoubleWord => RegisterName::EAX,
+                };
+                self.add_or_sub_mem_reg(parsed_address, &accumulator, is_addition)?;
+
+                // Restore the original value of EAX
+                self.registers[RegisterName::EAX.to_index()].load_dword(eax);
+            }
+            // MULL / IMUL
+            (op @ (Mnemonic::Mul | Mnemonic::Imul), [source]) => {
+                let (src_value, size) = self.read_operand(source)?;
+                self.mul_value(src_value, size, op == Mnemonic::Imul)?;
+            }
+            // DIV / IDIV Instructions
+            (op @ (Mnemonic::Div | Mnemonic::Idiv), [source]) => {
+                let (src_value, size) = self.read_operand(source)?;
+                self.div_value(src_value, size, op == Mnemonic::Idiv)?;
+            }
+            // Handle SHR and SHL instructions
+            //         OP              REG          CL/CONST
+            (op @ (Mnemonic::Shr | Mnemonic::Shl), [Register(register), count]) => {
+                let (shift_amount, _) = self.read_operand(count)?;
+                let shift_amount = shift_amount as u8;
+
+                // Get the size of the register operand
+                let register_size = get_register_size(register);
+
+                // Determine if it's a shift right (SHR) or shift left (SHL)
+                let is_shr = op == Mnemonic::Shr;
+
+                // Perform the shift operation based on the register size
+                match register_size {
+                    VariableSize::Byte => {
+                        let value_masked = shift_amount & 0b111; // Mask to 3 bits (0-7)
+                        if shift_amount > 7 {
+                            println!(
+                                "[WARNING] Shift Amount is truncated to 3 bits: ({}).",
+                                value_masked
+                            );
                         }
-                    } else {
-                        // Check if argument is a string literal and remove surrounding quotes
-                        println!("[PRINT]@[IP={ip}] {parameter}: {0}\n", src_value);
+                        let top = register.is_top().expect("Register should have been verified to be VariableSize::Byte by match arm.");
+                        let current_value: u8 = self.registers[register.to_index()].get_byte(top);
+                        let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
+
+                        let new_value = if is_shr {
+                            current_value >> value_masked
+                        } else {
+                            current_value << value_masked
+                        };
+
+                        self.registers[register.to_index()].load_byte(new_value, top);
+                        self.set_flags(new_value as usize, VariableSize::Byte, false);
+                        self.set_flag(Flag::Carry, carry_flag == 1);
                     }
-                }
-                ["print", number, memory_address_maybe_ch] => {
-                    //if self.memory_manager.is_memory_operand(memory_address) &&
-                    //parse_string_to_usize(*number).is_some() => {
+                    VariableSize::Word => {
+                        let value_masked = shift_amount & 0b11111; // Mask to 5 bits (0-31)
+                        if shift_amount > 31 {
+                            println!(
+                                "[WARNING] Shift Amount is truncated to 5 bits: ({}).",
+                                value_masked
+                            );
+                        }
 
-                    let args: Vec<&str> = memory_address_maybe_ch.split_whitespace().collect();
+                        let current_value: u16 = self.registers[register.to_index()].get_word();
+                        let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
 
-                    let (ch, memory_address) = match args.as_slice() {
-                        ["char", address] => (true, *address),
-                        [address] => (false, *address),
-                        _ => return Err(ErrorCode::InvalidOpcode(line.join(", "))),
-                    };
+                        let new_value = if is_shr {
+                            current_value >> value_masked
+                        } else {
+                            current_value << value_masked
+                        };
 
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
+                        self.registers[register.to_index()].load_word(new_value);
+                        self.set_flags(new_value as usize, VariableSize::Word, false);
+                        self.set_flag(Flag::Carry, carry_flag == 1);
                     }
-
-                    let (size_option_src, trimmed_address) = self.get_argument_size(memory_address);
-                    let size = size_option_src.unwrap_or(VariableSize::Byte);
-                    if let Ok(parsed_address) = self.memory_manager.calculate_effective_address(
-                        trimmed_address,
-                        &self.registers,
-                        true,
-                    ) {
-                        let (value, _) = self.parse_value_from_parameter(number, None)?;
-                        self.memory_manager.check_memory_address(
-                            parsed_address + (value as usize) * size.value(),
-                        )?;
-                        let ip = self.registers[ip_index].get_word();
-                        print!(
-                            "[PRINT]@[IP={ip}][{parsed_address}..{}]:\t[",
-                            parsed_address + (value as usize) - 1
-                        );
-                        for i in 0..value {
-                            match size {
-                                VariableSize::Byte => {
-                                    let src_value = self
-                                        .memory_manager
-                                        .get_byte(parsed_address + (i as usize) * size.value())?;
-
-                                    if ch {
-                                        if let Some(src_value_char) =
-                                            std::char::from_u32(src_value as u32)
-                                        {
-                                            print!("{0}", src_value_char);
-                                        } else {
-                                            print!("{: >width$}", src_value, width = 4);
-                                        }
-                                    } else {
-                                        print!("{: >width$}", src_value, width = 4);
-                                    }
-                                }
-                                VariableSize::Word => {
-                                    let src_value = self
-                                        .memory_manager
-                                        .get_word(parsed_address + (i as usize) * size.value())?;
-                                    if ch {
-                                        if let Some(src_value_char) =
-                                            std::char::from_u32(src_value as u32)
-                                        {
-                                            print!("{0}", src_value_char);
-                                        } else {
-                                            print!("{: >width$}", src_value, width = 4);
-                                        }
-                                    } else {
-                                        print!("{: >width$}", src_value, width = 4);
-                                    }
-                                }
-                                VariableSize::DoubleWord => {
-                                    let src_value = self
-                                        .memory_manager
-                                        .get_dword(parsed_address + (i as usize) * size.value())?;
-                                    if ch {
-                                        if let Some(src_value_char) = std::char::from_u32(src_value)
-                                        {
-                                            print!("{0}", src_value_char);
-                                        } else {
-                                            print!("{0} ", src_value);
-                                        }
-                                    } else {
-                                        print!("{0} ", src_value);
-                                    }
-                                }
-                            }
+                    VariableSize::DoubleWord => {
+                        let value_masked = shift_amount & 0b11111; // Mask to 5 bits (0-31)
+                        if shift_amount > 31 {
+                            println!(
+                                "[WARNING] Shift Amount is truncated to 5 bits: ({}).",
+                                value_masked
+                            );
                         }
-                        println!("]");
 
-                    // } else {
-                    //     return Err(ErrorCode::InvalidValue(format!("Integer {number} could not be parsed.")))
-                    // }
-                    } else {
-                        return Err(ErrorCode::InvalidValue(format!(
-                            "Memory address {memory_address} could not be parsed."
-                        )));
+                        let current_value: u32 = self.registers[register.to_index()].get_dword();
+                        let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
+
+                        let new_value = if is_shr {
+                            current_value >> value_masked
+                        } else {
+                            current_value << value_masked
+                        };
+
+                        self.registers[register.to_index()].load_dword(new_value);
+                        self.set_flags(new_value as usize, VariableSize::DoubleWord, false);
+                        self.set_flag(Flag::Carry, carry_flag == 1);
                     }
                 }
-                ["NOP"] => {}
-                [variable_name, define_as @ ("db" | "dw" | "dd"), rest @ ..]
-                    if self.memory_manager.is_valid_variable_name(variable_name) =>
-                {
-                    // (parse_string_to_usize(*data).is_some() || self.memory_manager.is_valid_array(*data).is_ok() )=> {
-                    let size: VariableSize = match *define_as {
-                        "db" => VariableSize::Byte,
-                        "dw" => VariableSize::Word,
-                        "dd" => VariableSize::DoubleWord,
-                        _ => {
-                            return Err(ErrorCode::InvalidValue(
-                                "Invalid Variable Size".to_string(),
-                            ))
+            }
+            // Handle SHR and SHL instructions with memory address operand
+            (op @ (Mnemonic::Shr | Mnemonic::Shl), [Memory(memory), count]) => {
+                let (shift_amount, _) = self.read_operand(count)?;
+                let shift_amount = shift_amount as u8;
+
+                // Read the destination memory operand
+                let size_dst = memory.size();
+                let destination = self.effective_address(memory)?;
+                let memory_value = self.read_memory(destination, size_dst)?;
+
+                // Determine if it's a shift right (SHR) or shift left (SHL)
+                let is_shr = op == Mnemonic::Shr;
+
+                // Perform the shift operation based on the size of the destination operand
+                match size_dst {
+                    VariableSize::Byte => {
+                        let value_masked = shift_amount & 0b111; // Mask to 3 bits (0-7)
+                        if shift_amount > 7 {
+                            println!(
+                                "[WARNING] Shift Amount is truncated to 3 bits: ({}).",
+                                value_masked
+                            );
                         }
-                    };
-                    let mut bytes: Vec<u32> = Vec::new();
-                    for &arg in rest.iter() {
-                        // Check if argument is a string literal and remove surrounding quotes
-                        if let Some((start_char, end_char)) =
-                            arg.chars().next().zip(arg.chars().next_back())
-                        {
-                            if (start_char == '"' && end_char == '"')
-                                || (start_char == '\'' && end_char == '\'')
-                            {
-                                let inner = &arg[1..arg.len() - 1];
-                                for c in inner.chars() {
-                                    bytes.push(c as u32);
-                                }
-                            } else {
-                                // Handle other cases (numeric values, etc.)
-                                if let Some(value) = parse_string_to_usize(arg) {
-                                    bytes.push(value);
-                                } else {
-                                    return Err(ErrorCode::InvalidValue(format!(
-                                        "Could not parse {arg}"
-                                    )));
-                                }
-                            }
+
+                        let current_value = memory_value as u8;
+                        let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
+
+                        let new_value = if is_shr {
+                            current_value >> value_masked
                         } else {
-                            // Handle other cases (numeric values, etc.)
-                            if let Some(value) = parse_string_to_usize(arg) {
-                                bytes.push(value);
-                            } else {
-                                return Err(ErrorCode::InvalidValue(format!(
-                                    "Could not parse {arg}"
-                                )));
-                            }
-                        }
-                    }
-                    if debug {
-                        lines_to_skip += 1;
-                        let _ = skip_lines(lines_to_skip);
+                            current_value << value_masked
+                        };
+
+                        // Update the memory with the shifted value
+                        self.memory_manager.set_byte(destination, new_value)?;
+                        self.set_flags(new_value as usize, VariableSize::Byte, false);
+                        self.set_flag(Flag::Carry, carry_flag == 1);
                     }
-                    self.memory_manager
-                        .save_variable(variable_name.to_string(), &bytes, size)?
-                }
-                //////// JUMPS ////////////
-                ["jmp", label] => {
-                    if let Err(error) = self.jump_to(label) {
-                        if debug {
-                            lines_to_skip += 1;
-                            let _ = skip_lines(lines_to_skip);
+                    VariableSize::Word => {
+                        let value_masked = shift_amount & 0b11111; // Mask to 5 bits (0-31)
+                        if shift_amount > 31 {
+                            println!(
+                                "[WARNING] Shift Amount is truncated to 5 bits: ({}).",
+                                value_masked
+                            );
                         }
-                        println!("{}", Instruction::get_help_string(Instruction::Jmp));
-                        return Err(error);
-                    }
-                }
-                // JUMPS
-                [_flag @ ("je" | "jz" | "jne" | "jnz"), label] => {
-                    let equal = *_flag == "je" || *_flag == "jz";
-                    if equal != self.is_flag_on(Flag::Zero) {
-                        continue;
+
+                        let current_value = memory_value as u16;
+                        let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
+
+                        let new_value = if is_shr {
+                            current_value >> value_masked
+                        } else {
+                            current_value << value_masked
+                        };
+
+                        // Update the memory with the shifted value
+                        self.memory_manager.set_word(destination, new_value)?;
+                        self.set_flags(new_value as usize, VariableSize::Word, false);
+                        self.set_flag(Flag::Carry, carry_flag == 1);
                     }
-                    if let Err(error) = self.jump_to(label) {
-                        if debug {
-                            lines_to_skip += 1;
-                            let _ = skip_lines(lines_to_skip);
+                    VariableSize::DoubleWord => {
+                        let value_masked = shift_amount & 0b11111; // Mask to 5 bits (0-31)
+                        if shift_amount > 31 {
+                            println!(
+                                "[WARNING] Shift Amount is truncated to 5 bits: ({}).",
+                                value_masked
+                            );
                         }
-                        match *_flag {
-                            "je" => println!("{}", Instruction::get_help_string(Instruction::Je)),
-                            "jz" => println!("{}", Instruction::get_help_string(Instruction::Jz)),
-                            "jne" => println!("{}", Instruction::get_help_string(Instruction::Jne)),
-                            "jnz" => println!("{}", Instruction::get_help_string(Instruction::Jnz)),
-                            _ => {}
-                        }
-                        return Err(error);
-                    }
-                }
-                [_flag @ ("jg" | "jge"), label] => {
-                    let is_jg = *_flag == "jg";
 
-                    if is_jg && self.is_flag_on(Flag::Zero) {
-                        continue;
-                    }
-                    if self.is_flag_on(Flag::Sign) != self.is_flag_on(Flag::Overflow) {
-                        continue;
-                    }
+                        let current_value = memory_value;
+                        let carry_flag = (current_value >> (value_masked - 1)) & 1; // Last bit shifted out
 
-                    if let Err(error) = self.jump_to(label) {
-                        if debug {
-                            lines_to_skip += 1;
-                            let _ = skip_lines(lines_to_skip);
-                        }
-                        match *_flag {
-                            "jg" => println!("{}", Instruction::get_help_string(Instruction::Jg)),
-                            "jge" => println!("{}", Instruction::get_help_string(Instruction::Jge)),
-                            _ => {}
-                        }
-                        return Err(error);
+                        let new_value = if is_shr {
+                            current_value >> value_masked
+                        } else {
+                            current_value << value_masked
+                        };
+
+                        // Update the memory with the shifted value
+                        self.memory_manager.set_dword(destination, new_value)?;
+                        self.set_flag(Flag::Carry, carry_flag == 1);
                     }
                 }
-                [_flag @ ("jl" | "jle"), label] => {
-                    let is_jl = *_flag == "jl";
-
-                    if is_jl && self.is_flag_on(Flag::Zero) {
-                        continue;
+            }
+            // PRINT  Instructions
+            (Mnemonic::Print, [operand]) => {
+                let parameter = &text[1];
+                if debug {
+                    self.lines_to_skip += 1;
+                    let _ = skip_lines(self.lines_to_skip);
+                }
+                match operand {
+                    Operand::Text(_) => println!("[PRINT]@[IP={ip}]:\t{parameter}\n"),
+                    Operand::Char(operand) => {
+                        let (src_value, _) = self.read_operand(operand)?;
+                        if let Some(src_value_char) = std::char::from_u32(src_value) {
+                            println!("[PRINT]@[IP={ip}] {parameter}: {0}\n", src_value_char);
+                        }
                     }
-
-                    if self.is_flag_on(Flag::Sign) == self.is_flag_on(Flag::Overflow) {
-                        continue;
+                    operand => {
+                        let (src_value, _) = self.read_operand(operand)?;
+                        println!("[PRINT]@[IP={ip}] {parameter}: {0}\n", src_value);
                     }
+                }
+            }
+            (Mnemonic::Print, [number, target]) => {
+                let (ch, memory) = match target {
+                    Operand::Char(target) => match &**target {
+                        Memory(memory) => (true, memory),
+                        _ => return Err(ErrorCode::InvalidOpcode(text.join(", "))),
+                    },
+                    Memory(memory) => (false, memory),
+                    _ => return Err(ErrorCode::InvalidOpcode(text.join(", "))),
+                };
 
-                    if let Err(error) = self.jump_to(label) {
-                        if debug {
-                            lines_to_skip += 1;
-                            let _ = skip_lines(lines_to_skip);
-                        }
-                        match *_flag {
-                            "jl" => println!("{}", Instruction::get_help_string(Instruction::Jl)),
-                            "jle" => println!("{}", Instruction::get_help_string(Instruction::Jle)),
-                            _ => {}
-                        }
-                        return Err(error);
-                    }
+                if debug {
+                    self.lines_to_skip += 1;
+                    let _ = skip_lines(self.lines_to_skip);
                 }
-                [_flag @ ("ja" | "jae"), label] => {
-                    let is_ja = *_flag == "ja";
 
-                    if self.is_flag_on(Flag::Carry) || (is_ja && self.is_flag_on(Flag::Zero)) {
-                        continue;
+                let size = memory.size();
+                let parsed_address = self.effective_address(memory)?;
+                let (value, _) = self.read_operand(number)?;
+                self.memory_manager
+                    .check_memory_address(parsed_address + (value as usize) * size.value())?;
+                print!(
+                    "[PRINT]@[IP={ip}][{parsed_address}..{}]:\t[",
+                    parsed_address + (value as usize) - 1
+                );
+                for i in 0..value {
+                    let src_value =
+                        self.read_memory(parsed_address + (i as usize) * size.value(), size)?;
+                    let src_value_char = std::char::from_u32(src_value).filter(|_| ch);
+                    match (src_value_char, size) {
+                        (Some(src_value_char), _) => print!("{0}", src_value_char),
+                        (None, VariableSize::DoubleWord) => print!("{0} ", src_value),
+                        (None, _) => print!("{: >width$}", src_value, width = 4),
                     }
-
-                    if let Err(error) = self.jump_to(label) {
-                        if debug {
-                            lines_to_skip += 1;
-                            let _ = skip_lines(lines_to_skip);
-                        }
-                        match *_flag {
-                            "ja" => println!("{}", Instruction::get_help_string(Instruction::Ja)),
-                            "jae" => println!("{}", Instruction::get_help_string(Instruction::Jae)),
-                            _ => {}
-                        }
+                }
+                println!("]");
+            }
+            //////// JUMPS ////////////
+            (jump, [target]) if jump.is_jump() => {
+                let zero = self.is_flag_on(Flag::Zero);
+                let carry = self.is_flag_on(Flag::Carry);
+                let sign_is_overflow =
+                    self.is_flag_on(Flag::Sign) == self.is_flag_on(Flag::Overflow);
+                let taken = match jump {
+                    Mnemonic::Je | Mnemonic::Jz => zero,
+                    Mnemonic::Jne | Mnemonic::Jnz => !zero,
+                    Mnemonic::Jg => !zero && sign_is_overflow,
+                    Mnemonic::Jge => sign_is_overflow,
+                    Mnemonic::Jl => !zero && !sign_is_overflow,
+                    Mnemonic::Jle => !sign_is_overflow,
+                    Mnemonic::Ja | Mnemonic::Jb => !carry && !zero,
+                    Mnemonic::Jae | Mnemonic::Jbe => !carry,
+                    _ => true,
+                };
+                if taken {
+                    if let Err(error) = self.jump_to(target) {
+                        self.print_help(jump, debug);
                         return Err(error);
                     }
                 }
-                [_flag @ ("jb" | "jbe"), label] => {
-                    let is_jb = *_flag == "jb";
+            }
+            // CMP
+            (Mnemonic::Cmp, [first_operand, second_operand]) => {
+                let (first_operand_value, first_operand_size) = self.read_operand(first_operand)?;
+                let (second_operand_value, second_operand_size) =
+                    self.read_operand(second_operand)?;
 
-                    if self.is_flag_on(Flag::Carry) || (is_jb && self.is_flag_on(Flag::Zero)) {
-                        continue;
-                    }
+                let second_is_immediate = second_operand.is_constant();
 
-                    if let Err(error) = self.jump_to(label) {
-                        if debug {
-                            lines_to_skip += 1;
-                            let _ = skip_lines(lines_to_skip);
+                if (second_is_immediate && first_operand_size.value() < second_operand_size.value())
+                    || (!second_is_immediate && first_operand_size != second_operand_size)
+                {
+                    return Err(ErrorCode::InvalidValue(
+                        format!(
+                            "Target memory pointer size ({}) bytes doesn't match second parameter size ({}) bytes",
+                            first_operand_size.value(),
+                            second_operand_size.value()
+                        )
+                    ));
+                }
+                // Initialize variables to store parsed values
+                let first_value = first_operand_value as isize;
+                let second_value = second_operand_value as isize;
+
+                let result = first_value - second_value;
+                // Set flags based on comparison results
+                self.set_flag(Flag::Zero, result == 0);
+                self.set_flag(Flag::Carry, first_value < second_value);
+                self.set_flag(
+                    Flag::Overflow,
+                    (first_value < 0 && second_value > 0 && result > 0)
+                        || (first_value > 0 && second_value < 0 && result < 0),
+                );
+                self.set_flag(Flag::Sign, result < 0);
+                self.set_flag(Flag::Parity, result.count_ones().is_multiple_of(2));
+            }
+            (Mnemonic::Call, [target]) => {
+                let ip: u32 = self.get_register_value(&RegisterName::IP);
+                self.jump_to(target)?;
+                self.memory_manager.push_to_stack(
+                    ip,
+                    VariableSize::Word,
+                    &mut self.registers[RegisterName::ESI.to_index()],
+                )?;
+            }
+            (Mnemonic::Ret, []) => {
+                let ip_from_stack = self.memory_manager.pop_from_stack(
+                    VariableSize::Word,
+                    &mut self.registers[RegisterName::ESI.to_index()],
+                )?;
+                self.registers[ip_index].load_word(ip_from_stack as u16);
+            }
+            // push BP, point BP at it, then reserve the locals below
+            (Mnemonic::Enter, [Operand::Immediate(size), _]) => {
+                let bp = self.get_register_value(&RegisterName::BP);
+                let si = &mut self.registers[RegisterName::SI.to_index()];
+                self.memory_manager
+                    .push_to_stack(bp, VariableSize::Word, si)?;
+                let frame = self.memory_manager.stack_pointer(si)?;
+                self.memory_manager
+                    .set_stack_pointer(frame.wrapping_sub(*size as usize), si)?;
+                self.registers[RegisterName::BP.to_index()].load_word(frame as u16);
+            }
+            (Mnemonic::Leave, []) => {
+                let frame = self.get_register_value(&RegisterName::BP) as usize;
+                let si = &mut self.registers[RegisterName::SI.to_index()];
+                self.memory_manager.set_stack_pointer(frame, si)?;
+                let bp = self.memory_manager.pop_from_stack(VariableSize::Word, si)?;
+                self.registers[RegisterName::BP.to_index()].load_word(bp as u16);
+            }
+            // STACK OPERATIONS
+            (Mnemonic::Push, [source]) => {
+                let (value, size) = self.read_operand(source)?;
+                self.memory_manager.push_to_stack(
+                    value,
+                    size,
+                    &mut self.registers[RegisterName::SI.to_index()],
+                )?;
+            }
+            (Mnemonic::Pop, [destination]) => {
+                let size = self.operand_size(destination);
+                let popped_value = self
+                    .memory_manager
+                    .pop_from_stack(size, &mut self.registers[RegisterName::SI.to_index()])?;
+                if size == VariableSize::Byte {
+                    return Err(ErrorCode::InvalidValue(
+                        "POP can only receive a 16-bit or 32-bit parameter.".to_string(),
+                    ));
+                }
+                match destination {
+                    Register(register_name) => match size {
+                        VariableSize::DoubleWord => {
+                            self.registers[register_name.to_index()].load_dword(popped_value)
                         }
-                        match *_flag {
-                            "jb" => println!("{}", Instruction::get_help_string(Instruction::Jb)),
-                            "jbe" => println!("{}", Instruction::get_help_string(Instruction::Jbe)),
-                            _ => {}
+                        _ => {
+                            self.registers[register_name.to_index()].load_word(popped_value as u16)
                         }
-                        return Err(error);
+                    },
+                    Memory(memory) => {
+                        let parsed_address = self.effective_address(memory)?;
+                        self.write_memory(parsed_address, size, popped_value)?;
                     }
+                    _ => return Err(ErrorCode::InvalidOpcode(text.join(", "))),
                 }
-                // CMP
-                ["cmp", first_operand, second_operand] => {
-                    let (first_size_option, trimmed_first_parameter) =
-                        self.get_argument_size(first_operand);
-                    let (first_operand_value, first_operand_size) = self
-                        .parse_value_from_parameter(trimmed_first_parameter, first_size_option)?;
-
-                    let (second_size_option, trimmed_second_parameter) =
-                        self.get_argument_size(second_operand);
-                    let (second_operand_value, second_operand_size) = self
-                        .parse_value_from_parameter(trimmed_second_parameter, second_size_option)?;
-
-                    let second_is_immediate: bool = parse_string_to_usize(second_operand).is_some();
-
-                    if (second_is_immediate
-                        && first_operand_size.value() < second_operand_size.value())
-                        || (!second_is_immediate && first_operand_size != second_operand_size)
-                    {
-                        return Err(ErrorCode::InvalidValue(
-                            format!(
-                                "Target memory pointer size ({}) bytes doesn't match second parameter size ({}) bytes",
-                                first_operand_size.value(),
-                                second_operand_size.value()
-                            )
-                        ));
-                    }
-                    // Initialize variables to store parsed values
-                    let first_value = first_operand_value as isize;
-                    let second_value = second_operand_value as isize;
-
-                    let result = first_value - second_value;
-                    // Set flags based on comparison results
-                    self.set_flag(Flag::Zero, result == 0);
-                    self.set_flag(Flag::Carry, first_value < second_value);
-                    self.set_flag(
-                        Flag::Overflow,
-                        (first_value < 0 && second_value > 0 && result > 0)
-                            || (first_value > 0 && second_value < 0 && result < 0),
-                    );
-                    self.set_flag(Flag::Sign, result < 0);
-                    self.set_flag(Flag::Parity, result.count_ones() % 2 == 0);
+            }
+            // INTERRUPTS
+            (Mnemonic::Int, [vector]) => {
+                let (vector, _) = self.read_operand(vector)?;
+                if vector > u8::MAX as u32 {
+                    println!("{}", Instruction::get_help_string(Instruction::Int));
+                    return Err(ErrorCode::InvalidValue(format!(
+                        "Interrupt vector {vector} is outside of 0-255"
+                    )));
                 }
-                ["call", label] => {
-                    let ip: u32 = self.get_register_value(&RegisterName::IP);
-                    match self.jump_to(label) {
-                        Ok(_) => {
-                            self.memory_manager.push_to_stack(
-                                ip,
-                                VariableSize::Word,
-                                &mut self.registers[RegisterName::ESI.to_index()],
-                            )?;
-                        }
-                        Err(error) => return Err(error),
+                let vector = vector as u8;
+                if !self.is_vector_installed(vector)? {
+                    return self.builtin_interrupt(vector);
+                }
+                self.interrupt(vector)?;
+            }
+            (Mnemonic::Iret, []) => {
+                let stack_pointer = &mut self.registers[RegisterName::SI.to_index()];
+                let return_ip = self
+                    .memory_manager
+                    .pop_from_stack(VariableSize::Word, stack_pointer)?;
+                let return_cs = self
+                    .memory_manager
+                    .pop_from_stack(VariableSize::Word, stack_pointer)?;
+                let flags = self
+                    .memory_manager
+                    .pop_from_stack(VariableSize::Word, stack_pointer)?;
+                self.code_segment = return_cs as u16;
+                self.registers[RegisterName::FLAG.to_index()].load_word(flags as u16);
+                self.registers[ip_index].load_word(return_ip as u16);
+            }
+            (Mnemonic::Int3, []) => {
+                if !self.is_vector_installed(BREAKPOINT_VECTOR)? {
+                    return Err(ErrorCode::UnhandledInterrupt(BREAKPOINT_VECTOR));
+                }
+                self.interrupt(BREAKPOINT_VECTOR)?;
+            }
+            (Mnemonic::Into, []) => {
+                if self.is_flag_on(Flag::Overflow) {
+                    // A trap rather than a fault, so it never falls back to #DE
+                    if !self.is_vector_installed(OVERFLOW_VECTOR)? {
+                        return Err(ErrorCode::UnhandledInterrupt(OVERFLOW_VECTOR));
                     }
+                    self.interrupt(OVERFLOW_VECTOR)?;
                 }
-                ["ret"] => {
-                    let ip_from_stack = self.memory_manager.pop_from_stack(
-                        VariableSize::Word,
-                        &mut self.registers[RegisterName::ESI.to_index()],
-                    )?;
-                    // I would use JUMP_TO but it hates me apparently.
-                    self.registers[ip_index].load_word(ip_from_stack as u16);
-                    // self.lines.set_ip(ip_from_stack as usize);
+            }
+            (Mnemonic::Cli, []) => self.set_flag(Flag::Interrupt, false),
+            (Mnemonic::Sti, []) => {
+                if !self.is_flag_on(Flag::Interrupt) {
+                    self.interrupts.set_shadow();
                 }
-                // STACK OPERATIONS
-                ["push", parameter] => {
-                    // No "WORD PTR" etc.
-
-                    let (size_option, trimmed_parameter) = self.get_argument_size(parameter);
-
-                    let (value, size) =
-                        self.parse_value_from_parameter(trimmed_parameter, size_option)?;
-                    self.memory_manager.push_to_stack(
-                        value,
-                        size,
-                        &mut self.registers[RegisterName::SI.to_index()],
-                    )?;
+                self.set_flag(Flag::Interrupt, true);
+            }
+            (Mnemonic::Hlt, []) => {
+                // With interrupts enabled HLT idles until the next IRQ; otherwise it stops the CPU.
+                if !self.is_flag_on(Flag::Interrupt) {
+                    return Ok(Status::Halted);
                 }
-                ["pop", parameter] => {
-                    // No "WORD PTR" etc.
-                    let (size_option, trimmed_parameter) = self.get_argument_size(parameter);
-
-                    let (_, size) =
-                        self.parse_value_from_parameter(trimmed_parameter, size_option)?;
-                    let popped_value = self
-                        .memory_manager
-                        .pop_from_stack(size, &mut self.registers[RegisterName::SI.to_index()])?;
-                    if let Ok(register_name) = RegisterName::from_str_to_reg_name(parameter) {
-                        match size {
-                            VariableSize::Byte => {
-                                return Err(ErrorCode::InvalidValue(
-                                    "POP can only receive a 16-bit or 32-bit parameter."
-                                        .to_string(),
-                                ))
-                            }
-                            VariableSize::Word => self.registers[register_name.to_index()]
-                                .load_word(popped_value as u16),
-                            VariableSize::DoubleWord => {
-                                self.registers[register_name.to_index()].load_dword(popped_value)
-                            }
-                        }
+                if !self.interrupts.has_pending() {
+                    if let Some(timer) = self.interrupts.timer.as_mut() {
+                        self.instruction_count += timer.idle();
                     } else {
-                        // Calculate effective address of destination
-                        match self.memory_manager.calculate_effective_address(
-                            trimmed_parameter,
-                            &self.registers,
-                            true,
-                        ) {
-                            // Destination is valid address
-                            Ok(parsed_address) => match size {
-                                VariableSize::Byte => {
-                                    return Err(ErrorCode::InvalidValue(
-                                        "POP can only receive a 16-bit or 32-bit parameter."
-                                            .to_string(),
-                                    ))
-                                }
-                                VariableSize::Word => self
-                                    .memory_manager
-                                    .set_word(parsed_address, popped_value as u16)?,
-                                VariableSize::DoubleWord => self
-                                    .memory_manager
-                                    .set_dword(parsed_address, popped_value)?,
-                            },
-                            Err(error) => return Err(error),
+                        // Without a timer only the serial receive interrupt can wake the CPU
+                        let Some(serial) = self
+                            .serial
+                            .as_mut()
+                            .filter(|serial| serial.receive_interrupt_armed())
+                        else {
+                            return Ok(Status::Halted);
+                        };
+                        if !serial.wait_for_data() {
+                            return Ok(Status::Halted);
                         }
                     }
                 }
-                // IGNORE LABELS
-                [label] if label.ends_with(':') => {
-                    let no_colon = &label[..label.len() - 1];
-                    // Found no label
-                    if !self.memory_manager.labels.contains_key(no_colon) {
-                        let error_msg = format!(
-                            "Unknown instruction: {:?}.\nPerhaps you misspelt the label name?",
-                            label
-                        );
-                        return Err(ErrorCode::InvalidOpcode(error_msg));
-                    }
+            }
+            // PORT I/O
+            (Mnemonic::In, [Register(dest), port]) => {
+                let port = self.port_number(port)?;
+                let low = self.port_in(port);
+                if *dest == RegisterName::AL {
+                    self.registers[RegisterName::AX.to_index()].load_byte(low, false);
+                } else {
+                    let high = self.port_in(port + 1);
+                    self.registers[RegisterName::AX.to_index()]
+                        .load_word((high as u16) << 8 | low as u16);
+                }
+            }
+            (Mnemonic::Out, [port, Register(source)]) => {
+                let port = self.port_number(port)?;
+                let ax = self.get_register_value(&RegisterName::AX) as u16;
+                self.port_out(port, ax as u8);
+                if *source == RegisterName::AX {
+                    self.port_out(port + 1, (ax >> 8) as u8);
                 }
-                // SKIP PROCS
-                [proc, arg @ ("PROC" | "END")] => {
-                    let end = *arg == "END";
+            }
+            // IGNORE LABELS, CONSTANTS AND DATA, WHICH IS STORED AT LOAD TIME
+            (
+                Mnemonic::Label
+                | Mnemonic::Equ
+                | Mnemonic::Define(_)
+                | Mnemonic::Align
+                | Mnemonic::Directive
+                | Mnemonic::Stack,
+                _,
+            ) => {}
+            // SKIP PROCS
+            (Mnemonic::Proc, [Operand::Label(end)]) => {
+                self.registers[ip_index].load_word(end.address as u16);
+            }
+            _ => {
+                self.print_help(instruction.mnemonic, debug);
+                return Err(ErrorCode::InvalidOpcode(text.join(", ")));
+            }
+        }
+        // Won't panic
+        // If some JUMP was made, this will update the lines module.
+        // If no JUMP was made, this basically does nothing, as it sets lines' ip to itself.
+        self.lines
+            .set_ip(self.get_register_value(&RegisterName::IP) as usize);
+        Ok(Status::Ok)
+    }
 
-                    let end_ip = if let Some((_, end_ip)) = self.memory_manager.procs.get(*proc) {
-                        *end_ip
-                    } else {
-                        let error_msg: String = format!(
-                            "Unknown instruction: {:?}.\nPerhaps you misspelt the proc name?",
-                            proc
-                        );
-                        return Err(ErrorCode::InvalidOpcode(error_msg));
-                    };
+    fn jump_to(&mut self, target: &Operand) -> Result<usize, ErrorCode> {
+        let ip_index = RegisterName::IP.to_index();
+        let target = match target {
+            Operand::Label(label) => {
+                self.registers[ip_index].load_word(label.address as u16);
+                return Ok(label.address);
+            }
+            Operand::Memory(memory) => self.effective_address(memory)?,
+            operand => self.read_operand(operand)?.0 as usize,
+        };
 
-                    if end {
-                        let error_msg: String = "Must return in proc.".to_string();
-                        return Err(ErrorCode::InvalidOpcode(error_msg));
-                    } else {
-                        let caller_ip = format!("{}", end_ip);
-                        self.jump_to(&caller_ip.as_str())?;
-                    }
-                }
+        if target > self.memory_manager.size() - 1 {
+            return Err(ErrorCode::InvalidPointer(format!(
+                "Target IP \"{}\" is outside of memory bounds.",
+                target
+            )));
+        }
 
-                // NO MATCH
-                _ => {
-                    let error_msg = format!("Unknown instruction: {:?}", line);
-                    return Err(ErrorCode::InvalidOpcode(error_msg));
-                    // Handle unrecognized instructions
-                }
+        self.registers[ip_index].load_word(target as u16);
+        Ok(target)
+    }
+
+    // A vector is installed once the program stores a non-zero CS:IP in its IVT entry.
+    fn is_vector_installed(&self, vector: u8) -> Result<bool, ErrorCode> {
+        let (offset, segment) = self.memory_manager.get_interrupt_vector(vector)?;
+        Ok(offset != 0 || segment != 0)
+    }
+
+    // Push FLAGS, CS and IP, clear IF/TF and continue at the handler for `vector`.
+    fn interrupt(&mut self, vector: u8) -> Result<(), ErrorCode> {
+        let (offset, segment) = self.memory_manager.get_interrupt_vector(vector)?;
+        let flags = self.get_register_value(&RegisterName::FLAG);
+        let return_ip = self.get_register_value(&RegisterName::IP);
+        let stack_pointer = &mut self.registers[RegisterName::SI.to_index()];
+        self.memory_manager
+            .push_to_stack(flags, VariableSize::Word, stack_pointer)?;
+        self.memory_manager.push_to_stack(
+            self.code_segment as u32,
+            VariableSize::Word,
+            stack_pointer,
+        )?;
+        self.memory_manager
+            .push_to_stack(return_ip, VariableSize::Word, stack_pointer)?;
+
+        self.set_flag(Flag::Interrupt, false);
+        self.set_flag(Flag::Trap, false);
+        self.code_segment = segment;
+        self.registers[RegisterName::IP.to_index()].load_word(offset);
+        self.lines.set_ip(offset as usize);
+        Ok(())
+    }
+
+    // Services the simulator provides for vectors the program hasn't hooked.
+    fn builtin_interrupt(&mut self, vector: u8) -> Result<Status, ErrorCode> {
+        match vector {
+            LINUX_SYSCALL_VECTOR => linux_syscall(
+                &mut self.memory_manager,
+                &mut self.registers,
+                &mut self.host_io,
+                &mut self.exit_code,
+            ),
+            DOS_VECTOR => dos_service(
+                &mut self.memory_manager,
+                &mut self.registers,
+                &mut self.host_io,
+                &mut self.dos_files,
+                &mut self.exit_code,
+            ),
+            DISK_VECTOR => disk_service(
+                &mut self.memory_manager,
+                &mut self.registers,
+                self.disk.as_mut(),
+            ),
+            VIDEO_VECTOR => {
+                video_service(&mut self.memory_manager, &mut self.registers, &mut self.vga)
             }
-            // Won't panic
-            // If some JUMP was made, this will update the lines module.
-            // If no JUMP was made, this basically does nothing, as it sets lines' ip to itself.
-            self.lines
-                .set_ip(self.get_register_value(&RegisterName::IP) as usize);
+            _ => Err(ErrorCode::UnhandledInterrupt(vector)),
         }
-        for _ in 0..lines_to_skip {
-            println!();
+    }
+
+    // In exception mode a CPU fault vectors to the program's handler instead of ending the run.
+    // Faults push the IP of the faulting instruction, so IRET retries it.
+    fn raise_exception(&mut self, error: ErrorCode) -> Result<Status, ErrorCode> {
+        let Some(vector) = exception_vector(&error) else {
+            return Err(error);
+        };
+        if !self.is_vector_installed(vector)? {
+            return Err(error);
         }
-        Ok(())
+        let fault_ip = self.lines.get_current_ip();
+        self.registers[RegisterName::IP.to_index()].load_word(fault_ip as u16);
+        self.interrupt(vector)?;
+        Ok(Status::Ok)
     }
 
-    fn jump_to(&mut self, label: &&str) -> Result<usize, ErrorCode> {
-        let ip_index = RegisterName::IP.to_index();
-        if let Some(address) = self.memory_manager.labels.get(*label) {
-            // self.lines.set_ip(*address);
-            self.registers[ip_index].load_word(*address as u16);
-            Ok(*address)
-        } else if let Some((start_ip, _)) = self.memory_manager.procs.get(*label) {
-            // self.lines.set_ip(*start_ip);
-            self.registers[ip_index].load_word(*start_ip as u16);
-            Ok(*start_ip)
-        } else {
-            let (size_option, trimmed) = self.get_argument_size(label);
+    // Hardware interrupts are only taken between instructions, and only while IF is set.
+    // An IRQ without an installed handler is acknowledged and dropped.
+    fn dispatch_pending_interrupt(&mut self) -> Result<(), ErrorCode> {
+        if self.interrupts.take_shadow() || !self.is_flag_on(Flag::Interrupt) {
+            return Ok(());
+        }
+        if let Some(vector) = self.interrupts.take_pending() {
+            if self.is_vector_installed(vector)? {
+                self.interrupt(vector)?;
+            }
+        }
+        Ok(())
+    }
 
-            if trimmed != *label {
-                return Err(ErrorCode::InvalidPointer("Invalid Syntax.".to_string()));
+    // Advance the instruction clock and the devices driven by it.
+    fn tick(&mut self) -> Result<(), ErrorCode> {
+        self.instruction_count += 1;
+        if let Some(timer) = self.interrupts.timer.as_mut() {
+            if timer.tick() {
+                self.interrupts.raise(TIMER_VECTOR);
+            }
+        }
+        if let Some(serial) = self.serial.as_mut() {
+            if serial.poll() {
+                self.interrupts.raise(SERIAL_VECTOR);
             }
+        }
+        if let Some(path) = self.vga.advance(self.instruction_count) {
+            self.dump_framebuffer(&path.to_string_lossy())
+                .map_err(|error| dump_error(&path, error))?;
+        }
+        Ok(())
+    }
 
-            let target = if self.memory_manager.is_memory_operand(label) {
-                self.memory_manager
-                    .calculate_effective_address(label, &self.registers, true)?
-            } else {
-                let (a, _) = self.parse_value_from_parameter(label, size_option)?;
-                a as usize
-            };
+    // Reads a byte from an I/O port. Unconnected ports float high.
+    fn port_in(&mut self, port: u16) -> u8 {
+        let now = self.instruction_count;
+        self.vga
+            .port_in(port, now)
+            .or_else(|| self.speaker.port_in(port, now))
+            .or_else(|| self.serial.as_mut().and_then(|serial| serial.port_in(port)))
+            .unwrap_or(0xFF)
+    }
 
-            if target > MEMORY_SIZE - 1 {
-                return Err(ErrorCode::InvalidPointer(format!(
-                    "Target IP \"{}\" is outside of memory bounds.",
-                    target
-                )));
-            }
+    // Writes a byte to an I/O port. Writes to unconnected ports are dropped.
+    fn port_out(&mut self, port: u16, value: u8) {
+        let _ = self.vga.port_out(port, value)
+            || self.speaker.port_out(port, value, self.instruction_count)
+            || self
+                .serial
+                .as_mut()
+                .is_some_and(|serial| serial.port_out(port, value));
+    }
 
-            self.registers[ip_index].load_word(target as u16);
-            // self.lines.set_ip(target);
-            Ok(target)
+    // The port operand of IN/OUT: DX or an 8-bit constant.
+    fn port_number(&self, port: &Operand) -> Result<u16, ErrorCode> {
+        match port {
+            Operand::Register(RegisterName::DX) => {
+                Ok(self.get_register_value(&RegisterName::DX) as u16)
+            }
+            Operand::Immediate(port) if (0..=u8::MAX as i64).contains(port) => Ok(*port as u16),
+            _ => Err(ErrorCode::InvalidValue(format!(
+                "Port {:?} must be DX or a constant in 0-255",
+                port
+            ))),
         }
     }
 
@@ -1894,6 +1817,10 @@ impl Engine {
             let (q, r) = (al_signed / src_signed, al_signed % src_signed);
             (q as u8, r as u8)
         } else {
+            // A quotient that doesn't fit in AL is a divide error, like division by zero
+            if ax_value / src_value as u16 > u8::MAX as u16 {
+                return Err(ErrorCode::Overflow);
+            }
             (
                 (ax_value / src_value as u16) as u8,
                 (ax_value % src_value as u16) as u8,
@@ -1922,8 +1849,14 @@ impl Engine {
                 dividend_signed / src_signed as i32,
                 dividend_signed % src_signed as i32,
             );
+            if q < i16::MIN as i32 || q > i16::MAX as i32 {
+                return Err(ErrorCode::Overflow);
+            }
             (q as u16, r as u16)
         } else {
+            if dividend / src_value as u32 > u16::MAX as u32 {
+                return Err(ErrorCode::Overflow);
+            }
             (
                 (dividend / src_value as u32) as u16,
                 (dividend % src_value as u32) as u16,
@@ -1990,7 +1923,7 @@ impl fmt::Display for Engine {
             write!(f, "{reg_string}  ")?;
 
             let start_index = if i > 6 {
-                MEMORY_SIZE - ((length - i) * 24)
+                self.memory_manager.size() - ((length - i) * 24)
             } else {
                 i * 24
             };
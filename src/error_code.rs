@@ -1,6 +1,6 @@
 use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ErrorCode {
     DivisionByZero,
     StackOverflow,
@@ -13,6 +13,14 @@ pub enum ErrorCode {
     InvalidPointer(String),
     NotEnoughSpace(String),
     InvalidValue(String),
+    UnhandledInterrupt(u8),
+    SyntaxError {
+        // Set for lines that came from an included file
+        file: Option<String>,
+        line: usize,
+        column: usize,
+        message: String,
+    },
 }
 
 impl fmt::Display for ErrorCode {
@@ -32,6 +40,27 @@ impl fmt::Display for ErrorCode {
             ErrorCode::InvalidPointer(msg) => write!(f, "Invalid Pointer: {}", msg),
             ErrorCode::NotEnoughSpace(msg) => write!(f, "Not Enough Space: {}", msg),
             ErrorCode::InvalidValue(msg) => write!(f, "Invalid Value: {}", msg),
+            ErrorCode::UnhandledInterrupt(vector) => {
+                write!(
+                    f,
+                    "Unhandled Interrupt: no handler installed for INT {vector:02X}h"
+                )
+            }
+            ErrorCode::SyntaxError {
+                file: Some(file),
+                line,
+                column,
+                message,
+            } => write!(
+                f,
+                "Syntax Error in {file} at line {line}, column {column}: {message}"
+            ),
+            ErrorCode::SyntaxError {
+                file: None,
+                line,
+                column,
+                message,
+            } => write!(f, "Syntax Error at line {line}, column {column}: {message}"),
         }
     }
 }
@@ -0,0 +1,394 @@
+//! Constant expressions, evaluated while assembling: `+ - * / mod shl shr and or xor not`,
+//! the comparisons `eq ne lt le gt ge`, parentheses, EQU and `=` symbols, the `$` location counter and the OFFSET, SIZEOF,
+//! LENGTHOF and TYPE operators, and STRUC fields as `point.y` offsets or `p1.y` addresses.
+//!
+//! Variables are placed when the program is loaded, so the address of one is not a number
+//! yet. An expression naming a variable evaluates to an offset from it. Two such offsets
+//! can be subtracted, which is how `len equ $ - msg` becomes a plain number.
+
+use crate::{
+    assembler::Program,
+    error_code::ErrorCode,
+    lexer::{tokenize, LexerOptions, Token, TokenKind},
+    register::{get_register_size, RegisterName},
+    utils::{char_immediate, parse_integer},
+    variable_metadata::VariableSize,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    pub constant: i64,
+    // Set when the value is an address: `constant` is then relative to this variable
+    pub variable: Option<String>,
+}
+
+impl Value {
+    pub fn number(constant: i64) -> Self {
+        Self {
+            constant,
+            variable: None,
+        }
+    }
+}
+
+// What the expressions on a line are evaluated against
+pub struct Scope<'a> {
+    pub program: &'a Program,
+    pub options: LexerOptions,
+    // The value of `$`: the line's own address in code, the data location elsewhere
+    pub here: Option<Value>,
+}
+
+pub fn evaluate(text: &str, scope: &Scope) -> Result<Value, ErrorCode> {
+    let mut parser = Parser {
+        text,
+        tokens: tokenize(text, 0, scope.options)?,
+        position: 0,
+        scope,
+    };
+    let value = parser.or()?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(invalid(format!(
+            "Unexpected {} in {text}",
+            &text[token.start..token.end]
+        ))),
+        None => Ok(value),
+    }
+}
+
+fn invalid(message: String) -> ErrorCode {
+    ErrorCode::InvalidValue(message)
+}
+
+// A binary operator on numbers. None when the result is undefined, as for division by zero.
+type Operation = fn(i64, i64) -> Option<i64>;
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    scope: &'a Scope<'a>,
+}
+
+impl Parser<'_> {
+    fn word(&self) -> Option<&str> {
+        match self.tokens.get(self.position)? {
+            token if token.kind == TokenKind::Word => Some(&self.text[token.start..token.end]),
+            _ => None,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self
+            .word()
+            .is_some_and(|word| word.eq_ignore_ascii_case(keyword));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        let found = self
+            .tokens
+            .get(self.position)
+            .is_some_and(|token| token.kind == TokenKind::Punct(c));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    // Both sides must be numbers
+    fn numbers(
+        &self,
+        operator: &str,
+        left: Value,
+        right: Value,
+        apply: Operation,
+    ) -> Result<Value, ErrorCode> {
+        if left.variable.is_some() || right.variable.is_some() {
+            return Err(invalid(format!(
+                "{operator} can't be applied to an address in {}",
+                self.text
+            )));
+        }
+        apply(left.constant, right.constant)
+            .map(Value::number)
+            .ok_or_else(|| invalid(format!("{operator} has no result in {}", self.text)))
+    }
+
+    // OR and XOR bind loosest
+    fn or(&mut self) -> Result<Value, ErrorCode> {
+        let mut value = self.and()?;
+        loop {
+            if self.eat_keyword("or") {
+                let right = self.and()?;
+                value = self.numbers("OR", value, right, |a, b| Some(a | b))?;
+            } else if self.eat_keyword("xor") {
+                let right = self.and()?;
+                value = self.numbers("XOR", value, right, |a, b| Some(a ^ b))?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn and(&mut self) -> Result<Value, ErrorCode> {
+        let mut value = self.not()?;
+        while self.eat_keyword("and") {
+            let right = self.not()?;
+            value = self.numbers("AND", value, right, |a, b| Some(a & b))?;
+        }
+        Ok(value)
+    }
+
+    fn not(&mut self) -> Result<Value, ErrorCode> {
+        if self.eat_keyword("not") {
+            let value = self.not()?;
+            return self.numbers("NOT", value, Value::number(0), |a, _| Some(!a));
+        }
+        self.compare()
+    }
+
+    // True is -1, all bits set, so NOT and AND combine comparisons as MASM does
+    fn compare(&mut self) -> Result<Value, ErrorCode> {
+        let mut value = self.sum()?;
+        loop {
+            let (operator, apply): (&str, Operation) = if self.eat_keyword("eq") {
+                ("EQ", |a, b| Some(-i64::from(a == b)))
+            } else if self.eat_keyword("ne") {
+                ("NE", |a, b| Some(-i64::from(a != b)))
+            } else if self.eat_keyword("lt") {
+                ("LT", |a, b| Some(-i64::from(a < b)))
+            } else if self.eat_keyword("le") {
+                ("LE", |a, b| Some(-i64::from(a <= b)))
+            } else if self.eat_keyword("gt") {
+                ("GT", |a, b| Some(-i64::from(a > b)))
+            } else if self.eat_keyword("ge") {
+                ("GE", |a, b| Some(-i64::from(a >= b)))
+            } else {
+                return Ok(value);
+            };
+            let right = self.sum()?;
+            value = self.numbers(operator, value, right, apply)?;
+        }
+    }
+
+    fn sum(&mut self) -> Result<Value, ErrorCode> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat_punct('+') {
+                let right = self.product()?;
+                value = self.add(value, right)?;
+            } else if self.eat_punct('-') {
+                let right = self.product()?;
+                value = self.subtract(value, right)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn add(&self, left: Value, right: Value) -> Result<Value, ErrorCode> {
+        let variable = match (left.variable, right.variable) {
+            (Some(_), Some(_)) => {
+                return Err(invalid(format!(
+                    "Two addresses can't be added in {}",
+                    self.text
+                )))
+            }
+            (variable, None) | (None, variable) => variable,
+        };
+        let constant = left
+            .constant
+            .checked_add(right.constant)
+            .ok_or_else(|| self.overflow())?;
+        Ok(Value { constant, variable })
+    }
+
+    // The distance between two addresses is a number, even before the variables are placed
+    fn subtract(&self, left: Value, right: Value) -> Result<Value, ErrorCode> {
+        let (left_base, variable) = match (&left.variable, &right.variable) {
+            (Some(from), Some(to)) => (self.offset(from) - self.offset(to), None),
+            (variable, None) => (0, variable.clone()),
+            (None, Some(_)) => {
+                return Err(invalid(format!(
+                    "An address can't be subtracted from a number in {}",
+                    self.text
+                )))
+            }
+        };
+        let constant = left
+            .constant
+            .checked_sub(right.constant)
+            .and_then(|constant| constant.checked_add(left_base))
+            .ok_or_else(|| self.overflow())?;
+        Ok(Value { constant, variable })
+    }
+
+    // Where a variable starts, counted from the first data definition
+    fn offset(&self, variable: &str) -> i64 {
+        self.scope
+            .program
+            .variables
+            .get(variable)
+            .map_or(0, |metadata| metadata.start_index as i64)
+    }
+
+    fn overflow(&self) -> ErrorCode {
+        invalid(format!("{} doesn't fit in 64 bits", self.text))
+    }
+
+    fn product(&mut self) -> Result<Value, ErrorCode> {
+        let mut value = self.unary()?;
+        loop {
+            let (operator, apply): (&str, Operation) = if self.eat_punct('*') {
+                ("*", i64::checked_mul)
+            } else if self.eat_punct('/') {
+                ("/", i64::checked_div)
+            } else if self.eat_keyword("mod") {
+                ("MOD", i64::checked_rem)
+            } else if self.eat_keyword("shl") {
+                ("SHL", |a, b| {
+                    u32::try_from(b).ok().map(|b| a.checked_shl(b).unwrap_or(0))
+                })
+            } else if self.eat_keyword("shr") {
+                ("SHR", |a, b| u32::try_from(b).ok().map(|b| a >> b.min(63)))
+            } else {
+                return Ok(value);
+            };
+            let right = self.unary()?;
+            value = self.numbers(operator, value, right, apply)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, ErrorCode> {
+        if self.eat_punct('-') {
+            let value = self.unary()?;
+            return self.numbers("-", value, Value::number(0), |a, _| a.checked_neg());
+        }
+        if self.eat_punct('+') || self.eat_keyword("offset") {
+            return self.unary();
+        }
+        for operator in ["sizeof", "lengthof", "type"] {
+            if self.eat_keyword(operator) {
+                return self.measure(operator);
+            }
+        }
+        self.primary()
+    }
+
+    // SIZEOF, LENGTHOF and TYPE of a variable. TYPE also takes a register or BYTE/WORD/DWORD.
+    fn measure(&mut self, operator: &str) -> Result<Value, ErrorCode> {
+        let name = self.word().unwrap_or_default().to_string();
+        self.position += 1;
+        let program = self.scope.program;
+        if let Some(structure) = program.structs.get(&name) {
+            return Ok(Value::number(structure.size as i64));
+        }
+        if let Some((_, field)) = program.field(&name) {
+            let size = match operator {
+                "sizeof" => field.length,
+                "lengthof" => field.length / field.width,
+                _ => field.width,
+            };
+            return Ok(Value::number(size as i64));
+        }
+        let size = match program.variables.get(&name) {
+            Some(metadata) => match operator {
+                "sizeof" => metadata.length,
+                "lengthof" => metadata.length / metadata.element_size,
+                _ => metadata.element_size,
+            },
+            None if operator == "type" => match name.as_str() {
+                "BYTE" => VariableSize::Byte,
+                "WORD" => VariableSize::Word,
+                "DWORD" => VariableSize::DoubleWord,
+                _ => RegisterName::from_str_to_reg_name(&name)
+                    .map(|register| get_register_size(&register))
+                    .map_err(|_| {
+                        invalid(format!(
+                            "TYPE expects a variable or register in {}",
+                            self.text
+                        ))
+                    })?,
+            }
+            .value(),
+            None => {
+                return Err(invalid(format!(
+                    "{} expects a variable in {}",
+                    operator.to_uppercase(),
+                    self.text
+                )))
+            }
+        };
+        Ok(Value::number(size as i64))
+    }
+
+    fn primary(&mut self) -> Result<Value, ErrorCode> {
+        if self.eat_punct('(') {
+            let value = self.or()?;
+            if !self.eat_punct(')') {
+                return Err(invalid(format!("Missing ) in {}", self.text)));
+            }
+            return Ok(value);
+        }
+        let Some(token) = self.tokens.get(self.position).cloned() else {
+            return Err(invalid(format!("Missing value in {}", self.text)));
+        };
+        self.position += 1;
+        let word = &self.text[token.start..token.end];
+        match token.kind {
+            TokenKind::Str(string) => char_immediate(&string, self.scope.options.dialect)
+                .map(|value| Value::number(value as i64))
+                .ok_or_else(|| invalid(format!("{word} is too long to be used as a number"))),
+            TokenKind::Word => self.symbol(word),
+            TokenKind::Punct(_) => Err(invalid(format!("Unexpected {word} in {}", self.text))),
+        }
+    }
+
+    fn symbol(&self, word: &str) -> Result<Value, ErrorCode> {
+        let program = self.scope.program;
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            return parse_integer(word)
+                .and_then(|value| i64::try_from(value).ok())
+                .map(Value::number)
+                .ok_or_else(|| invalid(format!("Invalid number {word}")));
+        }
+        if word == "$" {
+            return self
+                .scope
+                .here
+                .clone()
+                .ok_or_else(|| invalid("$ needs a data definition to count from".to_string()));
+        }
+        // `point.y` is the offset of the field, `p1.y` its address in p1
+        if let Some((variable, field)) = program.field(word) {
+            return Ok(Value {
+                constant: field.offset as i64,
+                variable: variable.map(String::from),
+            });
+        }
+        // Memory is flat, so every segment starts at 0
+        if word.eq_ignore_ascii_case("@data") || program.segments.contains(word) {
+            return Ok(Value::number(0));
+        }
+        if let Some(value) = program.constants.get(word) {
+            Ok(value.clone())
+        } else if program.variables.contains_key(word) {
+            Ok(Value {
+                constant: 0,
+                variable: Some(word.to_string()),
+            })
+        } else if let Some(address) = program.code_address(word) {
+            Ok(Value::number(address as i64))
+        } else if RegisterName::from_str_to_reg_name(word).is_ok() {
+            Err(invalid(format!(
+                "Register {word} can't be used in a constant expression"
+            )))
+        } else {
+            Err(invalid(format!("Parameter {word} could not be parsed.")))
+        }
+    }
+}
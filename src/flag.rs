@@ -2,11 +2,13 @@
 
 #[derive(Debug, Clone, Copy)]
 pub enum Flag {
-    Carry    = 0b0000_0001, // Carry Flag
-    Parity   = 0b0000_0010, // Parity Flag
-    Zero     = 0b0000_1000, // Zero Flag
-    Sign     = 0b0001_0000, // Sign Flag
-    Overflow = 0b0010_0000, // Overflow Flag
+    Carry = 0b0000_0001,     // Carry Flag
+    Parity = 0b0000_0010,    // Parity Flag
+    Zero = 0b0000_1000,      // Zero Flag
+    Sign = 0b0001_0000,      // Sign Flag
+    Overflow = 0b0010_0000,  // Overflow Flag
+    Trap = 0b0100_0000,      // Trap Flag
+    Interrupt = 0b1000_0000, // Interrupt Enable Flag
 }
 
 impl Flag {
@@ -14,4 +16,9 @@ impl Flag {
     pub fn value(&self) -> u16 {
         *self as u16
     }
+
+    // Control flags survive arithmetic; only the status flags are recomputed.
+    pub fn control_mask() -> u16 {
+        Flag::Trap.value() | Flag::Interrupt.value()
+    }
 }
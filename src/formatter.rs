@@ -0,0 +1,316 @@
+//! Pretty-prints a program into one canonical layout, for `assembly fmt`:
+//!
+//! * Mnemonics start at the indent, operands 8 columns later and trailing comments line up
+//!   across each run of code lines.
+//! * Mnemonics are written in lowercase and registers the way the dialect spells them:
+//!   capitals for TASM, lowercase for NASM and AT&T.
+//! * Lines under a code label, and the bodies of PROC, MACRO, STRUC and REPT blocks, are
+//!   indented by 4 spaces. Sections, blocks and the next label end a label's body.
+//!
+//! Comments keep their text and blank lines stay where they are, so every line of the
+//! output is the line of the input with the same number.
+
+use crate::{
+    assembler::{Mnemonic, SEGMENT_REGISTERS},
+    att,
+    lexer::{tokenize, Dialect, LexerOptions, Token, TokenKind},
+    nasm::INTERNAL_REGISTERS,
+    register::RegisterName,
+};
+use std::collections::HashSet;
+
+const INDENT: usize = 4;
+// Operands start this many columns after the mnemonic, unless it is longer
+const MNEMONIC_WIDTH: usize = 8;
+// The keywords after a name that declare it
+const DECLARATIONS: [&str; 18] = [
+    "db", "dw", "dd", "dq", "dt", "equ", "=", "label", "proc", "macro", "struc", "struct",
+    "segment", "times", "resb", "resw", "resd", "resq",
+];
+// Blocks open with `name PROC` or `REPT 3`, and close with `name ENDP` or `ENDM`
+const NAMED_BLOCKS: [&str; 4] = ["proc", "macro", "struc", "struct"];
+const BLOCKS: [&str; 5] = ["rept", "irp", "irpc", "%macro", "%rep"];
+const BLOCK_ENDS: [&str; 3] = ["endm", "%endmacro", "%endrep"];
+// Directives that end a label's body
+const SECTIONS: [&str; 16] = [
+    ".code", ".data", ".data?", ".const", ".stack", ".model", ".text", ".bss", ".section",
+    "section", "segment", "assume", "end", "global", ".globl", ".global",
+];
+
+// A formatted line: code with its trailing comment, or a line that stands on its own
+enum Line {
+    Code(String, Option<String>),
+    Text(String),
+}
+
+/// Formats `text`, a whole source file written in `options.dialect`.
+pub fn format_source(text: &str, options: LexerOptions) -> String {
+    let names = declared_names(text, options);
+    let mut lines = Vec::new();
+    // The blocks around the line, innermost last, and whether a label's body is open
+    let mut blocks: Vec<String> = Vec::new();
+    let mut labelled = false;
+    for line in text.lines() {
+        let Some((code, comment)) = split_comment(line, options) else {
+            lines.push(Line::Text(line.trim_end().to_string()));
+            continue;
+        };
+        let comment = comment.map(|comment| comment.trim_end().to_string());
+        let code = code.trim();
+        if code.is_empty() {
+            // Comments at the start of the line stay there, the others follow the code
+            let text = match comment {
+                Some(comment) if line.starts_with(char::is_whitespace) => {
+                    let indent = (blocks.len() + labelled as usize) * INDENT;
+                    format!("{:indent$}{comment}", "")
+                }
+                comment => comment.unwrap_or_default(),
+            };
+            lines.push(Line::Text(text));
+            continue;
+        }
+
+        let (label, statement) = match label_end(code, options) {
+            Some(colon) => (Some(&code[..=colon]), code[colon + 1..].trim()),
+            None => (None, code),
+        };
+        let mut words = statement.split_whitespace().map(str::to_lowercase);
+        let first = words.next().unwrap_or_default();
+        let second = words.next().unwrap_or_default();
+        let indent = if let Some(label) = label {
+            labelled = statement.is_empty() || is_mnemonic(&first, options.dialect);
+            let indent = blocks.len() * INDENT;
+            match statement {
+                "" => format!("{:indent$}{label}", ""),
+                _ => format!("{:indent$}{label} ", ""),
+            }
+        } else if NAMED_BLOCKS.contains(&second.as_str()) || BLOCKS.contains(&first.as_str()) {
+            labelled = false;
+            blocks.push(second);
+            format!("{:1$}", "", (blocks.len() - 1) * INDENT)
+        } else {
+            if BLOCK_ENDS.contains(&first.as_str())
+                || second == "endp"
+                || (second == "ends"
+                    && blocks
+                        .last()
+                        .is_some_and(|block| block.starts_with("struc")))
+                || (second == "end" && blocks.last().is_some_and(|block| block == "proc"))
+            {
+                blocks.pop();
+                labelled = false;
+            } else if SECTIONS.contains(&first.as_str())
+                || ["segment", "ends"].contains(&second.as_str())
+            {
+                labelled = false;
+            }
+            format!("{:1$}", "", (blocks.len() + labelled as usize) * INDENT)
+        };
+        let code = format!("{indent}{}", format_statement(statement, &names, options));
+        lines.push(Line::Code(code.trim_end().to_string(), comment));
+    }
+
+    let mut output = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        match line {
+            Line::Text(text) => output.push_str(text),
+            Line::Code(code, None) => output.push_str(code),
+            Line::Code(code, Some(comment)) => {
+                let width = comment_column(&lines, index);
+                output.push_str(&format!("{code:width$} {comment}"));
+            }
+        }
+        output.push('\n');
+    }
+    if !text.ends_with('\n') {
+        output.pop();
+    }
+    output
+}
+
+// The widest code with a comment in the run of code lines around `index`
+fn comment_column(lines: &[Line], index: usize) -> usize {
+    let is_code = |line: &&Line| matches!(line, Line::Code(..));
+    let start = lines[..index]
+        .iter()
+        .rposition(|line| !is_code(&line))
+        .map_or(0, |start| start + 1);
+    let end = lines[index..]
+        .iter()
+        .position(|line| !is_code(&line))
+        .map_or(lines.len(), |end| index + end);
+    lines[start..end]
+        .iter()
+        .filter_map(|line| match line {
+            Line::Code(code, Some(_)) => Some(code.chars().count()),
+            _ => None,
+        })
+        .max()
+        .unwrap_or_default()
+}
+
+// The mnemonic, padded to its column, and the operands separated by `, `
+fn format_statement(text: &str, names: &HashSet<String>, options: LexerOptions) -> String {
+    let Ok(tokens) = tokenize(text, 0, options) else {
+        return text.to_string();
+    };
+    let Some(first) = tokens.first() else {
+        return String::new();
+    };
+    // As in split_line, the mnemonic runs up to the first whitespace
+    let mut mnemonic_end = first.end;
+    let mut rest = 1;
+    while let Some(token) = tokens.get(rest).filter(|token| token.start == mnemonic_end) {
+        mnemonic_end = token.end;
+        rest += 1;
+    }
+    let written = &text[first.start..mnemonic_end];
+    let lowercase = written.to_lowercase();
+    let mnemonic = if is_mnemonic(&lowercase, options.dialect) && !names.contains(written) {
+        lowercase
+    } else {
+        written.to_string()
+    };
+    let operands = &tokens[rest..];
+    if operands.is_empty() {
+        return mnemonic;
+    }
+
+    // `%define` and friends take their text as it is
+    let groups: Vec<&[Token]> = if mnemonic.starts_with('%') {
+        vec![operands]
+    } else {
+        let mut depth = 0;
+        operands
+            .split(|token| {
+                match token.kind {
+                    TokenKind::Punct('(') => depth += 1,
+                    TokenKind::Punct(')') => depth -= 1,
+                    _ => {}
+                }
+                depth == 0 && token.kind == TokenKind::Punct(',')
+            })
+            .collect()
+    };
+    let operands: Vec<String> = groups
+        .iter()
+        .map(|group| join_tokens(text, group, names, options))
+        .collect();
+    let width = MNEMONIC_WIDTH.max(mnemonic.chars().count() + 1);
+    format!("{mnemonic:width$}{}", operands.join(", "))
+}
+
+// The tokens as written, with registers in the dialect's case and single spaces between
+fn join_tokens(
+    text: &str,
+    tokens: &[Token],
+    names: &HashSet<String>,
+    options: LexerOptions,
+) -> String {
+    let mut joined = String::new();
+    let mut previous: Option<&Token> = None;
+    for token in tokens {
+        if previous.is_some_and(|previous| previous.end < token.start) {
+            joined.push(' ');
+        }
+        let written = &text[token.start..token.end];
+        // AT&T registers are the words after a `%`
+        let register_position = options.dialect != Dialect::Att
+            || previous.is_some_and(|previous| {
+                previous.kind == TokenKind::Punct('%') && previous.end == token.start
+            });
+        match register_case(written, options.dialect) {
+            Some(register)
+                if token.kind == TokenKind::Word
+                    && register_position
+                    && !names.contains(written) =>
+            {
+                joined.push_str(&register)
+            }
+            _ => joined.push_str(written),
+        }
+        previous = Some(token);
+    }
+    joined
+}
+
+fn register_case(word: &str, dialect: Dialect) -> Option<String> {
+    let lowercase = word.to_lowercase();
+    let is_register = RegisterName::from_str_to_reg_name(&word.to_uppercase()).is_ok()
+        || SEGMENT_REGISTERS.contains(&lowercase.as_str());
+    match dialect {
+        _ if !is_register => None,
+        Dialect::Tasm => Some(word.to_uppercase()),
+        Dialect::Nasm if INTERNAL_REGISTERS.contains(&lowercase.as_str()) => None,
+        Dialect::Nasm | Dialect::Att => Some(lowercase),
+    }
+}
+
+// AT&T mnemonics may carry a size suffix, as in `movl`
+fn is_mnemonic(word: &str, dialect: Dialect) -> bool {
+    Mnemonic::parse(word).is_some()
+        || (dialect == Dialect::Att
+            && ["b", "w", "l"].iter().any(|suffix| {
+                word.strip_suffix(suffix)
+                    .is_some_and(|base| Mnemonic::parse(base).is_some())
+            }))
+}
+
+// Labels, variables, constants, procs and macros, which keep their case even when they
+// look like a register or a mnemonic
+fn declared_names(text: &str, options: LexerOptions) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for line in text.lines() {
+        let Some((code, _)) = split_comment(line, options) else {
+            continue;
+        };
+        let code = code.trim();
+        if let Some(colon) = label_end(code, options) {
+            names.insert(code[..colon].to_string());
+            continue;
+        }
+        let mut words = code.split_whitespace();
+        if let (Some(name), Some(keyword)) = (words.next(), words.next()) {
+            let keyword = keyword.trim_end_matches(',').to_lowercase();
+            if DECLARATIONS.contains(&keyword.as_str()) {
+                names.insert(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+// The code and the trailing comment, or None when the line doesn't tokenize
+fn split_comment(line: &str, options: LexerOptions) -> Option<(&str, Option<&str>)> {
+    let start = match options.dialect {
+        Dialect::Att => att::comment_start(line),
+        _ => {
+            let tokens = tokenize(line, 0, options).ok()?;
+            let end = tokens.last().map_or(0, |token| token.end);
+            line[end..].find(';').map(|offset| end + offset)
+        }
+    };
+    Some(match start {
+        Some(start) => (&line[..start], Some(&line[start..])),
+        None => (line, None),
+    })
+}
+
+// The colon of a `name:` at the start of the line
+fn label_end(text: &str, options: LexerOptions) -> Option<usize> {
+    let tokens = tokenize(text, 0, options).ok()?;
+    match tokens.as_slice() {
+        [name, colon, rest @ ..]
+            if name.kind == TokenKind::Word
+                && colon.kind == TokenKind::Punct(':')
+                && colon.start == name.end
+                // `x::` and `seg:[offset]` are something else
+                && rest.first().is_none_or(|next| {
+                    next.start > colon.end || !matches!(next.kind, TokenKind::Punct(':' | '['))
+                }) =>
+        {
+            Some(colon.start)
+        }
+        _ => None,
+    }
+}
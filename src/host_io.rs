@@ -0,0 +1,29 @@
+//! Host streams that emulated OS services read from and write to.
+
+use std::io::{self, Read, Write};
+
+pub struct HostIo {
+    pub stdin: Box<dyn Read>,
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+}
+
+impl HostIo {
+    pub fn new(stdin: Box<dyn Read>, stdout: Box<dyn Write>, stderr: Box<dyn Write>) -> Self {
+        Self {
+            stdin,
+            stdout,
+            stderr,
+        }
+    }
+}
+
+impl Default for HostIo {
+    fn default() -> Self {
+        Self::new(
+            Box::new(io::stdin()),
+            Box::new(io::stdout()),
+            Box::new(io::stderr()),
+        )
+    }
+}
@@ -221,6 +221,60 @@ pub enum Instruction {
     ret
     ret <const>
     */
+    Enter,
+    /*
+    Syntax
+    enter <const>, 0
+    */
+    Leave,
+    /*
+    Syntax
+    leave
+    */
+    Int,
+    /*
+    Syntax
+    int <const>
+    */
+    Into,
+    /*
+    Syntax
+    into
+    */
+    Iret,
+    /*
+    Syntax
+    iret
+    */
+    Cli,
+    /*
+    Syntax
+    cli
+    */
+    Sti,
+    /*
+    Syntax
+    sti
+    */
+    Hlt,
+    /*
+    Syntax
+    hlt
+    */
+    In,
+    /*
+    Syntax
+    in al, dx
+    in ax, dx
+    in al, <const>
+    */
+    Out,
+    /*
+    Syntax
+    out dx, al
+    out dx, ax
+    out <const>, al
+    */
 }
 
 // #[derive(Debug)]
@@ -485,6 +539,61 @@ Syntax:
     ret
     ret <const>".to_string()
             },
+            Instruction::Enter => {
+                "The 'enter' instruction pushes BP, points BP at the saved BP and reserves <const> bytes of locals below it.
+Syntax:
+    enter <const>, 0".to_string()
+            },
+            Instruction::Leave => {
+                "The 'leave' instruction frees the locals reserved by 'enter' and pops BP.
+Syntax:
+    leave".to_string()
+            },
+            Instruction::Int => {
+                "The 'int' instruction pushes FLAGS, CS and IP, clears IF and jumps through the interrupt vector table entry at <const>*4.
+Syntax:
+    int <const>
+    int3".to_string()
+            },
+            Instruction::Into => {
+                "The 'into' instruction raises interrupt 4 (#OF) if the overflow flag (OF) is set.
+Syntax:
+    into".to_string()
+            },
+            Instruction::Iret => {
+                "The 'iret' instruction returns from an interrupt handler, popping IP, CS and FLAGS.
+Syntax:
+    iret".to_string()
+            },
+            Instruction::Cli => {
+                "The 'cli' instruction clears the interrupt flag (IF), masking hardware interrupts.
+Syntax:
+    cli".to_string()
+            },
+            Instruction::Sti => {
+                "The 'sti' instruction sets the interrupt flag (IF), allowing hardware interrupts.
+Syntax:
+    sti".to_string()
+            },
+            Instruction::Hlt => {
+                "The 'hlt' instruction waits for the next hardware interrupt, or stops execution if interrupts are disabled.
+Syntax:
+    hlt".to_string()
+            },
+            Instruction::In => {
+                "The 'in' instruction reads a byte or word from an I/O port. Ports above 255 must be given in DX.
+Syntax:
+    in al, dx
+    in ax, dx
+    in al, <const>".to_string()
+            },
+            Instruction::Out => {
+                "The 'out' instruction writes a byte or word to an I/O port. Ports above 255 must be given in DX.
+Syntax:
+    out dx, al
+    out dx, ax
+    out <const>, al".to_string()
+            },
         }
     }
 }
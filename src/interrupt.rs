@@ -0,0 +1,112 @@
+//! Real-mode interrupt support: the interrupt vector table layout and a programmable timer.
+
+use crate::ErrorCode;
+
+/// Number of vectors in the real-mode interrupt vector table.
+pub const IVT_ENTRIES: usize = 256;
+/// Each vector is a 2-byte offset (IP) followed by a 2-byte segment (CS).
+pub const IVT_ENTRY_SIZE: usize = 4;
+/// The IVT occupies the first kilobyte of memory.
+pub const IVT_SIZE: usize = IVT_ENTRIES * IVT_ENTRY_SIZE;
+
+/// #DE: division by zero, or a quotient too large for the destination.
+pub const DIVIDE_ERROR_VECTOR: u8 = 0x00;
+/// #BP: raised by `int 3` / `int3`.
+pub const BREAKPOINT_VECTOR: u8 = 0x03;
+/// #OF: raised by `into` when OF is set.
+pub const OVERFLOW_VECTOR: u8 = 0x04;
+/// #UD: unknown or malformed instruction.
+pub const INVALID_OPCODE_VECTOR: u8 = 0x06;
+/// IRQ0 (the system timer) is mapped to vector 8, as on the PC.
+pub const TIMER_VECTOR: u8 = 0x08;
+
+/// Mnemonics that make the pre-pass reserve the IVT before any data is laid out.
+pub const INTERRUPT_MNEMONICS: [&str; 6] = ["int", "int3", "into", "iret", "cli", "sti"];
+
+/// The CPU exception an execution error corresponds to, if any.
+pub fn exception_vector(error: &ErrorCode) -> Option<u8> {
+    match error {
+        ErrorCode::DivisionByZero | ErrorCode::Overflow => Some(DIVIDE_ERROR_VECTOR),
+        ErrorCode::InvalidOpcode(_) => Some(INVALID_OPCODE_VECTOR),
+        _ => None,
+    }
+}
+
+/// Raises IRQ0 every `period` executed instructions.
+#[derive(Debug, Clone)]
+pub struct Timer {
+    period: usize,
+    elapsed: usize,
+}
+
+impl Timer {
+    pub fn new(period: usize) -> Self {
+        Self {
+            period: period.max(1),
+            elapsed: 0,
+        }
+    }
+
+    /// Counts one executed instruction. Returns true when the timer fires.
+    pub fn tick(&mut self) -> bool {
+        self.elapsed += 1;
+        if self.elapsed >= self.period {
+            self.elapsed = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Skips ahead so the next `tick` fires. Returns the number of instructions skipped.
+    pub fn idle(&mut self) -> usize {
+        let skipped = self.period - 1 - self.elapsed;
+        self.elapsed = self.period - 1;
+        skipped
+    }
+}
+
+/// Latches hardware interrupt requests until the CPU is able to take them.
+#[derive(Debug, Default)]
+pub struct InterruptController {
+    pending: Vec<u8>,
+    pub timer: Option<Timer>,
+    // Set by STI: interrupts are recognized only after the next instruction
+    shadow: bool,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Latches `vector`. A vector that is already pending is not queued twice.
+    pub fn raise(&mut self, vector: u8) {
+        if !self.pending.contains(&vector) {
+            self.pending.push(vector);
+        }
+    }
+
+    /// Takes the oldest pending vector, if any.
+    pub fn take_pending(&mut self) -> Option<u8> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(self.pending.remove(0))
+        }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Holds off interrupts for one instruction, so `sti` followed by `hlt` can't miss a wake-up.
+    pub fn set_shadow(&mut self) {
+        self.shadow = true;
+    }
+
+    /// Whether the instruction about to run is in the shadow of `sti`. Clears the shadow.
+    pub fn take_shadow(&mut self) -> bool {
+        std::mem::take(&mut self.shadow)
+    }
+}
@@ -0,0 +1,218 @@
+//! Splits a source line into tokens. Quotes are tracked before anything else,
+//! so `;` and `,` inside a string literal are part of the string.
+
+use crate::error_code::ErrorCode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // Mnemonics, registers, names and numbers
+    Word,
+    // A string literal, holding its value once quotes and escapes are resolved
+    Str(String),
+    // Any other character: , [ ] + - * : and so on
+    Punct(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    // Byte offsets into the line
+    pub start: usize,
+    pub end: usize,
+}
+
+// The syntax the source is written in. Other dialects are rewritten into TASM's.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Dialect {
+    #[default]
+    Tasm,
+    Nasm,
+    // AT&T syntax, as GAS and `gcc -S` write it
+    Att,
+}
+
+impl Dialect {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "tasm" | "masm" => Some(Dialect::Tasm),
+            "nasm" => Some(Dialect::Nasm),
+            "att" | "gas" => Some(Dialect::Att),
+            _ => None,
+        }
+    }
+
+    // `.nasm` files are NASM, `.s` files AT&T and anything else TASM
+    pub fn from_path(path: &str) -> Self {
+        match std::path::Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some(extension) if extension.eq_ignore_ascii_case("nasm") => Dialect::Nasm,
+            Some(extension) if extension.eq_ignore_ascii_case("s") => Dialect::Att,
+            _ => Dialect::Tasm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LexerOptions {
+    // Resolve C-style escapes such as \n and \x41 inside string literals
+    pub escapes: bool,
+    pub dialect: Dialect,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '@' | '$' | '?' | '.')
+}
+
+// Column of a byte offset, counted in characters from 1
+fn column(line: &str, offset: usize) -> usize {
+    line[..offset].chars().count() + 1
+}
+
+/// Tokenizes `line`, the line at index `line_number`, stopping at the first `;` outside a string.
+pub fn tokenize(
+    line: &str,
+    line_number: usize,
+    options: LexerOptions,
+) -> Result<Vec<Token>, ErrorCode> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c == ';' {
+            break;
+        }
+        let kind = if c == '\'' || c == '"' {
+            let value =
+                read_string(&mut chars, c, options).ok_or_else(|| ErrorCode::SyntaxError {
+                    file: None,
+                    line: line_number + 1,
+                    column: column(line, start),
+                    message: format!("Unterminated string, expected a closing {c}"),
+                })?;
+            TokenKind::Str(value)
+        } else if is_word_char(c) {
+            while chars.next_if(|(_, next)| is_word_char(*next)).is_some() {}
+            TokenKind::Word
+        } else {
+            TokenKind::Punct(c)
+        };
+        let end = chars.peek().map_or(line.len(), |(offset, _)| *offset);
+        tokens.push(Token { kind, start, end });
+    }
+    Ok(tokens)
+}
+
+// Reads up to the closing `quote`. A doubled quote stands for one quote character.
+fn read_string(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    quote: char,
+    options: LexerOptions,
+) -> Option<String> {
+    let mut value = String::new();
+    loop {
+        let (_, c) = chars.next()?;
+        match c {
+            c if c == quote => {
+                if chars.next_if(|(_, next)| *next == quote).is_none() {
+                    return Some(value);
+                }
+                value.push(quote);
+            }
+            // GAS strings always have escapes
+            '\\' if options.escapes || options.dialect == Dialect::Att => {
+                value.push(read_escape(chars)?)
+            }
+            c => value.push(c),
+        }
+    }
+}
+
+fn read_escape(chars: &mut std::iter::Peekable<std::str::CharIndices>) -> Option<char> {
+    let (_, c) = chars.next()?;
+    let escaped = match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        'a' => '\x07',
+        'b' => '\x08',
+        'e' => '\x1B',
+        'x' => {
+            let mut code = 0;
+            let mut digits = 0;
+            while let Some((_, digit)) =
+                chars.next_if(|(_, next)| next.is_ascii_hexdigit() && digits < 2)
+            {
+                code = code * 16 + digit.to_digit(16).expect("Checked to be a hex digit.");
+                digits += 1;
+            }
+            char::from_u32(code)?
+        }
+        // \\, \' and \" stand for themselves
+        c => c,
+    };
+    Some(escaped)
+}
+
+/// The value of `text` when it is exactly one string literal.
+pub fn unquote(text: &str, options: LexerOptions) -> Option<String> {
+    match tokenize(text, 0, options).ok()?.as_slice() {
+        [Token {
+            kind: TokenKind::Str(value),
+            ..
+        }] => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Splits a line into its mnemonic and comma separated operands, as written.
+/// Blank lines, comments and NOP produce nothing.
+pub fn split_line(
+    line: &str,
+    line_number: usize,
+    options: LexerOptions,
+) -> Result<Option<Vec<String>>, ErrorCode> {
+    let tokens = tokenize(line, line_number, options)?;
+    let Some(first) = tokens.first() else {
+        return Ok(None);
+    };
+
+    // The mnemonic runs up to the first whitespace
+    let mut mnemonic_end = first.end;
+    let mut rest = 1;
+    while let Some(token) = tokens.get(rest).filter(|token| token.start == mnemonic_end) {
+        mnemonic_end = token.end;
+        rest += 1;
+    }
+    let mnemonic = &line[first.start..mnemonic_end];
+    if mnemonic == "NOP" {
+        return Ok(None);
+    }
+
+    let mut parts = vec![mnemonic.to_string()];
+    let operands = &tokens[rest..];
+    if !operands.is_empty() {
+        // Commas inside parentheses belong to the operand, as in 5 DUP(1, 2)
+        let mut depth = 0;
+        let groups = operands.split(|token| {
+            match token.kind {
+                TokenKind::Punct('(') => depth += 1,
+                TokenKind::Punct(')') => depth -= 1,
+                _ => {}
+            }
+            depth == 0 && token.kind == TokenKind::Punct(',')
+        });
+        for group in groups {
+            let operand = match (group.first(), group.last()) {
+                (Some(first), Some(last)) => &line[first.start..last.end],
+                _ => "",
+            };
+            parts.push(operand.to_string());
+        }
+    }
+    Ok(Some(parts))
+}
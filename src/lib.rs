@@ -1,13 +1,29 @@
+pub mod assembler;
+pub mod att;
+pub mod diagnostics;
+pub mod disk;
+pub mod dos;
 pub mod engine;
 pub mod error_code;
+pub mod expression;
 pub mod flag;
+pub mod formatter;
+pub mod host_io;
 pub mod instruction;
+pub mod interrupt;
+pub mod lexer;
 pub mod line_processor;
 pub mod memory_manager;
+pub mod nasm;
+pub mod preprocessor;
 pub mod register;
+pub mod serial;
+pub mod speaker;
 pub mod status;
+pub mod syscall;
 pub mod utils;
 pub mod variable_metadata;
+pub mod vga;
 
 pub use crate::{
     error_code::ErrorCode,
@@ -20,6 +36,32 @@ pub use std::io;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::host_io::HostIo;
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    // Collects what the program writes through the emulated OS services
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture_output(assembly: &mut Engine, stdin: &'static [u8]) -> SharedBuffer {
+        let stdout = SharedBuffer::default();
+        assembly.set_host_io(HostIo::new(
+            Box::new(stdin),
+            Box::new(stdout.clone()),
+            Box::new(io::sink()),
+        ));
+        stdout
+    }
     #[test]
     fn add_sub() {
         let mut assembly = initialize_engine("./tests/add_sub.txt");
@@ -100,4 +142,929 @@ mod tests {
         let expected_memory: Vec<u8> = vec![0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 17];
         verify_memory(&assembly, &expected_memory, 4 * 4);
     }
+
+    #[test]
+    fn int_iret() {
+        let mut assembly = initialize_engine("./tests/int_iret.txt");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.registers[RegisterName::BX.to_index()].get_word() == 15);
+        // Stack is balanced after IRET
+        assert!(assembly.registers[RegisterName::SI.to_index()].get_word() == 0);
+    }
+
+    #[test]
+    fn timer_interrupt() {
+        let mut assembly = initialize_engine("./tests/timer_interrupt.txt");
+        assembly.set_timer(50);
+        execute_engine(&mut assembly, false);
+        assert!(assembly.registers[RegisterName::CX.to_index()].get_word() == 3);
+        // HLT idled until each tick
+        assert!(assembly.instruction_count >= 150);
+    }
+
+    #[test]
+    fn divide_error_handler() {
+        let mut assembly = initialize_engine("./tests/divide_error.txt");
+        assembly.set_exception_mode(true);
+        execute_engine(&mut assembly, false);
+        assert!(assembly.registers[RegisterName::AX.to_index()].get_word() == 20);
+        // AL = 100 / 5
+    }
+
+    #[test]
+    fn invalid_opcode_handler() {
+        let mut assembly = initialize_engine("./tests/invalid_opcode.txt");
+        assembly.set_exception_mode(true);
+        execute_engine(&mut assembly, false);
+        assert!(assembly.registers[RegisterName::BX.to_index()].get_word() == 1);
+        assert!(assembly.registers[RegisterName::CX.to_index()].get_word() == 1);
+    }
+
+    #[test]
+    fn into_without_handler() {
+        let mut assembly = initialize_engine("./tests/into_without_handler.txt");
+        assembly.set_exception_mode(true);
+        let result = assembly.execute(false);
+        assert!(matches!(result, Err(ErrorCode::UnhandledInterrupt(4))));
+    }
+
+    #[test]
+    fn exception_without_handler() {
+        // The data at address 0 isn't taken for a #DE vector
+        let mut assembly = initialize_engine("./tests/divide_by_zero.txt");
+        assembly.set_exception_mode(true);
+        let result = assembly.execute(false);
+        assert!(matches!(result, Err(ErrorCode::DivisionByZero)));
+    }
+
+    #[test]
+    fn linux_hello() {
+        let mut assembly = initialize_engine("./examples/linux_hello.txt");
+        let stdout = capture_output(&mut assembly, b"");
+        execute_engine(&mut assembly, false);
+        assert_eq!(stdout.0.borrow().as_slice(), b"Hello, world!\n");
+        assert_eq!(assembly.exit_code, Some(0));
+        assert!(assembly.registers[RegisterName::EAX.to_index()].get_dword() == 1);
+    }
+
+    #[test]
+    fn linux_syscalls() {
+        let mut assembly = initialize_engine("./tests/linux_syscalls.txt");
+        execute_engine(&mut assembly, false);
+        let initial_break = assembly.registers[RegisterName::EDI.to_index()].get_dword();
+        assert!(assembly.registers[RegisterName::EDX.to_index()].get_dword() == initial_break + 64);
+        assert!(assembly.registers[RegisterName::ECX.to_index()].get_dword() as i32 == -38); // ENOSYS
+        assert!(assembly.registers[RegisterName::EBX.to_index()].get_dword() == 3);
+        assert_eq!(assembly.exit_code, Some(3));
+
+        let mut assembly = initialize_engine("./tests/linux_write_fault.txt");
+        let stdout = capture_output(&mut assembly, b"");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.registers[RegisterName::EAX.to_index()].get_dword() as i32 == -14); // EFAULT
+        assert!(stdout.0.borrow().is_empty());
+
+        let mut assembly = initialize_engine("./tests/linux_framebuffer_fault.txt");
+        let stdout = capture_output(&mut assembly, b"abc");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.registers[RegisterName::EDI.to_index()].get_dword() as i32 == -14);
+        assert!(assembly.registers[RegisterName::EAX.to_index()].get_dword() as i32 == -14);
+        assert!(stdout.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn dos_files() {
+        let root = std::env::temp_dir().join(format!("assembly-dos-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("sandbox")).unwrap();
+        std::fs::write(root.join("secret.txt"), "secret").unwrap();
+
+        let mut assembly = initialize_engine("./tests/dos_files.txt");
+        let stdout = capture_output(&mut assembly, b"abc");
+        assembly
+            .set_file_root(root.join("sandbox").to_str().unwrap())
+            .unwrap();
+        execute_engine(&mut assembly, false);
+
+        assert_eq!(stdout.0.borrow().as_slice(), b"count meabc");
+        assert!(assembly.registers[RegisterName::DI.to_index()].get_word() == 8);
+        assert!(assembly.registers[RegisterName::CX.to_index()].get_word() == 5); // Access denied
+        assert!(assembly.registers[RegisterName::BP.to_index()].get_word() == 5);
+        assert_eq!(
+            std::fs::read_to_string(root.join("sandbox").join("out.txt")).unwrap(),
+            "count me"
+        );
+
+        // Creating a file through a dangling link would land outside the root
+        #[cfg(unix)]
+        {
+            let link = root.join("sandbox").join("link.txt");
+            std::os::unix::fs::symlink(root.join("escaped.txt"), link).unwrap();
+            let mut files = crate::dos::DosFiles::new();
+            files.set_root(&root.join("sandbox")).unwrap();
+            assert_eq!(files.resolve("link.txt"), Err(5));
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn disk_boot() {
+        use crate::disk::{chs_to_lba, IMAGE_SIZE, SECTOR_SIZE};
+        let image_path =
+            std::env::temp_dir().join(format!("assembly-disk-{}.img", std::process::id()));
+        let mut image = vec![0u8; IMAGE_SIZE];
+        image[0] = 0xEB;
+        image[SECTOR_SIZE - 2] = 0x55;
+        image[SECTOR_SIZE - 1] = 0xAA;
+        image[chs_to_lba(1, 1, 3).unwrap() * SECTOR_SIZE] = 42;
+        std::fs::write(&image_path, &image).unwrap();
+
+        let mut assembly = initialize_engine("./tests/disk_boot.txt");
+        assembly.attach_disk(image_path.to_str().unwrap()).unwrap();
+        assembly.load_boot_sector().unwrap();
+        execute_engine(&mut assembly, false);
+
+        let memory = assembly.get_memory(0x7C00 + SECTOR_SIZE);
+        assert_eq!(memory[0x7C00..], image[..SECTOR_SIZE]);
+        assert!(assembly.registers[RegisterName::DI.to_index()].get_word() == 0x0001);
+        assert!(assembly.registers[RegisterName::CX.to_index()].get_byte(false) == 42);
+        assert!(assembly.registers[RegisterName::BP.to_index()].get_word() == 0x0400); // Sector not found
+
+        let written = std::fs::read(&image_path).unwrap();
+        assert_eq!(written[SECTOR_SIZE..2 * SECTOR_SIZE], image[..SECTOR_SIZE]);
+        std::fs::remove_file(&image_path).unwrap();
+    }
+
+    #[test]
+    fn vga_mode_13h() {
+        use crate::vga::{default_palette, HEIGHT, WIDTH};
+        let image_path =
+            std::env::temp_dir().join(format!("assembly-vga-{}.ppm", std::process::id()));
+        let mut assembly = initialize_engine("./tests/vga.txt");
+        assembly.set_vga_output(image_path.to_str().unwrap(), None);
+        execute_engine(&mut assembly, false);
+
+        assert!(assembly.registers[RegisterName::BP.to_index()].get_word() == 0x2813); // 40 columns, mode 13h
+        assert!(assembly.registers[RegisterName::BX.to_index()].get_word() == 0x203F); // Color 1 read back
+        assert!(assembly.registers[RegisterName::AX.to_index()].get_byte(false) == 1);
+        assert!(assembly.instruction_count % 10_000 >= 9_500); // Left the loop in the retrace
+
+        let image = std::fs::read(&image_path).unwrap();
+        let header = format!("P6\n{WIDTH} {HEIGHT}\n255\n");
+        assert!(image.starts_with(header.as_bytes()));
+        let pixels = &image[header.len()..];
+        assert_eq!(pixels.len(), WIDTH * HEIGHT * 3);
+        assert_eq!(pixels[..3], [0xAA, 0x00, 0x00]); // Color 4, red
+        assert_eq!(pixels[pixels.len() - 3..], [0xFF, 0xFF, 0xFF]); // Color 15, white
+        assert_eq!(
+            pixels[(WIDTH + 10) * 3..(WIDTH + 11) * 3],
+            [0xFF, 0x82, 0x00]
+        ); // Orange
+        assert_eq!(pixels[3..6], [0x00, 0x00, 0x00]);
+        std::fs::remove_file(&image_path).unwrap();
+
+        let palette = default_palette();
+        assert_eq!(palette[32], [0x00, 0x00, 0x3F]); // Blue
+        assert_eq!(palette[40], [0x3F, 0x00, 0x00]); // Red
+        assert_eq!(palette[248], [0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn pc_speaker() {
+        use crate::speaker::{INSTRUCTIONS_PER_SECOND, SAMPLE_RATE};
+        let wav_path =
+            std::env::temp_dir().join(format!("assembly-speaker-{}.wav", std::process::id()));
+        let mut assembly = initialize_engine("./tests/speaker.txt");
+        assembly.set_speaker_output(wav_path.to_str().unwrap());
+        execute_engine(&mut assembly, false);
+
+        assert!(assembly.registers[RegisterName::BX.to_index()].get_byte(false) & 0x03 == 0x03);
+        let wav = std::fs::read(&wav_path).unwrap();
+        std::fs::remove_file(&wav_path).unwrap();
+        assert_eq!(wav[..4], *b"RIFF");
+        assert_eq!(wav[8..16], *b"WAVEfmt ");
+        let samples: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(
+            samples.len(),
+            assembly.instruction_count * SAMPLE_RATE / INSTRUCTIONS_PER_SECOND
+        );
+
+        // About 30 ms of a 1000 Hz square wave, then silence
+        let sample_at = |ms: usize| samples[ms * SAMPLE_RATE / 1000];
+        assert!(sample_at(1) != 0);
+        assert!(sample_at(20) != 0);
+        assert!(sample_at(45) == 0);
+        let edges = samples[..SAMPLE_RATE * 30 / 1000]
+            .windows(2)
+            .filter(|pair| pair[0] < 0 && pair[1] > 0)
+            .count();
+        assert!((29..=31).contains(&edges));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serial_echo() {
+        use crate::serial::Serial;
+        use std::{io::Read, os::unix::net::UnixListener};
+        let socket_path =
+            std::env::temp_dir().join(format!("assembly-com1-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let harness = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // One byte at a time, so every byte wakes the program from HLT
+            let mut reply = [0; 3];
+            for (byte, echoed) in b"HAL".iter().zip(reply.iter_mut()) {
+                stream.write_all(&[*byte]).unwrap();
+                let mut received = [0];
+                stream.read_exact(&mut received).unwrap();
+                *echoed = received[0];
+            }
+            reply
+        });
+
+        let mut assembly = initialize_engine("./tests/serial_echo.txt");
+        let bridge = format!("unix:{}", socket_path.display());
+        assembly.attach_serial(Serial::open(&bridge).unwrap());
+        execute_engine(&mut assembly, false);
+
+        assert_eq!(&harness.join().unwrap(), b"IBM");
+        assert!(assembly.registers[RegisterName::BX.to_index()].get_byte(false) == 0x60);
+        std::fs::remove_file(&socket_path).unwrap();
+    }
+
+    #[test]
+    fn decode_program() {
+        use crate::assembler::{assemble, Mnemonic, Operand};
+        use crate::variable_metadata::VariableSize;
+        let lines = utils::read_lines_from_file("./tests/decode.txt").unwrap();
+        let program = assemble(&lines, Default::default()).unwrap();
+        // Blank lines produce no instruction: IP 3 runs the label on line 4
+        assert_eq!(program.at(3).unwrap().line, 4);
+
+        let mov = program.at(5).unwrap();
+        let [Operand::Memory(memory), Operand::Immediate(0x105)] = mov.operands.as_slice() else {
+            panic!("Unexpected operands {:?}", mov.operands);
+        };
+        assert_eq!(memory.base, Some(RegisterName::BX));
+        assert_eq!(memory.index, Some(RegisterName::SI));
+        assert_eq!((memory.scale, memory.disp), (2, -1));
+        assert_eq!(memory.size, Some(VariableSize::Word));
+        assert_eq!(memory.variable.as_deref(), Some("arr"));
+
+        let jnz = program.at(7).unwrap();
+        assert!(matches!(&jnz.operands[..], [Operand::Label(label)] if label.address == 4));
+
+        // Undecodable lines still assemble, and fail when they run
+        let unknown = program.at(8).unwrap();
+        assert_eq!(unknown.mnemonic, Mnemonic::Unknown);
+        assert!(unknown.error.is_some());
+
+        let mut assembly = initialize_engine("./tests/decode.txt");
+        let result = assembly.execute(false);
+        assert!(matches!(result, Err(ErrorCode::InvalidOpcode(_))));
+        verify_memory(&assembly, &[1, 2, 1, 5], 4);
+    }
+
+    #[test]
+    fn string_literals() {
+        let mut assembly = initialize_engine("./tests/strings.txt");
+        execute_engine(&mut assembly, false);
+        verify_memory(&assembly, b"a;b\0x, yit\"sit's", 16);
+        // Backslashes are kept as written unless escapes are enabled
+        assert_eq!(&assembly.get_memory(23)[16..], b"A\\n\\x42");
+
+        let mut assembly = initialize_engine("./tests/strings.txt");
+        assembly.set_escapes(true);
+        execute_engine(&mut assembly, false);
+        assert_eq!(&assembly.get_memory(19)[16..], b"A\nB");
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let mut assembly = initialize_engine("./tests/unterminated_string.txt");
+        let result = assembly.execute(false);
+        assert!(matches!(
+            result,
+            Err(ErrorCode::SyntaxError {
+                line: 2,
+                column: 9,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn numeric_literals() {
+        let mut assembly = initialize_engine("./tests/literals.txt");
+        execute_engine(&mut assembly, false);
+        verify_memory(&assembly, &[0x03, 0xE8, 0, 0x0F, 0xFF, 0xFF, 0xFF, 0xFF], 8);
+        assert!(assembly.get_register_value(&RegisterName::EAX) == 0xFFFF_FFFF);
+        assert!(assembly.get_register_value(&RegisterName::EBX) == 0x7FFF_FFFF);
+        assert!(assembly.get_register_value(&RegisterName::CL) == 0xA5);
+        assert!(assembly.get_register_value(&RegisterName::CH) == 0o17);
+        assert!(assembly.get_register_value(&RegisterName::DL) == 'A' as u32);
+        assert!(assembly.get_register_value(&RegisterName::DH) == 11);
+        assert!(assembly.get_register_value(&RegisterName::BP) == 0x4142);
+        assert!(assembly.get_register_value(&RegisterName::EDI) == 5);
+
+        use crate::utils::parse_integer;
+        assert_eq!(parse_integer("0FFFFFFFFFFFFFFFFh"), Some(u64::MAX as i128));
+        assert_eq!(
+            parse_integer("-8000_0000_0000_0000h"),
+            Some(i64::MIN as i128)
+        );
+        assert_eq!(parse_integer("10000000000000000h"), None);
+        assert_eq!(parse_integer("FFh"), None); // A name, not a number
+        assert_eq!(parse_integer("0b"), Some(0));
+        assert_eq!(parse_integer("19q"), None);
+
+        // NASM puts the first character in the low byte
+        use crate::{lexer::Dialect, utils::char_immediate};
+        assert_eq!(char_immediate("ab", Dialect::Tasm), Some(0x6162));
+        assert_eq!(char_immediate("ab", Dialect::Nasm), Some(0x6261));
+        assert_eq!(char_immediate("\u{100}", Dialect::Nasm), None);
+    }
+
+    #[test]
+    fn literal_out_of_range() {
+        use crate::assembler::assemble;
+        for line in [
+            "mov AL, 256",
+            "mov AX, -32769",
+            "x db, 300",
+            "mov EAX, 100000000h",
+        ] {
+            let program = assemble(&[line.to_string()], Default::default()).unwrap();
+            let error = program.instructions[0].error.clone();
+            assert!(
+                matches!(&error, Some(ErrorCode::InvalidValue(message)) if message.contains("out of range")),
+                "{line}: {error:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn constant_expressions() {
+        let mut assembly = initialize_engine("./tests/constants.txt");
+        execute_engine(&mut assembly, false);
+        // hello, then 1, 2, 3 as words, then the sizes
+        assert_eq!(assembly.get_memory(14)[11..], [11, 2, 0xFF]);
+        assert!(assembly.get_register_value(&RegisterName::AL) == 'o' as u32);
+        assert!(assembly.get_register_value(&RegisterName::AH) == 0);
+        assert!(assembly.get_register_value(&RegisterName::BX) == 6);
+        assert!(assembly.get_register_value(&RegisterName::CX) == 9);
+        assert!(assembly.get_register_value(&RegisterName::DX) == 33);
+        assert!(assembly.get_register_value(&RegisterName::BP) == 2);
+
+        use crate::assembler::assemble;
+        let lines = ["x equ 1".to_string(), "x equ 2".to_string()];
+        assert!(matches!(
+            assemble(&lines, Default::default()),
+            Err(ErrorCode::LabelAlreadyExists(_))
+        ));
+        let lines = ["mov AX, 1 / (2 - 2)".to_string()];
+        let program = assemble(&lines, Default::default()).unwrap();
+        assert!(matches!(
+            program.instructions[0].error,
+            Some(ErrorCode::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn data_directives() {
+        let mut assembly = initialize_engine("./tests/data.txt");
+        execute_engine(&mut assembly, false);
+        #[rustfmt::skip]
+        verify_memory(&assembly, &[
+            1, 7, 7, 1, 7, 7, 0, 0, // bytes, then even
+            0, 0, 0, 0, 0, 0, 0xAA, 0xBB, // words, then table and after at 14
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE, // quad, aligned to 8
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 1, // ten
+        ], 34);
+        assert!(assembly.get_register_value(&RegisterName::AX) == 7);
+        assert!(assembly.get_register_value(&RegisterName::BX) == 6);
+        assert!(assembly.get_register_value(&RegisterName::CX) == 8);
+        assert!(assembly.get_register_value(&RegisterName::DX) == 0xAABB);
+        assert!(assembly.get_register_value(&RegisterName::DI) == 16);
+        assert!(assembly.get_register_value(&RegisterName::BP) == 0);
+
+        use crate::assembler::assemble;
+        for line in [
+            "x db, 70000 DUP(0)",
+            "x db, -1 DUP(0)",
+            "align 3",
+            "x dw, 2 DUP(70000)",
+        ] {
+            let program = assemble(&[line.to_string()], Default::default()).unwrap();
+            assert!(program.instructions[0].error.is_some(), "{line}");
+        }
+    }
+
+    #[test]
+    fn data_at_load_time() {
+        let mut assembly = initialize_engine("./tests/hoisting.txt");
+        execute_engine(&mut assembly, false);
+        // The definition in the loop runs three times but is stored once
+        verify_memory(&assembly, &[b'h', b'i', 8, 42, 0, 3], 6);
+        assert!(assembly.get_register_value(&RegisterName::AL) == 'i' as u32);
+        assert!(assembly.get_register_value(&RegisterName::BL) == 42);
+        assert!(assembly.get_register_value(&RegisterName::DI) == 3);
+    }
+
+    #[test]
+    fn macros() {
+        let mut assembly = initialize_engine("./tests/macros.txt");
+        execute_engine(&mut assembly, false);
+        verify_memory(&assembly, b"abc", 3);
+        assert!(assembly.get_register_value(&RegisterName::AL) == 'c' as u32);
+        assert!(assembly.get_register_value(&RegisterName::BX) == 6);
+        assert!(assembly.get_register_value(&RegisterName::CX) == 0);
+        assert!(assembly.get_register_value(&RegisterName::DX) == 11);
+        assert!(assembly.get_register_value(&RegisterName::SI) == 11);
+
+        use crate::assembler::assemble;
+        let lines = ["broken macro".to_string(), "mov AX, 1".to_string()];
+        assert!(matches!(
+            assemble(&lines, Default::default()),
+            Err(ErrorCode::SyntaxError { line: 1, .. })
+        ));
+        // A bad line in an expansion reports the line of the call
+        let lines = [
+            "broken macro",
+            "mov AX, nowhere",
+            "endm",
+            "mov BX, 1",
+            "broken",
+        ]
+        .map(String::from);
+        let program = assemble(&lines, Default::default()).unwrap();
+        assert!(program.instructions[1].error.is_some());
+        assert_eq!(program.instructions[1].source_line, 4);
+    }
+
+    #[test]
+    fn includes() {
+        let mut assembly = initialize_engine("./tests/includes.txt");
+        assembly.add_include_path("./tests/include/lib");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.get_register_value(&RegisterName::AX) == 42);
+        assert!(assembly.get_register_value(&RegisterName::BX) == 10);
+
+        use crate::assembler::assemble;
+        use crate::preprocessor::{preprocess, PreprocessOptions};
+        let lines = ["include tests/include/lib/values.inc".to_string()];
+        let program = assemble(&lines, Default::default()).unwrap();
+        assert_eq!(program.instructions[1].file, 1);
+        assert_eq!(program.instructions[1].source_line, 1);
+        assert!(program.files[1].ends_with("values.inc"));
+        let lines = ["include tests/include/cycle_a.inc".to_string()];
+        let result = preprocess(&lines, Default::default(), &PreprocessOptions::default());
+        assert!(matches!(
+            result,
+            Err(ErrorCode::SyntaxError { file: Some(file), line: 1, message, .. })
+                if file.ends_with("cycle_b.inc") && message.starts_with("Include cycle")
+        ));
+        let lines = ["include missing.inc".to_string()];
+        assert!(assemble(&lines, Default::default()).is_err());
+    }
+
+    #[test]
+    fn conditional_assembly() {
+        let mut assembly = initialize_engine("./tests/conditional.txt");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.get_register_value(&RegisterName::AX) == 2);
+        assert!(assembly.get_register_value(&RegisterName::BX) == 5);
+
+        let mut assembly = initialize_engine("./tests/conditional.txt");
+        assembly.define("DEBUG", "1");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.get_register_value(&RegisterName::AX) == 1);
+        assert!(assembly.get_register_value(&RegisterName::BX) == 0);
+
+        use crate::assembler::assemble;
+        let lines = ["x equ 3", ".ERRE x LT 2, <x is too big>"].map(String::from);
+        assert!(matches!(
+            assemble(&lines, Default::default()),
+            Err(ErrorCode::SyntaxError { line: 2, message, .. }) if message == "x is too big"
+        ));
+        // `error` is a name unless it has the dot, or a `%` in NASM
+        let lines = ["error db, 'Oops$'", "lea DX, [error]"].map(String::from);
+        let program = assemble(&lines, Default::default()).unwrap();
+        assert!(program
+            .instructions
+            .iter()
+            .all(|instruction| instruction.error.is_none()));
+        use crate::lexer::{Dialect, LexerOptions};
+        let options = LexerOptions {
+            dialect: Dialect::Nasm,
+            ..LexerOptions::default()
+        };
+        let lines = ["%error 'Oops'"].map(String::from);
+        assert!(matches!(
+            assemble(&lines, options),
+            Err(ErrorCode::SyntaxError { line: 1, message, .. }) if message == "Oops"
+        ));
+        for lines in [
+            vec!["IF 1"],
+            vec!["ENDIF"],
+            vec!["IF 0", "ELSE", "ELSE", "ENDIF"],
+            vec!["IF nowhere", "ENDIF"],
+        ] {
+            let lines: Vec<String> = lines.into_iter().map(String::from).collect();
+            assert!(matches!(
+                assemble(&lines, Default::default()),
+                Err(ErrorCode::SyntaxError { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn program_structure() {
+        let mut assembly = initialize_engine("./tests/structure.txt");
+        let stdout = capture_output(&mut assembly, b"");
+        execute_engine(&mut assembly, false);
+        assert_eq!(stdout.0.borrow().as_slice(), b"Hi");
+        assert!(assembly.get_register_value(&RegisterName::BX) == 1);
+
+        let mut assembly = initialize_engine("./tests/textbook.txt");
+        let stdout = capture_output(&mut assembly, b"");
+        execute_engine(&mut assembly, false);
+        assert_eq!(stdout.0.borrow().as_slice(), b"Hello, World!\r\n");
+
+        let mut assembly = initialize_engine("./tests/segments.txt");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.get_register_value(&RegisterName::AX) == 0);
+        assert!(assembly.get_register_value(&RegisterName::CX) == 7);
+
+        let mut assembly = initialize_engine("./tests/stack_overflow.txt");
+        assert!(matches!(
+            assembly.execute(false),
+            Err(ErrorCode::StackOverflow)
+        ));
+
+        use crate::assembler::assemble;
+        for lines in [vec!["data segment"], vec!["data ends"], vec!["end start"]] {
+            let lines: Vec<String> = lines.into_iter().map(String::from).collect();
+            assert!(assemble(&lines, Default::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn structures() {
+        let mut assembly = initialize_engine("./tests/structs.txt");
+        execute_engine(&mut assembly, false);
+        #[rustfmt::skip]
+        verify_memory(&assembly, &[
+            0, 0, 0, 5, // origin
+            0, 3, 0, 4, // corner
+            0, 1, 0, 5, 0, 1, 0, 5, // pair
+            9, 0, 0, // last
+            7, 0, 16, // first
+        ], 22);
+        assert!(assembly.get_register_value(&RegisterName::AL) == 9);
+        assert!(assembly.get_register_value(&RegisterName::BX) == 16);
+        assert!(assembly.get_register_value(&RegisterName::CX) == 3);
+        assert!(assembly.get_register_value(&RegisterName::DX) == 2);
+        assert!(assembly.get_register_value(&RegisterName::SI) == 5);
+        assert!(assembly.get_register_value(&RegisterName::DI) == 8);
+
+        use crate::assembler::assemble;
+        for line in ["bad point <1, 2, 3>", "bad point 3", "mov AX, [BX].z"] {
+            let lines = ["point STRUC", "x dw, 0", "y dw, 0", "point ENDS", line].map(String::from);
+            let program = assemble(&lines, Default::default()).unwrap();
+            assert!(program.instructions[4].error.is_some(), "{line}");
+        }
+        let lines = ["point STRUC", "mov AX, 1", "point ENDS"].map(String::from);
+        assert!(assemble(&lines, Default::default()).is_err());
+    }
+
+    #[test]
+    fn labels() {
+        let mut assembly = initialize_engine("./tests/labels.txt");
+        execute_engine(&mut assembly, false);
+        verify_memory(&assembly, &[4, 5, 6, b'o', b'k', 0, 15], 7);
+        assert!(assembly.get_register_value(&RegisterName::CL) == 'o' as u32);
+        assert!(assembly.get_register_value(&RegisterName::DX) == 4);
+        assert!(assembly.get_register_value(&RegisterName::SI) == 6);
+        assert!(assembly.get_register_value(&RegisterName::DI) == 7);
+
+        use crate::assembler::is_valid_name;
+        for name in [
+            "loop2", "arr_1", "msg$", "@@next", ".loop", "?tmp", "_start",
+        ] {
+            assert!(is_valid_name(name), "{name}");
+        }
+        for name in ["2nd", "$", "?", "a-b", ""] {
+            assert!(!is_valid_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn procs() {
+        let mut assembly = initialize_engine("./tests/procs.txt");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.get_register_value(&RegisterName::DI) == 43);
+        assert!(assembly.get_register_value(&RegisterName::AL) == 42);
+        assert!(assembly.get_register_value(&RegisterName::DX) == 41);
+        // USES registers and BP are restored
+        assert!(assembly.get_register_value(&RegisterName::BX) == 100);
+        assert!(assembly.get_register_value(&RegisterName::CX) == 9);
+        assert!(assembly.get_register_value(&RegisterName::BP) == 7);
+
+        use crate::assembler::assemble;
+        for lines in [
+            &["f PROC", "ret"][..],
+            &["f PROC", "g PROC", "ret", "g ENDP", "f ENDP"],
+            &["f PROC", "ret", "g ENDP"],
+            &["ARG a:WORD"],
+            &["f PROC", "ARG a:QWORD", "ret", "f ENDP"],
+            &["f PROC", "LOCAL a, a", "ret", "f ENDP"],
+        ] {
+            let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+            assert!(assemble(&lines, Default::default()).is_err(), "{lines:?}");
+        }
+    }
+
+    #[test]
+    fn nasm_dialect() {
+        let mut assembly = initialize_engine("./tests/nasm.nasm");
+        execute_engine(&mut assembly, false);
+        #[rustfmt::skip]
+        verify_memory(&assembly, &[
+            0x12, 0x34, 0x56, 0x78, // buffer
+            b'H', b'i', 10, // msg
+            0, 16, 0, 16, 0, 16, // table
+            5, // flag
+        ], 14);
+        // Execution starts at _start
+        assert!(assembly.get_register_value(&RegisterName::EAX) == 42);
+        assert!(assembly.get_register_value(&RegisterName::EBX) == 4);
+        assert!(assembly.get_register_value(&RegisterName::ECX) == 3);
+        assert!(assembly.get_register_value(&RegisterName::DX) == 16);
+        assert!(assembly.get_register_value(&RegisterName::EDI) == 7);
+
+        // Both dialects decode to the same instructions
+        use crate::{
+            assembler::assemble,
+            lexer::{Dialect, LexerOptions},
+        };
+        // Memory without a size takes the register's
+        let nasm = [
+            "x: dd 5",
+            "y resb 4",
+            "mov dword [x], 1",
+            "mov eax, x",
+            "add al, [y + 1]",
+            "mov bx, [x + 2]",
+            "cmp [y], cx",
+        ]
+        .map(String::from);
+        let tasm = [
+            "x dd, 5",
+            "y db, 4 DUP(?)",
+            "mov DWORD PTR [x], 1",
+            "mov EAX, x",
+            "add AL, BYTE PTR [y + 1]",
+            "mov BX, WORD PTR [x + 2]",
+            "cmp WORD PTR [y], CX",
+        ]
+        .map(String::from);
+        let options = LexerOptions {
+            dialect: Dialect::Nasm,
+            ..LexerOptions::default()
+        };
+        let nasm = assemble(&nasm, options).unwrap();
+        let tasm = assemble(&tasm, LexerOptions::default()).unwrap();
+        for (nasm, tasm) in nasm.instructions.iter().zip(&tasm.instructions) {
+            assert!(nasm.error.is_none() && tasm.error.is_none());
+            assert_eq!(
+                (nasm.mnemonic, &nasm.operands),
+                (tasm.mnemonic, &tasm.operands)
+            );
+        }
+    }
+
+    #[test]
+    fn att_dialect() {
+        let mut assembly = initialize_engine("./tests/att.s");
+        execute_engine(&mut assembly, false);
+        #[rustfmt::skip]
+        verify_memory(&assembly, &[
+            0, 0, 0, 42, // total
+            0, 1, 0, 2, 0, 3, // table
+            b'H', b'i', 10, 0, // msg
+            0, 5, 0, 0, // buffer
+        ], 18);
+        assert!(assembly.get_register_value(&RegisterName::EAX) == 42);
+        assert!(assembly.get_register_value(&RegisterName::ECX) == 10);
+        assert!(assembly.get_register_value(&RegisterName::DX) == 3);
+        // The arguments are popped again
+        assert!(assembly.get_register_value(&RegisterName::SP) == 16384);
+
+        // Both dialects decode to the same instructions
+        use crate::{
+            assembler::assemble,
+            lexer::{Dialect, LexerOptions},
+        };
+        let att = [
+            "x: .long 5",
+            "movl $1, x",
+            "movl %esp, %ebp",
+            "movb -2(%ebp,%esi,4), %al",
+            "pushl $7",
+        ]
+        .map(String::from);
+        let tasm = [
+            "x dd, 5",
+            "mov DWORD PTR [x], 1",
+            "mov BP, SP",
+            "mov AL, BYTE PTR [BP + ESI*4-2]",
+            "push DWORD 7",
+        ]
+        .map(String::from);
+        let options = LexerOptions {
+            dialect: Dialect::Att,
+            ..LexerOptions::default()
+        };
+        let att = assemble(&att, options).unwrap();
+        let tasm = assemble(&tasm, LexerOptions::default()).unwrap();
+        for (att, tasm) in att.instructions.iter().zip(&tasm.instructions) {
+            assert!(att.error.is_none() && tasm.error.is_none());
+            assert_eq!(
+                (att.mnemonic, &att.operands),
+                (tasm.mnemonic, &tasm.operands)
+            );
+        }
+    }
+
+    #[test]
+    fn large_si() {
+        let mut assembly = initialize_engine("./tests/large_si.txt");
+        execute_engine(&mut assembly, false);
+        assert!(assembly.get_register_value(&RegisterName::SP) == 5);
+        assert!(assembly.get_register_value(&RegisterName::BX) == 1);
+    }
+
+    #[test]
+    fn formatter() {
+        use crate::{
+            assembler::assemble,
+            formatter::format_source,
+            lexer::{Dialect, LexerOptions},
+        };
+        let source = "\
+x dw,5
+; counts down
+ start:   MOV cx,[x]  ;from x
+  again:dec Cx
+\tjnz again ; loop
+
+count PROC
+LOCAL n:WORD
+  mov  ax , bx
+    ret
+count  ENDP
+";
+        let formatted = "\
+x       dw, 5
+; counts down
+start: mov     CX, [x] ;from x
+again: dec     CX
+    jnz     again      ; loop
+
+count   PROC
+    LOCAL   n:WORD
+    mov     AX, BX
+    ret
+count   ENDP
+";
+        assert_eq!(format_source(source, LexerOptions::default()), formatted);
+
+        // Formatting keeps the program the same, apart from fixing the case of registers, and a
+        // formatted file stays as it is
+        let files = std::fs::read_dir("./examples")
+            .unwrap()
+            .chain(std::fs::read_dir("./tests").unwrap());
+        for path in files
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+        {
+            let text = std::fs::read_to_string(&path).unwrap();
+            let options = LexerOptions {
+                dialect: Dialect::from_path(path.to_str().unwrap()),
+                ..LexerOptions::default()
+            };
+            let formatted = format_source(&text, options);
+            assert_eq!(format_source(&formatted, options), formatted, "{path:?}");
+            let lines = |text: &str| text.lines().map(String::from).collect::<Vec<_>>();
+            assert_eq!(lines(&text).len(), lines(&formatted).len());
+            match (
+                assemble(&lines(&text), options),
+                assemble(&lines(&formatted), options),
+            ) {
+                (Ok(before), Ok(after)) => {
+                    for (before, after) in before
+                        .instructions
+                        .iter()
+                        .zip(&after.instructions)
+                        .filter(|(before, _)| before.error.is_none())
+                    {
+                        assert_eq!(
+                            (before.mnemonic, &before.operands),
+                            (after.mnemonic, &after.operands),
+                            "{path:?}"
+                        );
+                    }
+                }
+                (before, after) => assert_eq!(before.is_ok(), after.is_ok(), "{path:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn check() {
+        use crate::diagnostics::Severity::{Error, Warning};
+        let assembly = initialize_engine("./tests/check.txt");
+        let diagnostics = assembly.check();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.severity,
+                    diagnostic.line,
+                    diagnostic.column,
+                    diagnostic.suggestion.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Error, 3, 1, Some("mov")),
+                (Error, 4, 9, Some("EAX")),
+                (Warning, 5, 1, None),
+                (Error, 6, 5, Some("start")),
+                (Error, 7, 5, Some("AX")),
+                (Warning, 8, 1, None),
+            ]
+        );
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "./tests/check.txt:4:9: error: Invalid Value: Parameter EXA could not be parsed.\n    mov EXA, [x]\n        ^^^\nhelp: did you mean `EAX`?"
+        );
+
+        // Errors that stop the program from loading point at their line too
+        use crate::{diagnostics::check, lexer::LexerOptions, preprocessor::PreprocessOptions};
+        let lines = ["mov AX, 1", "p PROC", "ret"].map(String::from);
+        let diagnostics = check(
+            &lines,
+            LexerOptions::default(),
+            &PreprocessOptions::default(),
+        );
+        assert_eq!(
+            (
+                diagnostics.len(),
+                diagnostics[0].line,
+                diagnostics[0].message.as_str()
+            ),
+            (1, 2, "Invalid Opcode: Proc p has no ENDP")
+        );
+
+        // and the lines after them are still checked
+        let lines = [
+            "twice MACRO a, b",
+            "add a, b",
+            "ENDM",
+            "twice AX, BX, CX",
+            "p PROC",
+            "mov EXA, 5",
+            "mvo AX, 1",
+            "ret",
+        ]
+        .map(String::from);
+        let diagnostics = check(
+            &lines,
+            LexerOptions::default(),
+            &PreprocessOptions::default(),
+        );
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (4, "twice takes 2 arguments but was given 3"),
+                (5, "Invalid Opcode: Proc p has no ENDP"),
+                (6, "Invalid Value: Parameter EXA could not be parsed."),
+                (
+                    7,
+                    "Invalid Opcode: Unknown instruction: [\"mvo\", \"AX\", \"1\"]"
+                ),
+            ]
+        );
+    }
 }
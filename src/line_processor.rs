@@ -3,51 +3,26 @@ use crate::register::Register;
 pub struct LineProcessor {
     lines: Vec<String>,
     ip: usize,
+    current_ip: usize, // Index of the line last fetched
 }
 
 impl LineProcessor {
     pub fn new(lines: Vec<String>) -> Self {
-        LineProcessor { lines, ip: 0 }
+        LineProcessor {
+            lines,
+            ip: 0,
+            current_ip: 0,
+        }
     }
 
-    pub fn next_line(&mut self) -> Option<Vec<String>> {
-        while self.ip < self.lines.len() {
-            let line = self.lines[self.ip].clone();
-            let whole_line = line.trim();
-
-            // Split by ";" to remove comments and take the first part
-            let without_comment = whole_line.split(';').next().unwrap_or("").trim();
-
-            if without_comment.is_empty() {
-                self.ip += 1;
-                continue;
-            }
-
-            let instruction = without_comment
-                .split_whitespace()
-                .next()
-                .unwrap_or("NOP")
-                .to_string();
-
-            if instruction == "NOP" {
-                self.ip += 1;
-                continue;
-            }
-
-            let operands: Vec<String> = without_comment[instruction.len()..]
-                .split(',')
-                .map(str::trim)
-                .map(String::from)
-                .collect();
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
 
-            self.ip += 1;
-            let mut parts = vec![instruction];
-            if operands[0] != *"" {
-                parts.extend(operands);
-            }
-            return Some(parts);
-        }
-        None
+    // Marks `line` as executing and moves IP past it
+    pub fn fetch(&mut self, line: usize) {
+        self.current_ip = line;
+        self.ip = line + 1;
     }
 
     pub fn set_ip(&mut self, value: usize) {
@@ -56,15 +31,10 @@ impl LineProcessor {
     pub fn get_ip(&self) -> usize {
         self.ip
     }
+    pub fn get_current_ip(&self) -> usize {
+        self.current_ip
+    }
     pub fn update_ip_register(&mut self, ip_register: &mut Register) {
         ip_register.load_word(self.ip as u16);
     }
-
-    pub fn peak(&mut self) -> Option<(Vec<String>, usize)> {
-        let prev_ip = self.ip; // Capture current instruction pointer
-        let res = self.next_line(); // Get the next instruction
-        let next_ip = self.ip; // Capture the updated instruction pointer
-        self.set_ip(prev_ip); // Restore the instruction pointer to its original state
-        res.map(|line| (line, next_ip)) // Return the result along with the updated instruction pointer
-    }
 }
@@ -2,15 +2,94 @@
 
 use assembly::io;
 
-use assembly::{execute_engine, initialize_engine};
+use assembly::{
+    diagnostics::Severity,
+    execute_engine,
+    formatter::format_source,
+    initialize_engine,
+    lexer::{Dialect, LexerOptions},
+    serial::Serial,
+    Engine,
+};
 
 fn main() -> io::Result<()> {
-    let mut args = std::env::args();
+    let mut args = std::env::args().peekable();
     args.next().unwrap();
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        return format_files(args);
+    }
+    // assembly check FILE: report every problem instead of running the program
+    let check = args.next_if(|arg| arg == "check").is_some();
+
+    let mut positional: Vec<String> = Vec::new();
+    let mut timer_period: Option<usize> = None;
+    let mut exception_mode = false;
+    let mut escapes = false;
+    let mut file_root: Option<String> = None;
+    let mut disk_image: Option<String> = None;
+    let mut boot = false;
+    let mut vga_output: Option<String> = None;
+    let mut vga_every: Option<usize> = None;
+    let mut speaker_output: Option<String> = None;
+    let mut serial_bridge: Option<String> = None;
+    let mut include_paths: Vec<String> = Vec::new();
+    let mut defines: Vec<String> = Vec::new();
+    let mut dialect: Option<Dialect> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // --timer N: raise IRQ0 every N executed instructions
+            "--timer" => {
+                timer_period = args.next().and_then(|value| value.parse().ok());
+                if timer_period.is_none() {
+                    println!("--timer expects a number of instructions, ignoring it");
+                }
+            }
+            // --exceptions: vector CPU faults to the program's handlers
+            "--exceptions" => exception_mode = true,
+            // --escapes: resolve \n, \t, \xHH and friends in string literals
+            "--escapes" => escapes = true,
+            // --root DIR: directory the int 21h file services are confined to
+            "--root" => file_root = args.next(),
+            // --disk IMAGE: serve int 13h from a 1.44 MB floppy image
+            "--disk" => disk_image = args.next(),
+            // --boot: copy the image's boot sector to 0000:7C00 before running the source file.
+            // The sector's machine code is not executed.
+            "--boot" => boot = true,
+            // --vga-out PATH: write the mode 13h screen to PATH (.png or .ppm) at exit
+            "--vga-out" => vga_output = args.next(),
+            // --vga-every N: also write PATH_<frame> every N frames
+            "--vga-every" => {
+                vga_every = args.next().and_then(|value| value.parse().ok());
+                if vga_every.is_none() {
+                    println!("--vga-every expects a number of frames, ignoring it");
+                }
+            }
+            // --speaker-out PATH: render the PC speaker to a WAV file at exit
+            "--speaker-out" => speaker_output = args.next(),
+            // --serial stdio|file:OUT[:IN]|unix:SOCKET: bridge COM1 (3F8h)
+            "--serial" => serial_bridge = args.next(),
+            // --syntax tasm|nasm|att: the dialect, when the file extension doesn't say
+            // (.nasm is NASM and .s AT&T)
+            "--syntax" => {
+                dialect = args.next().as_deref().and_then(Dialect::parse);
+                if dialect.is_none() {
+                    println!("--syntax expects tasm, nasm or att, ignoring it");
+                }
+            }
+            // -I DIR or -IDIR: search DIR for INCLUDE files
+            "-I" => include_paths.extend(args.next()),
+            _ if arg.starts_with("-I") => include_paths.push(arg[2..].to_string()),
+            // -D NAME=value or -DNAME=value: define NAME before the first line, as 1 without a value
+            "-D" => defines.extend(args.next()),
+            _ if arg.starts_with("-D") => defines.push(arg[2..].to_string()),
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
     // Initialize the engine
 
     // Arg 1 is file_path
-    let file_path = match args.next() {
+    let file_path = match positional.next() {
         Some(file_path) => file_path,
         None => {
             // Not sure what to do here and if it was intended but now I guess there is a spot to write any warnings you`d like
@@ -20,16 +99,135 @@ fn main() -> io::Result<()> {
     };
 
     // Arg 2 is debug
-    let debug = match args.next() {
+    let debug = match positional.next() {
         Some(input) => ["true", "t"].contains(&input.to_lowercase().as_str()),
         None => false,
     };
 
     let mut engine = initialize_engine(&file_path);
+    engine.set_exception_mode(exception_mode);
+    engine.set_escapes(escapes);
+    if let Some(dialect) = dialect {
+        engine.set_dialect(dialect);
+    }
+    for directory in &include_paths {
+        engine.add_include_path(directory);
+    }
+    for define in &defines {
+        let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+        engine.define(name, value);
+    }
+    if check {
+        return check_file(&engine);
+    }
+    if let Some(root) = file_root {
+        engine.set_file_root(&root)?;
+    }
+    if let Some(image) = disk_image {
+        engine.attach_disk(&image)?;
+    }
+    if boot {
+        if let Err(error) = engine.load_boot_sector() {
+            panic!("Could not load the boot sector.\n{}", error);
+        }
+    }
+    if let Some(path) = vga_output {
+        engine.set_vga_output(&path, vga_every);
+    }
+    if let Some(spec) = serial_bridge {
+        engine.attach_serial(Serial::open(&spec)?);
+    }
+    if let Some(path) = speaker_output {
+        engine.set_speaker_output(&path);
+    }
+    if let Some(period) = timer_period {
+        engine.set_timer(period);
+    }
     execute_engine(&mut engine, debug);
     if !debug {
         // already printing every time.
         println!("{}", engine);
     }
+    // Programs that end through an exit service report their status to the host
+    if let Some(code) = engine.exit_code {
+        std::process::exit(code as i32);
+    }
+    Ok(())
+}
+
+fn check_file(engine: &Engine) -> io::Result<()> {
+    let diagnostics = engine.check();
+    for diagnostic in &diagnostics {
+        println!("{diagnostic}\n");
+    }
+    let count = |severity: Severity| {
+        diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    };
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    println!(
+        "{errors} error{}, {warnings} warning{}",
+        plural(errors),
+        plural(warnings)
+    );
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// assembly fmt [--check|--write] [--syntax tasm|nasm|att] FILE...: prints the files in the
+// canonical layout, rewrites them with --write, or lists the ones that differ with --check
+fn format_files(args: impl Iterator<Item = String>) -> io::Result<()> {
+    let mut args = args;
+    let mut check = false;
+    let mut write = false;
+    let mut dialect: Option<Dialect> = None;
+    let mut files: Vec<String> = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--write" | "-w" => write = true,
+            "--syntax" => {
+                dialect = args.next().as_deref().and_then(Dialect::parse);
+                if dialect.is_none() {
+                    println!("--syntax expects tasm, nasm or att, ignoring it");
+                }
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        println!("Usage: assembly fmt [--check|--write] [--syntax tasm|nasm|att] FILE...");
+        std::process::exit(2);
+    }
+
+    let mut unformatted = false;
+    for file in &files {
+        let text = std::fs::read_to_string(file)?;
+        let options = LexerOptions {
+            dialect: dialect.unwrap_or_else(|| Dialect::from_path(file)),
+            ..LexerOptions::default()
+        };
+        let formatted = format_source(&text, options);
+        if check {
+            if formatted != text {
+                println!("{file}");
+                unformatted = true;
+            }
+        } else if write {
+            if formatted != text {
+                std::fs::write(file, formatted)?;
+            }
+        } else {
+            print!("{formatted}");
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
     Ok(())
 }
@@ -1,19 +1,21 @@
 use crate::{
+    assembler::is_valid_name,
     error_code::ErrorCode,
+    interrupt::{IVT_ENTRY_SIZE, IVT_SIZE},
     register::{get_register_size, Register, RegisterName},
-    utils::parse_string_to_usize,
     variable_metadata::{VariableMetadata, VariableSize},
+    vga::{FRAMEBUFFER_ADDRESS, FRAMEBUFFER_SIZE},
 };
 
-use regex::Regex;
 use std::collections::HashMap;
 
 pub struct MemoryManager {
     memory: Vec<u8>,
     pub variable_pointers: HashMap<String, VariableMetadata>,
-    pub labels: HashMap<String, usize>,
-    pub procs: HashMap<String, (usize, usize)>,
+    pub program_break: usize, // End of the heap, moved by brk
     segments: [usize; 3],
+    framebuffer: Option<Vec<u8>>, // Mode 13h video memory, mapped at A000:0000
+    alignment: usize,             // Set by align and even, applies to the next variable stored
 }
 
 impl MemoryManager {
@@ -21,9 +23,71 @@ impl MemoryManager {
         MemoryManager {
             memory: vec![0; size],
             variable_pointers: HashMap::new(),
-            labels: HashMap::new(),
-            procs: HashMap::new(),
+            program_break: seg[1],
             segments: seg,
+            framebuffer: None,
+            alignment: 1,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    // Grows memory to `size` bytes, keeping the stack segment at the top.
+    // Only valid before anything has been pushed.
+    pub fn grow(&mut self, size: usize) {
+        if size > self.memory.len() {
+            let stack_size = self.memory.len() - self.segments[2];
+            self.memory.resize(size, 0);
+            self.segments[2] = size - stack_size;
+        }
+    }
+
+    // Sizes the stack at the top of memory. Only valid before anything has been pushed.
+    pub fn set_stack_size(&mut self, size: usize) -> Result<(), ErrorCode> {
+        if size > self.memory.len() - self.segments[1] {
+            return Err(ErrorCode::NotEnoughSpace(format!(
+                "A stack of {size} bytes doesn't fit in memory"
+            )));
+        }
+        self.segments[2] = self.memory.len() - size;
+        Ok(())
+    }
+
+    // Maps a cleared mode 13h framebuffer at linear address A0000h.
+    pub fn map_framebuffer(&mut self) {
+        self.framebuffer = Some(vec![0; FRAMEBUFFER_SIZE]);
+    }
+
+    pub fn unmap_framebuffer(&mut self) {
+        self.framebuffer = None;
+    }
+
+    pub fn framebuffer(&self) -> Option<&[u8]> {
+        self.framebuffer.as_deref()
+    }
+
+    // Offset into the framebuffer when `address` falls inside it.
+    fn framebuffer_offset(&self, address: usize) -> Option<usize> {
+        let framebuffer = self.framebuffer.as_ref()?;
+        address
+            .checked_sub(FRAMEBUFFER_ADDRESS)
+            .filter(|offset| *offset < framebuffer.len())
+    }
+
+    // Byte access for addresses already validated by check_memory_address.
+    fn read(&self, address: usize) -> u8 {
+        match (self.framebuffer_offset(address), &self.framebuffer) {
+            (Some(offset), Some(framebuffer)) => framebuffer[offset],
+            _ => self.memory[address],
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match (self.framebuffer_offset(address), &mut self.framebuffer) {
+            (Some(offset), Some(framebuffer)) => framebuffer[offset] = value,
+            _ => self.memory[address] = value,
         }
     }
 
@@ -32,7 +96,7 @@ impl MemoryManager {
     }
 
     pub fn check_memory_address(&self, mem_address: usize) -> Result<(), ErrorCode> {
-        if mem_address >= self.memory.len() {
+        if mem_address >= self.memory.len() && self.framebuffer_offset(mem_address).is_none() {
             Err(ErrorCode::InvalidPointer(format!(
                 "{} is not a valid memory address",
                 mem_address
@@ -42,12 +106,22 @@ impl MemoryManager {
         }
     }
 
+    // Every byte of `start..start + len`, so a buffer can't run from memory into the framebuffer
+    pub fn check_memory_range(&self, start: usize, len: usize) -> Result<(), ErrorCode> {
+        (start..start.saturating_add(len))
+            .try_for_each(|address| self.check_memory_address(address))
+    }
+
     pub fn push_to_stack(
         &mut self,
         value: u32,
         size: VariableSize,
         si_register: &mut Register,
     ) -> Result<(), ErrorCode> {
+        let depth = si_register.get_word() as usize + size.value();
+        if depth > self.memory.len() - self.segments[2] {
+            return Err(ErrorCode::StackOverflow);
+        }
         match size {
             VariableSize::Byte => {
                 let si = si_register.get_word() as usize;
@@ -94,26 +168,29 @@ impl MemoryManager {
         };
         Ok(result)
     }
-    pub fn save_label(&mut self, name: String, ip: usize) -> Result<(), ErrorCode> {
-        let name_copy = name.clone();
-        if self.labels.insert(name, ip).is_some() {
-            Err(ErrorCode::LabelAlreadyExists(name_copy))
-        } else {
-            Ok(())
-        }
+    // The address of the top of the stack, which grows down from the end of memory.
+    // Fails when SI holds more than the memory, as it does when a program uses it for data.
+    pub fn stack_pointer(&self, si_register: &Register) -> Result<usize, ErrorCode> {
+        self.memory
+            .len()
+            .checked_sub(si_register.get_word() as usize)
+            .ok_or(ErrorCode::StackOverflow)
     }
-    pub fn save_proc(
-        &mut self,
-        name: String,
-        start_ip: usize,
-        end_ip: usize,
+
+    // Moves the top of the stack to `address`, as `enter`, `leave` and AT&T writes to SP do
+    pub fn set_stack_pointer(
+        &self,
+        address: usize,
+        si_register: &mut Register,
     ) -> Result<(), ErrorCode> {
-        let name_copy = name.clone();
-        if self.procs.insert(name, (start_ip, end_ip)).is_some() {
-            Err(ErrorCode::LabelAlreadyExists(name_copy))
-        } else {
-            Ok(())
-        }
+        let depth = self
+            .memory
+            .len()
+            .checked_sub(address)
+            .filter(|depth| *depth <= self.memory.len() - self.segments[2])
+            .ok_or(ErrorCode::StackOverflow)?;
+        si_register.load_word(depth as u16);
+        Ok(())
     }
     pub fn save_variable(
         &mut self,
@@ -125,73 +202,103 @@ impl MemoryManager {
 
         let length = data.len() * multiplier;
 
-        if self.variable_pointers.contains_key(&variable_name) {
-            return Err(ErrorCode::LabelAlreadyExists(variable_name));
-        }
-        if let Ok(location) = self.find_free_block(length) {
-            // Save the metadata with the correct start_index
-            println!("[SAVED] Saved variable {} @ {}\n", variable_name, location);
-            self.variable_pointers
-                .insert(variable_name, VariableMetadata::new(location, length, size));
-
-            // Copy data to the found location
-            for (i, &byte) in data.iter().enumerate() {
-                match size {
-                    VariableSize::Byte => self.memory[location + i] = byte as u8,
-                    VariableSize::Word => {
-                        self.memory[location + i * multiplier] = (byte >> 8) as u8;
-                        self.memory[location + i * multiplier + 1] = (byte & 0x00FF) as u8;
-                    }
-                    VariableSize::DoubleWord => {
-                        self.memory[location + i * multiplier] = (byte >> 24) as u8;
-                        self.memory[location + i * multiplier + 1] =
-                            ((byte & 0x00FF0000) >> 16) as u8;
-                        self.memory[location + i * multiplier + 2] =
-                            ((byte & 0x0000FF00) >> 8) as u8;
-                        self.memory[location + i * multiplier + 3] = byte as u8;
-                    }
+        let location =
+            self.reserve_variable(variable_name, VariableMetadata::new(0, length, size))?;
+
+        // Copy data to the found location
+        for (i, &byte) in data.iter().enumerate() {
+            match size {
+                VariableSize::Byte => self.memory[location + i] = byte as u8,
+                VariableSize::Word => {
+                    self.memory[location + i * multiplier] = (byte >> 8) as u8;
+                    self.memory[location + i * multiplier + 1] = (byte & 0x00FF) as u8;
+                }
+                VariableSize::DoubleWord => {
+                    self.memory[location + i * multiplier] = (byte >> 24) as u8;
+                    self.memory[location + i * multiplier + 1] = ((byte & 0x00FF0000) >> 16) as u8;
+                    self.memory[location + i * multiplier + 2] = ((byte & 0x0000FF00) >> 8) as u8;
+                    self.memory[location + i * multiplier + 3] = byte as u8;
                 }
             }
+        }
 
-            Ok(())
-        } else {
-            Err(ErrorCode::NotEnoughSpace(format!(
-                "Not enough contiguous free memory to store variable of length {}",
-                length
-            )))
+        Ok(())
+    }
+
+    // Finds room for a variable laid out as `layout`, whose start_index is replaced by its address.
+    // Nothing is stored yet.
+    pub fn reserve_variable(
+        &mut self,
+        variable_name: String,
+        layout: VariableMetadata,
+    ) -> Result<usize, ErrorCode> {
+        if self.variable_pointers.contains_key(&variable_name) {
+            return Err(ErrorCode::LabelAlreadyExists(variable_name));
         }
+        let alignment = std::mem::replace(&mut self.alignment, 1);
+        let location = self.find_free_block(layout.length, alignment)?;
+        // Save the metadata with the correct start_index
+        self.variable_pointers.insert(
+            variable_name,
+            VariableMetadata {
+                start_index: location,
+                ..layout
+            },
+        );
+        Ok(location)
+    }
+
+    // Makes the next variable start at a multiple of `alignment`
+    pub fn align_next(&mut self, alignment: usize) {
+        self.alignment = self.alignment.max(alignment);
     }
 
     fn get_code_segment_displacement(&self) -> usize {
         self.segments[1]
     }
 
-    pub fn find_free_block(&mut self, length: usize) -> Result<usize, ErrorCode> {
-        let mut start_index = 0;
-
-        // If there's no variables yet, return the first available space
-        if self.variable_pointers.is_empty() {
-            return Ok(0);
-        }
+    // Source lines aren't stored in memory, so the code segment is free to serve as the heap.
+    pub fn heap_bounds(&self) -> (usize, usize) {
+        (self.segments[1], self.segments[2])
+    }
 
-        // Iterate over the variable pointers hashmap
-        let mut entries: Vec<_> = self.variable_pointers.iter().collect();
-        // Sort to guarantee consistent results
-        entries.sort_by_key(|(_, metadata)| metadata.start_index);
+    pub fn get_code_segment(&self) -> u16 {
+        (self.segments[1] >> 4) as u16
+    }
 
-        for (_, metadata) in entries {
-            // Check if there's enough contiguous free memory between allocated blocks
+    // Moves the start of the data segment past the interrupt vector table,
+    // so variables never overlap the vectors a program installs.
+    pub fn reserve_ivt(&mut self) {
+        if self.segments[0] < IVT_SIZE {
+            self.segments[0] = IVT_SIZE;
+        }
+    }
 
-            let end_index = metadata.start_index + metadata.length;
+    pub fn get_interrupt_vector(&self, vector: u8) -> Result<(u16, u16), ErrorCode> {
+        let entry = vector as usize * IVT_ENTRY_SIZE;
+        Ok((self.get_word(entry)?, self.get_word(entry + 2)?))
+    }
 
-            // Check if there's enough free space between end of previous block and start of current block
-            if start_index + length <= metadata.start_index {
-                return Ok(start_index);
-            }
+    pub fn set_interrupt_vector(
+        &mut self,
+        vector: u8,
+        offset: u16,
+        segment: u16,
+    ) -> Result<(), ErrorCode> {
+        let entry = vector as usize * IVT_ENTRY_SIZE;
+        self.set_word(entry, offset)?;
+        self.set_word(entry + 2, segment)
+    }
 
-            start_index = end_index; // Move start_index to end of current block
-            self.check_memory_address(start_index)?
-        }
+    // Variables are never freed, so each one goes after the last, the way the assembler counts `$`
+    pub fn find_free_block(&mut self, length: usize, alignment: usize) -> Result<usize, ErrorCode> {
+        let start_index = self
+            .variable_pointers
+            .values()
+            .map(|metadata| metadata.start_index + metadata.length)
+            .max()
+            .unwrap_or(self.segments[0])
+            .next_multiple_of(alignment.max(1));
 
         if start_index + length < self.get_code_segment_displacement() {
             return Ok(start_index);
@@ -207,156 +314,20 @@ impl MemoryManager {
     }
 
     pub fn is_valid_variable_name(&self, text: &str) -> bool {
-        let variable_pattern = Regex::new(r"^([a-zA-Z_]+)$").unwrap();
-        if let Some(captures) = variable_pattern.captures(text) {
-            if captures.get(1).is_some() {
-                return true;
-            }
-        }
-        false
-    }
-
-    pub fn is_memory_operand(&self, operand: &str) -> bool {
-        operand.starts_with('[') && operand.ends_with(']')
-    }
-
-    /*
-    Effective Address calculation follows this format:
-    Effective Address = Base + (Index * Scale) + Displacement
-    */
-    pub fn calculate_effective_address(
-        &self,
-        mem_operand: &str,
-        registers: &[Register; 10],
-        label_vars: bool,
-    ) -> Result<usize, ErrorCode> {
-        // Ensure the memory operand is valid and remove the square brackets
-        if !self.is_memory_operand(mem_operand) {
-            return Err(ErrorCode::InvalidPointer(
-                "Memory Operand must be enveloped in []".to_string(),
-            ));
-        }
-        let addr_expression = &mem_operand[1..mem_operand.len() - 1];
-
-        let mut effective_address = 0;
-
-        // Split the address expression into parts and process each part
-        for part in addr_expression.split(|c| c == '+' || c == '-') {
-            let part = part.trim();
-
-            // Determine if the part is negative based on the context
-            let is_negative = addr_expression
-                .chars()
-                .nth(addr_expression.find(part).unwrap().saturating_sub(1))
-                == Some('-');
-
-            // Process parts containing multiplication (index * scale)
-            if part.contains('*') {
-                let mut components = part.split('*').map(|s| s.trim());
-                let index_part = components.next().ok_or(ErrorCode::InvalidPointer(
-                    "Invalid Addressing Mode.".to_string(),
-                ))?;
-                let scale_part = components.next().ok_or(ErrorCode::InvalidPointer(
-                    "Invalid Addressing Mode.".to_string(),
-                ))?;
-
-                // Get the index value from registers or as a direct value
-                let index_value =
-                    if let Ok(register_name) = RegisterName::from_str_to_reg_name(index_part) {
-                        get_register_value(registers, &register_name) as usize
-                    } else {
-                        parse_string_to_usize(index_part)
-                            .ok_or(ErrorCode::InvalidRegister(index_part.to_string()))?
-                            as usize
-                    };
-
-                // Parse the scale value
-                let scale_value = parse_string_to_usize(scale_part).ok_or(
-                    ErrorCode::InvalidValue(format!("Invalid scale factor: {}", scale_part)),
-                )? as usize;
-
-                // Adjust the effective address based on the scale and sign
-                effective_address += if is_negative {
-                    -((index_value * scale_value) as isize)
-                } else {
-                    (index_value * scale_value) as isize
-                };
-
-            // Handle displacement, hexadecimal values, or variable names
-            } else if let Some(value) = self.parse_value(part, is_negative, registers, label_vars) {
-                effective_address += value;
-            } else {
-                return Err(ErrorCode::InvalidRegister(part.to_string()));
-            }
-        }
-
-        Ok(effective_address as usize)
-    }
-
-    pub fn parse_value(
-        &self,
-        part: &str,
-        is_negative: bool,
-        registers: &[Register; 10],
-        label_vars: bool,
-    ) -> Option<isize> {
-        // Parse the part as a usize if possible
-        if let Some(value) = parse_string_to_usize(part) {
-            Some(if is_negative {
-                -(value as isize)
-            } else {
-                value as isize
-            })
-
-        // Handle variable pointers based on label_vars flag
-        } else if let Some(var_metadata) = self.variable_pointers.get(part) {
-            if label_vars {
-                Some(if is_negative {
-                    -(var_metadata.start_index as isize)
-                } else {
-                    var_metadata.start_index as isize
-                })
-            } else {
-                let value = match var_metadata.length {
-                    1 => self.memory[var_metadata.start_index] as u16,
-                    2 => {
-                        (self.memory[var_metadata.start_index] as u16) << 8
-                            | self.memory[var_metadata.start_index + 1] as u16
-                    }
-                    _ => return None, // Handle unsupported lengths
-                };
-                Some(if is_negative {
-                    -(value as isize)
-                } else {
-                    value as isize
-                })
-            }
-
-        // Handle register values based on is_negative flag
-        } else if let Ok(register_name) = RegisterName::from_str_to_reg_name(part) {
-            Some(if is_negative {
-                -(get_register_value(registers, &register_name) as isize)
-            } else {
-                get_register_value(registers, &register_name) as isize
-            })
-
-        // Handle labels directly
-        } else {
-            self.labels.get(part).map(|v| *v as isize)
-        }
+        is_valid_name(text)
     }
 
     pub fn set_byte(&mut self, index: usize, value: u8) -> Result<(), ErrorCode> {
         self.check_memory_address(index)?;
-        self.memory[index] = value;
+        self.write(index, value);
         Ok(())
     }
 
     pub fn set_word(&mut self, index: usize, value: u16) -> Result<(), ErrorCode> {
         self.check_memory_address(index)?; // Lower Bound
         self.check_memory_address(index + 1)?; // Upper Bound
-        self.memory[index] = (value >> 8) as u8;
-        self.memory[index + 1] = (value & 0x00FF) as u8;
+        self.write(index, (value >> 8) as u8);
+        self.write(index + 1, (value & 0x00FF) as u8);
         Ok(())
     }
 
@@ -364,10 +335,10 @@ impl MemoryManager {
         self.check_memory_address(index)?; // Lower bound
         self.check_memory_address(index + 3)?; // Upper Bound
                                                // This is simpler.
-        self.memory[index] = ((value & 0xFF000000) >> 24) as u8;
-        self.memory[index + 1] = ((value & 0x00FF0000) >> 16) as u8;
-        self.memory[index + 2] = ((value & 0x0000FF00) >> 8) as u8;
-        self.memory[index + 3] = (value & 0x000000FF) as u8;
+        self.write(index, ((value & 0xFF000000) >> 24) as u8);
+        self.write(index + 1, ((value & 0x00FF0000) >> 16) as u8);
+        self.write(index + 2, ((value & 0x0000FF00) >> 8) as u8);
+        self.write(index + 3, (value & 0x000000FF) as u8);
 
         // for i in 0..4 {
         //     let mask = 0xFF000000 >> (i * 8); // Make mask for the byte we want
@@ -380,23 +351,23 @@ impl MemoryManager {
     }
     pub fn get_byte(&self, index: usize) -> Result<u8, ErrorCode> {
         self.check_memory_address(index)?;
-        Ok(self.memory[index])
+        Ok(self.read(index))
     }
 
     pub fn get_word(&self, index: usize) -> Result<u16, ErrorCode> {
         self.check_memory_address(index)?;
         self.check_memory_address(index + 1)?;
-        Ok((self.memory[index] as u16) << 8 | self.memory[index + 1] as u16)
+        Ok((self.read(index) as u16) << 8 | self.read(index + 1) as u16)
     }
 
     pub fn get_dword(&self, index: usize) -> Result<u32, ErrorCode> {
         for i in 0..4 {
             self.check_memory_address(index + i)?;
         }
-        Ok((self.memory[index] as u32) << 24
-            | (self.memory[index + 1] as u32) << 16
-            | (self.memory[index + 2] as u32) << 8
-            | (self.memory[index + 3] as u32))
+        Ok((self.read(index) as u32) << 24
+            | (self.read(index + 1) as u32) << 16
+            | (self.read(index + 2) as u32) << 8
+            | (self.read(index + 3) as u32))
     }
     pub fn _get_memory(&self, start_index: usize, amount: usize) -> Vec<u8> {
         self.memory[start_index..amount + start_index].to_vec()
@@ -0,0 +1,123 @@
+//! The NASM dialect. Each line is rewritten into the TASM syntax the rest of the assembler
+//! reads, so both dialects produce the same program:
+//!
+//! * `section .text` and `section .data` (or `segment`) become `.code` and `.data`, and
+//!   `bits`, `cpu` and `default` lines are dropped.
+//! * `msg db "hi", 0` and `msg: db ...` become `msg db, "hi", 0`.
+//! * `buf resb 16` becomes `buf db, 16 DUP(?)`, and `row times 4 dw 1` becomes `row dw, 4 DUP(1)`.
+//! * `dword [x]` becomes `DWORD PTR [x]`, and registers are written in capitals.
+//!
+//! A bare variable name is already its address, as in NASM, and memory without a size is as
+//! wide as the register next to it. `%define` and `.local` labels are handled for both
+//! dialects.
+
+use crate::{
+    lexer::{tokenize, LexerOptions, Token, TokenKind},
+    register::RegisterName,
+};
+
+const DEFINES: [&str; 5] = ["db", "dw", "dd", "dq", "dt"];
+const RESERVES: [(&str, &str); 5] = [
+    ("resb", "db"),
+    ("resw", "dw"),
+    ("resd", "dd"),
+    ("resq", "dq"),
+    ("rest", "dt"),
+];
+const SIZES: [&str; 3] = ["byte", "word", "dword"];
+// Registers the simulator names that NASM doesn't, and so are free as variable names
+pub(crate) const INTERNAL_REGISTERS: [&str; 2] = ["ip", "flag"];
+
+pub fn translate(text: &str, options: LexerOptions) -> String {
+    let Ok(tokens) = tokenize(text, 0, options) else {
+        return text.to_string();
+    };
+    let words: Vec<String> = tokens
+        .iter()
+        .map(|token| text[token.start..token.end].to_lowercase())
+        .collect();
+    let first = |index: usize| words.get(index).map_or("", String::as_str);
+    let section = match (first(0), first(1)) {
+        ("bits" | "cpu" | "default", _) | ("[", "bits") => return String::new(),
+        ("section" | "segment", name) => name,
+        ("[", "section") => first(2),
+        _ => "",
+    };
+    if !section.is_empty() {
+        let directive = match section {
+            ".text" | ".code" => ".code",
+            ".bss" => ".data?",
+            ".rodata" => ".const",
+            _ => ".data",
+        };
+        return directive.to_string();
+    }
+    if let Some(definition) = data_definition(text, &tokens, &words) {
+        return definition;
+    }
+
+    let mut translated = String::new();
+    let mut copied = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Word {
+            continue;
+        }
+        let word = &words[index];
+        let replacement = if SIZES.contains(&word.as_str())
+            && tokens
+                .get(index + 1)
+                .is_some_and(|next| next.kind == TokenKind::Punct('['))
+        {
+            format!("{} PTR", word.to_uppercase())
+        } else if !INTERNAL_REGISTERS.contains(&word.as_str())
+            && RegisterName::is_valid_name(&word.to_uppercase())
+        {
+            word.to_uppercase()
+        } else {
+            continue;
+        };
+        translated.push_str(&text[copied..token.start]);
+        translated.push_str(&replacement);
+        copied = token.end;
+    }
+    translated.push_str(&text[copied..]);
+    translated
+}
+
+// `name[:] db|resb|times ...` as a TASM data definition
+fn data_definition(text: &str, tokens: &[Token], words: &[String]) -> Option<String> {
+    let name = tokens.first().filter(|name| name.kind == TokenKind::Word)?;
+    let mut index = 1;
+    if tokens
+        .get(1)
+        .is_some_and(|colon| colon.kind == TokenKind::Punct(':') && colon.start == name.end)
+    {
+        index = 2;
+    }
+    let name = &text[name.start..name.end];
+    let keyword = words.get(index)?.as_str();
+    let last = tokens.last()?.end;
+    let rest = |from: usize| {
+        tokens
+            .get(from)
+            .map_or("", |first| &text[first.start..last])
+    };
+
+    if DEFINES.contains(&keyword) {
+        return Some(format!("{name} {keyword}, {}", rest(index + 1)));
+    }
+    if let Some((_, define)) = RESERVES.iter().find(|(reserve, _)| *reserve == keyword) {
+        return Some(format!("{name} {define}, {} DUP(?)", rest(index + 1)));
+    }
+    if keyword == "times" {
+        let define =
+            (index + 2..tokens.len()).find(|define| DEFINES.contains(&words[*define].as_str()))?;
+        let count = &text[tokens[index + 1].start..tokens[define - 1].end];
+        return Some(format!(
+            "{name} {}, {count} DUP({})",
+            words[define],
+            rest(define + 1)
+        ));
+    }
+    None
+}
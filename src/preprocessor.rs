@@ -0,0 +1,906 @@
+//! Expands macros before the source is assembled: `name MACRO params` ... `ENDM` with
+//! default and required parameters, `LOCAL` labels, and the `REPT`, `IRP`/`FOR` and
+//! `IRPC`/`FORC` repetition blocks.
+//!
+//! `INCLUDE file` (or `%include`) splices in another file, found next to the including file
+//! or else in one of the `-I` search paths.
+//!
+//! `IF`/`IFE`/`IFDEF`/`IFNDEF` ... `ELSEIF` ... `ELSE` ... `ENDIF` keep or drop lines on a
+//! constant expression, and `.ERR`, `.ERRE` and `.ERRNZ` stop the build with a message.
+//!
+//! `%define NAME text` replaces NAME by the text in the lines after it, until `%undef NAME`.
+//! NASM lines are rewritten into TASM syntax once they are expanded, and AT&T files as
+//! they are read.
+//!
+//! Every expanded line remembers the file and line it was written on. Lines produced by a
+//! macro call take the line of the call, so errors in an expansion point at the call site.
+
+use crate::{
+    assembler::{constant_definition, is_valid_name, Program},
+    att,
+    error_code::ErrorCode,
+    expression::{evaluate, Scope},
+    lexer::{split_line, tokenize, Dialect, LexerOptions, TokenKind},
+    nasm,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Deepest nesting of macro calls and repetitions, to stop runaway recursion
+const MAX_DEPTH: usize = 64;
+// Most lines a program can expand to
+const MAX_LINES: usize = 0x10000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub text: String,
+    // Index into the files of the expanded source. 0 is the file being assembled.
+    pub file: usize,
+    // Index of the line in that file
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PreprocessOptions {
+    // The file being assembled
+    pub main: Option<PathBuf>,
+    // Where INCLUDE looks after the including file's own directory
+    pub search_paths: Vec<PathBuf>,
+    // `-D NAME=value` symbols, defined with EQU before the first line
+    pub defines: Vec<(String, String)>,
+}
+
+// The file that `-D` definitions are reported in
+const COMMAND_LINE: &str = "<command line>";
+
+// One IF ... ENDIF
+struct Condition {
+    // Where the IF is, for a missing ENDIF
+    start: SourceLine,
+    // Lines are kept in the current branch
+    active: bool,
+    // A branch was kept already, so the rest are dropped
+    taken: bool,
+    // The whole IF sits in a dropped branch
+    dropped: bool,
+    seen_else: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Source {
+    pub files: Vec<PathBuf>,
+    pub lines: Vec<SourceLine>,
+}
+
+#[derive(Debug, Clone)]
+struct Parameter {
+    name: String,
+    default: Option<String>,
+    required: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    parameters: Vec<Parameter>,
+    body: Vec<SourceLine>,
+}
+
+// The first two words of a line and the text after each, without the comment
+struct Head<'a> {
+    first: &'a str,
+    second: &'a str,
+    after_first: &'a str,
+    after_second: &'a str,
+}
+
+fn head(text: &str, options: LexerOptions) -> Option<Head<'_>> {
+    let tokens = tokenize(text, 0, options).ok()?;
+    let first = tokens
+        .first()
+        .filter(|token| token.kind == TokenKind::Word)?;
+    let end = tokens.last()?.end;
+    let second = tokens.get(1);
+    let (second_text, after_second) = match second {
+        Some(token) if token.kind == TokenKind::Word => (
+            &text[token.start..token.end],
+            tokens.get(2).map_or("", |next| &text[next.start..end]),
+        ),
+        _ => ("", ""),
+    };
+    Some(Head {
+        first: &text[first.start..first.end],
+        second: second_text,
+        after_first: second.map_or("", |next| &text[next.start..end]),
+        after_second,
+    })
+}
+
+fn is_block_start(head: &Head) -> bool {
+    head.second.eq_ignore_ascii_case("macro")
+        || ["rept", "irp", "irpc", "for", "forc"]
+            .iter()
+            .any(|directive| head.first.eq_ignore_ascii_case(directive))
+}
+
+fn syntax_error(line: &SourceLine, message: String) -> ErrorCode {
+    ErrorCode::SyntaxError {
+        file: None,
+        line: line.line + 1,
+        column: 1,
+        message,
+    }
+}
+
+// Splits macro arguments on commas outside strings, parentheses and <...>
+fn split_arguments(text: &str, options: LexerOptions) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let Ok(tokens) = tokenize(text, 0, options) else {
+        return vec![text.trim().to_string()];
+    };
+    let mut arguments = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for token in tokens {
+        match token.kind {
+            TokenKind::Punct('(' | '<') => depth += 1,
+            TokenKind::Punct(')' | '>') => depth -= 1,
+            TokenKind::Punct(',') if depth == 0 => {
+                arguments.push(strip_brackets(&text[start..token.start]));
+                start = token.end;
+            }
+            _ => {}
+        }
+    }
+    arguments.push(strip_brackets(&text[start..]));
+    arguments
+}
+
+// <a, b> passes `a, b` as one argument
+fn strip_brackets(text: &str) -> String {
+    let text = text.trim();
+    text.strip_prefix('<')
+        .and_then(|inner| inner.strip_suffix('>'))
+        .unwrap_or(text)
+        .to_string()
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '@' | '$' | '?')
+}
+
+// Replaces every parameter that appears as a whole word. Inside strings only `&name&`
+// is replaced, and `&` next to a parameter is dropped, so `&x&_end` joins the pieces.
+fn substitute(text: &str, bindings: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if !is_name_char(c) {
+            match quote {
+                Some(open) if c == open => quote = None,
+                None if c == '\'' || c == '"' => quote = Some(c),
+                None if c == ';' => {
+                    result.push_str(&text[start..]);
+                    break;
+                }
+                _ => {}
+            }
+            result.push(c);
+            continue;
+        }
+        let mut end = start + c.len_utf8();
+        while let Some((offset, next)) = chars.next_if(|(_, next)| is_name_char(*next)) {
+            end = offset + next.len_utf8();
+        }
+        let word = &text[start..end];
+        let marked = result.ends_with('&');
+        match bindings.get(word) {
+            Some(value) if quote.is_none() || marked => {
+                if marked {
+                    result.pop();
+                }
+                result.push_str(value);
+                chars.next_if(|(_, next)| *next == '&');
+            }
+            _ => result.push_str(word),
+        }
+    }
+    result
+}
+
+// Names the file in a syntax error raised on one of its lines, unless a file is named already
+pub fn in_file(error: ErrorCode, path: &Path) -> ErrorCode {
+    match error {
+        ErrorCode::SyntaxError {
+            file: None,
+            line,
+            column,
+            message,
+        } => ErrorCode::SyntaxError {
+            file: Some(path.display().to_string()),
+            line,
+            column,
+            message,
+        },
+        error => error,
+    }
+}
+
+fn source_lines(lines: &[String], file: usize) -> Vec<SourceLine> {
+    lines
+        .iter()
+        .enumerate()
+        .map(|(line, text)| SourceLine {
+            text: text.clone(),
+            file,
+            line,
+        })
+        .collect()
+}
+
+// The first word of a line, lowercase and without NASM's `%`, and the text after it
+fn directive(text: &str) -> (String, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    let (directive, rest) = text.split_at(end);
+    let directive = directive.strip_prefix('%').unwrap_or(directive);
+    (directive.to_lowercase(), rest.trim())
+}
+
+// The text of a message argument, without the comment and the quotes or <...> around it
+fn message_text(text: &str) -> &str {
+    let text = text.trim();
+    match text.chars().next() {
+        Some(open @ ('\'' | '"' | '<')) => {
+            let close = if open == '<' { '>' } else { open };
+            let inner = &text[1..];
+            inner.find(close).map_or(inner, |end| &inner[..end])
+        }
+        _ => text.split(';').next().unwrap_or_default().trim(),
+    }
+}
+
+// The file named by `INCLUDE file`, `INCLUDE "file"` or `%include <file>`
+fn include_target(text: &str) -> Option<&str> {
+    let (directive, rest) = directive(text);
+    if directive != "include" {
+        return None;
+    }
+    let close = match rest.chars().next()? {
+        quote @ ('\'' | '"') => quote,
+        '<' => '>',
+        _ => {
+            let end = rest.find(|c: char| c.is_whitespace() || c == ';');
+            return Some(&rest[..end.unwrap_or(rest.len())]);
+        }
+    };
+    let rest = &rest[1..];
+    rest.find(close).map(|end| &rest[..end])
+}
+
+pub fn preprocess(
+    lines: &[String],
+    options: LexerOptions,
+    preprocess_options: &PreprocessOptions,
+) -> Result<Source, ErrorCode> {
+    preprocess_with(lines, options, preprocess_options, None).map(|(source, _)| source)
+}
+
+/// As `preprocess`, but goes on past a line that fails, such as a bad macro call, and returns
+/// the errors with what is left. Only a bad `-D` definition still stops it.
+pub fn preprocess_all(
+    lines: &[String],
+    options: LexerOptions,
+    preprocess_options: &PreprocessOptions,
+) -> Result<(Source, Vec<ErrorCode>), ErrorCode> {
+    preprocess_with(lines, options, preprocess_options, Some(Vec::new()))
+}
+
+fn preprocess_with(
+    lines: &[String],
+    options: LexerOptions,
+    preprocess_options: &PreprocessOptions,
+    errors: Option<Vec<ErrorCode>>,
+) -> Result<(Source, Vec<ErrorCode>), ErrorCode> {
+    let mut preprocessor = Preprocessor {
+        options,
+        search_paths: preprocess_options.search_paths.clone(),
+        files: vec![preprocess_options.main.clone().unwrap_or_default()],
+        including: vec![0],
+        macros: HashMap::new(),
+        locals: 0,
+        defines: HashMap::new(),
+        constants: Program::default(),
+        symbols: HashSet::new(),
+        output: Vec::new(),
+        errors,
+    };
+    if !preprocess_options.defines.is_empty() {
+        let defines: Vec<String> = preprocess_options
+            .defines
+            .iter()
+            .map(|(name, value)| format!("{name} equ {value}"))
+            .collect();
+        preprocessor.files.push(COMMAND_LINE.into());
+        let file = preprocessor.files.len() - 1;
+        for line in source_lines(&defines, file) {
+            preprocessor
+                .emit(&line)
+                .map_err(|error| in_file(error, Path::new(COMMAND_LINE)))?;
+        }
+    }
+    preprocessor.run(&preprocessor.file_lines(lines, 0), 0)?;
+    let source = Source {
+        files: preprocessor.files,
+        lines: preprocessor.output,
+    };
+    Ok((source, preprocessor.errors.unwrap_or_default()))
+}
+
+struct Preprocessor {
+    options: LexerOptions,
+    search_paths: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+    // The files being included, outermost first, to catch cycles
+    including: Vec<usize>,
+    macros: HashMap<String, Macro>,
+    // LOCAL labels named so far, for MASM's ??0000 names
+    locals: usize,
+    // `%define` names and their text
+    defines: HashMap<String, String>,
+    // EQU and `=` symbols seen so far, for REPT counts and IF
+    constants: Program,
+    // Names of every EQU and `=` symbol, even those without a numeric value, for IFDEF
+    symbols: HashSet<String>,
+    output: Vec<SourceLine>,
+    // Errors passed over so far, when the run goes on after them rather than stopping
+    errors: Option<Vec<ErrorCode>>,
+}
+
+impl Preprocessor {
+    fn run(&mut self, lines: &[SourceLine], depth: usize) -> Result<(), ErrorCode> {
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut index = 0;
+        while index < lines.len() {
+            if let Err(error) = self.statement(lines, &mut index, &mut conditions, depth) {
+                self.recover(error)?;
+            }
+        }
+        match conditions.pop() {
+            Some(condition) => self.recover(syntax_error(
+                &condition.start,
+                format!("{} has no ENDIF", condition.start.text.trim()),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Reports `error`, or keeps it and goes on when every error is wanted
+    fn recover(&mut self, error: ErrorCode) -> Result<(), ErrorCode> {
+        let Some(errors) = &mut self.errors else {
+            return Err(error);
+        };
+        // A kept error never passes back through `include`, which names its file
+        errors.push(match self.including.last() {
+            Some(&file) if file != 0 => in_file(error, &self.files[file]),
+            _ => error,
+        });
+        Ok(())
+    }
+
+    // One line of `lines`, and the block it starts
+    fn statement(
+        &mut self,
+        lines: &[SourceLine],
+        index: &mut usize,
+        conditions: &mut Vec<Condition>,
+        depth: usize,
+    ) -> Result<(), ErrorCode> {
+        let line = &lines[*index];
+        *index += 1;
+        if self.condition(line, conditions)? {
+            return Ok(());
+        }
+        if conditions.last().is_some_and(|condition| !condition.active) {
+            return Ok(());
+        }
+        if self.define(line)? {
+            return Ok(());
+        }
+        let rewritten;
+        let line = if self.defines.is_empty() && self.options.dialect == Dialect::Tasm {
+            line
+        } else {
+            let mut text = substitute(&line.text, &self.defines);
+            if self.options.dialect == Dialect::Nasm {
+                text = nasm::translate(&text, self.options);
+            }
+            rewritten = SourceLine {
+                text,
+                ..line.clone()
+            };
+            &rewritten
+        };
+        if self.assertion(line)? {
+            return Ok(());
+        }
+        let instruction;
+        let line = match split_label(&line.text, self.options) {
+            Some((label, rest)) => {
+                self.emit(&SourceLine {
+                    text: label.to_string(),
+                    ..line.clone()
+                })?;
+                instruction = SourceLine {
+                    text: rest.to_string(),
+                    ..line.clone()
+                };
+                &instruction
+            }
+            None => line,
+        };
+        if let Some(target) = include_target(&line.text) {
+            self.include(target, line, depth)?;
+            return Ok(());
+        }
+        let Some(head) = head(&line.text, self.options) else {
+            self.emit(line)?;
+            return Ok(());
+        };
+
+        if head.first.eq_ignore_ascii_case("endm") {
+            return Err(syntax_error(
+                line,
+                "ENDM without MACRO, REPT or IRP".to_string(),
+            ));
+        }
+        if !is_block_start(&head) {
+            match self.macros.get(head.first).cloned() {
+                Some(definition) => {
+                    let expansion = self.expand(head.first, &definition, head.after_first, line)?;
+                    self.nested(&expansion, depth, line)?;
+                }
+                None => self.emit(line)?,
+            }
+            return Ok(());
+        }
+
+        let body = collect_block(lines, index, self.options)
+            .ok_or_else(|| syntax_error(line, format!("{} has no ENDM", line.text.trim())))?;
+        if head.second.eq_ignore_ascii_case("macro") {
+            let parameters = parse_parameters(head.after_second, self.options)
+                .map_err(|message| syntax_error(line, message))?;
+            self.macros
+                .insert(head.first.to_string(), Macro { parameters, body });
+            return Ok(());
+        }
+
+        let directive = head.first.to_lowercase();
+        let mut expansion = Vec::new();
+        if directive == "rept" {
+            for _ in 0..self.repeat_count(head.after_first, line)? {
+                expansion.extend(body.iter().cloned());
+            }
+        } else {
+            let arguments = split_arguments(head.after_first, self.options);
+            let [parameter, values] = arguments.as_slice() else {
+                return Err(syntax_error(
+                    line,
+                    format!(
+                        "{} expects a parameter and a list of values",
+                        directive.to_uppercase()
+                    ),
+                ));
+            };
+            let values: Vec<String> = if directive == "irpc" || directive == "forc" {
+                let text = values.trim_matches(|c| c == '\'' || c == '"');
+                text.chars().map(String::from).collect()
+            } else {
+                split_arguments(values, self.options)
+            };
+            for value in values {
+                let bindings = HashMap::from([(parameter.clone(), value)]);
+                expansion.extend(body.iter().map(|body_line| SourceLine {
+                    text: substitute(&body_line.text, &bindings),
+                    file: body_line.file,
+                    line: body_line.line,
+                }));
+            }
+        }
+        self.nested(&expansion, depth, line)
+    }
+
+    // Handles IF, ELSEIF, ELSE and ENDIF. False when the line is something else.
+    fn condition(
+        &self,
+        line: &SourceLine,
+        conditions: &mut Vec<Condition>,
+    ) -> Result<bool, ErrorCode> {
+        let (directive, rest) = directive(&line.text);
+        let dropped = conditions.last().is_some_and(|condition| !condition.active);
+        match directive.as_str() {
+            "if" | "ife" | "ifdef" | "ifndef" => {
+                let active = !dropped && self.test(&directive, rest, line)?;
+                conditions.push(Condition {
+                    start: line.clone(),
+                    active,
+                    taken: active,
+                    dropped,
+                    seen_else: false,
+                });
+            }
+            "elseif" | "elif" | "else" => {
+                let Some(condition) = conditions.last_mut() else {
+                    return Err(syntax_error(
+                        line,
+                        format!("{} without IF", directive.to_uppercase()),
+                    ));
+                };
+                if condition.seen_else {
+                    return Err(syntax_error(
+                        line,
+                        format!("{} after ELSE", directive.to_uppercase()),
+                    ));
+                }
+                condition.seen_else = directive == "else";
+                condition.active = !condition.dropped
+                    && !condition.taken
+                    && (directive == "else" || self.test("if", rest, line)?);
+                condition.taken |= condition.active;
+            }
+            "endif" => {
+                if conditions.pop().is_none() {
+                    return Err(syntax_error(line, "ENDIF without IF".to_string()));
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn test(&self, directive: &str, rest: &str, line: &SourceLine) -> Result<bool, ErrorCode> {
+        match directive {
+            "ifdef" | "ifndef" => {
+                let name = rest
+                    .split(|c: char| c.is_whitespace() || c == ';')
+                    .next()
+                    .unwrap_or_default();
+                let defined = self.symbols.contains(name) || self.macros.contains_key(name);
+                Ok(defined == (directive == "ifdef"))
+            }
+            _ => {
+                let value = self.value(rest, line)?;
+                Ok((value != 0) == (directive == "if"))
+            }
+        }
+    }
+
+    // Handles `%define` and `%undef`. False when the line is something else.
+    fn define(&mut self, line: &SourceLine) -> Result<bool, ErrorCode> {
+        if !line.text.trim_start().starts_with('%') {
+            return Ok(false);
+        }
+        let (directive, rest) = directive(&line.text);
+        if directive != "define" && directive != "undef" {
+            return Ok(false);
+        }
+        // The text runs up to the comment
+        let end = tokenize(rest, line.line, self.options)?
+            .last()
+            .map_or(0, |token| token.end);
+        let (name, text) = rest[..end]
+            .split_once(char::is_whitespace)
+            .unwrap_or((&rest[..end], ""));
+        if !is_valid_name(name) {
+            return Err(syntax_error(
+                line,
+                format!("%{directive} expects a name, not {name:?}"),
+            ));
+        }
+        if directive == "define" {
+            self.defines
+                .insert(name.to_string(), text.trim().to_string());
+            self.symbols.insert(name.to_string());
+        } else {
+            self.defines.remove(name);
+            self.symbols.remove(name);
+        }
+        Ok(true)
+    }
+
+    // A number, from the constants defined so far
+    fn value(&self, text: &str, line: &SourceLine) -> Result<i64, ErrorCode> {
+        let scope = Scope {
+            program: &self.constants,
+            options: self.options,
+            here: None,
+        };
+        let text = &substitute(text, &self.defines);
+        match evaluate(text, &scope) {
+            Ok(value) if value.variable.is_none() => Ok(value.constant),
+            Ok(_) => Err(syntax_error(
+                line,
+                format!("{text} is an address, not a constant"),
+            )),
+            Err(error) => Err(syntax_error(line, error.to_string())),
+        }
+    }
+
+    // Handles `.ERR`, and `.ERRE`/`.ERRNZ`, which fail when their expression is zero/nonzero.
+    // NASM spells `.ERR` as `%error`. False when the line is something else.
+    fn assertion(&self, line: &SourceLine) -> Result<bool, ErrorCode> {
+        let (mut directive, rest) = directive(&line.text);
+        // `error` without the `%` is a name, as in `error db, 'Oops$'`
+        if directive == "error" {
+            let nasm = self.options.dialect == Dialect::Nasm;
+            if !(nasm && line.text.trim_start().starts_with('%')) {
+                return Ok(false);
+            }
+            directive = ".err".to_string();
+        }
+        let (expression, message) = match rest.split_once(',') {
+            Some((expression, message)) => (expression.trim(), message_text(message)),
+            None => (rest, ""),
+        };
+        let failed = match directive.as_str() {
+            ".err" => true,
+            ".erre" => self.value(expression, line)? == 0,
+            ".errnz" => self.value(expression, line)? != 0,
+            _ => return Ok(false),
+        };
+        if !failed {
+            return Ok(true);
+        }
+        let message = match (directive.as_str(), message_text(rest), message) {
+            (".err", "", _) => ".ERR encountered".to_string(),
+            (".err", message, _) => message.to_string(),
+            (_, _, "") => format!(
+                "Assertion failed: {} {expression}",
+                directive.to_uppercase()
+            ),
+            (_, _, message) => message.to_string(),
+        };
+        Err(syntax_error(line, message))
+    }
+
+    fn nested(
+        &mut self,
+        lines: &[SourceLine],
+        depth: usize,
+        at: &SourceLine,
+    ) -> Result<(), ErrorCode> {
+        if depth >= MAX_DEPTH {
+            return Err(syntax_error(
+                at,
+                format!("Macros and includes nest deeper than {MAX_DEPTH} levels"),
+            ));
+        }
+        self.run(lines, depth + 1)
+    }
+
+    // Looks next to the including file first, then through the search paths
+    fn resolve(&self, target: &str, at: &SourceLine) -> Option<PathBuf> {
+        let own = self.files[at.file]
+            .parent()
+            .map_or_else(PathBuf::new, Path::to_path_buf);
+        std::iter::once(own)
+            .chain(self.search_paths.iter().cloned())
+            .map(|directory| directory.join(target))
+            .find(|path| path.is_file())
+    }
+
+    fn include(&mut self, target: &str, at: &SourceLine, depth: usize) -> Result<(), ErrorCode> {
+        let path = self
+            .resolve(target, at)
+            .ok_or_else(|| syntax_error(at, format!("Could not find include file {target}")))?;
+        // The same file reached by another path is still the same file
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        let same = |file: &PathBuf| fs::canonicalize(file).is_ok_and(|known| known == canonical);
+        let file = match self.files.iter().position(same) {
+            Some(file) => file,
+            None => {
+                self.files.push(path.clone());
+                self.files.len() - 1
+            }
+        };
+        if let Some(start) = self.including.iter().position(|open| *open == file) {
+            let cycle: Vec<String> = self.including[start..]
+                .iter()
+                .chain([&file])
+                .map(|open| self.files[*open].display().to_string())
+                .collect();
+            return Err(syntax_error(
+                at,
+                format!("Include cycle: {}", cycle.join(" -> ")),
+            ));
+        }
+        let text = fs::read_to_string(&path).map_err(|error| {
+            syntax_error(at, format!("Could not read {}: {error}", path.display()))
+        })?;
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+
+        self.including.push(file);
+        let result = self
+            .nested(&self.file_lines(&lines, file), depth, at)
+            .map_err(|error| in_file(error, &path));
+        self.including.pop();
+        result
+    }
+
+    // The lines of a file. AT&T data takes its name from the line above, so the whole file
+    // is translated at once.
+    fn file_lines(&self, lines: &[String], file: usize) -> Vec<SourceLine> {
+        match self.options.dialect {
+            Dialect::Att => source_lines(&att::translate(lines, self.options), file),
+            _ => source_lines(lines, file),
+        }
+    }
+
+    // Passes a line through, keeping track of constants for REPT
+    fn emit(&mut self, line: &SourceLine) -> Result<(), ErrorCode> {
+        if self.output.len() >= MAX_LINES {
+            return Err(syntax_error(
+                line,
+                format!("The program expands to more than {MAX_LINES} lines"),
+            ));
+        }
+        if let Ok(Some(parts)) = split_line(&line.text, line.line, self.options) {
+            if let Some((name, _, expression)) = constant_definition(&parts) {
+                self.symbols.insert(name.clone());
+                let scope = Scope {
+                    program: &self.constants,
+                    options: self.options,
+                    here: None,
+                };
+                if let Ok(value) = evaluate(&expression, &scope) {
+                    self.constants.constants.insert(name, value);
+                }
+            }
+        }
+        self.output.push(line.clone());
+        Ok(())
+    }
+
+    fn repeat_count(&self, text: &str, line: &SourceLine) -> Result<usize, ErrorCode> {
+        let scope = Scope {
+            program: &self.constants,
+            options: self.options,
+            here: None,
+        };
+        evaluate(text, &scope)
+            .ok()
+            .filter(|count| count.variable.is_none())
+            .and_then(|count| usize::try_from(count.constant).ok())
+            .filter(|count| *count <= MAX_LINES)
+            .ok_or_else(|| {
+                syntax_error(
+                    line,
+                    format!("REPT count {text} must be a number up to {MAX_LINES}"),
+                )
+            })
+    }
+
+    // The body of a macro for one call, with its parameters and LOCAL labels replaced
+    fn expand(
+        &mut self,
+        name: &str,
+        definition: &Macro,
+        arguments: &str,
+        call: &SourceLine,
+    ) -> Result<Vec<SourceLine>, ErrorCode> {
+        let arguments = split_arguments(arguments, self.options);
+        if arguments.len() > definition.parameters.len() {
+            return Err(syntax_error(
+                call,
+                format!(
+                    "{name} takes {} arguments but was given {}",
+                    definition.parameters.len(),
+                    arguments.len()
+                ),
+            ));
+        }
+        let mut bindings = HashMap::new();
+        for (i, parameter) in definition.parameters.iter().enumerate() {
+            let value = match arguments.get(i).filter(|argument| !argument.is_empty()) {
+                Some(argument) => argument.clone(),
+                None if parameter.required => {
+                    return Err(syntax_error(
+                        call,
+                        format!("{name} needs a value for {}", parameter.name),
+                    ))
+                }
+                None => parameter.default.clone().unwrap_or_default(),
+            };
+            bindings.insert(parameter.name.clone(), value);
+        }
+
+        let mut expansion = Vec::new();
+        for body_line in &definition.body {
+            match head(&body_line.text, self.options) {
+                Some(head) if head.first.eq_ignore_ascii_case("local") => {
+                    for local in split_arguments(head.after_first, self.options) {
+                        let unique = format!("??{:04X}", self.locals);
+                        self.locals += 1;
+                        bindings.insert(local, unique);
+                    }
+                }
+                _ => expansion.push(SourceLine {
+                    text: substitute(&body_line.text, &bindings),
+                    file: call.file,
+                    line: call.line,
+                }),
+            }
+        }
+        Ok(expansion)
+    }
+}
+
+// `again: dec CX` holds two lines: the label, and the instruction after it
+fn split_label(text: &str, options: LexerOptions) -> Option<(&str, &str)> {
+    let tokens = tokenize(text, 0, options).ok()?;
+    let [name, colon, next, ..] = tokens.as_slice() else {
+        return None;
+    };
+    let label = name.kind == TokenKind::Word
+        && colon.kind == TokenKind::Punct(':')
+        && colon.start == name.end;
+    label.then(|| (&text[..colon.end], &text[next.start..]))
+}
+
+// The lines up to the ENDM that closes the block started just before `index`
+fn collect_block(
+    lines: &[SourceLine],
+    index: &mut usize,
+    options: LexerOptions,
+) -> Option<Vec<SourceLine>> {
+    let mut depth = 1;
+    let start = *index;
+    while *index < lines.len() {
+        let line = &lines[*index];
+        *index += 1;
+        if let Some(head) = head(&line.text, options) {
+            if is_block_start(&head) {
+                depth += 1;
+            } else if head.first.eq_ignore_ascii_case("endm") {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(lines[start..*index - 1].to_vec());
+                }
+            }
+        }
+    }
+    None
+}
+
+// `a, b:=<1>, c:REQ`
+fn parse_parameters(text: &str, options: LexerOptions) -> Result<Vec<Parameter>, String> {
+    let mut parameters = Vec::new();
+    for parameter in split_arguments(text, options) {
+        let (name, rest) = match parameter.split_once(':') {
+            Some((name, rest)) => (name.trim(), Some(rest.trim())),
+            None => (parameter.trim(), None),
+        };
+        if name.is_empty() || !name.chars().all(is_name_char) {
+            return Err(format!("Invalid macro parameter {parameter}"));
+        }
+        let (default, required) = match rest {
+            None => (None, false),
+            Some(rest) if rest.eq_ignore_ascii_case("req") => (None, true),
+            Some(rest) => match rest.strip_prefix('=') {
+                Some(default) => (Some(strip_brackets(default)), false),
+                None => return Err(format!("Invalid macro parameter {parameter}")),
+            },
+        };
+        parameters.push(Parameter {
+            name: name.to_string(),
+            default,
+            required,
+        });
+    }
+    Ok(parameters)
+}
@@ -37,6 +37,12 @@ pub enum RegisterName {
 }
 
 impl RegisterName {
+    // Every register, as written in source
+    pub const NAMES: [&str; 24] = [
+        "EAX", "AX", "AL", "AH", "EBX", "BX", "BL", "BH", "ECX", "CX", "CL", "CH", "EDX", "DX",
+        "DL", "DH", "ESI", "SI", "EDI", "DI", "BP", "SP", "IP", "FLAG",
+    ];
+
     pub fn from_str_to_reg_name(input: &str) -> Result<Self, ErrorCode> {
         match input {
             "EAX" => Ok(RegisterName::EAX),
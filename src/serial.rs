@@ -0,0 +1,227 @@
+//! COM1: a 16550 UART at 3F8h, bridged to stdio, files or a Unix domain socket.
+//! Incoming bytes are read by a background thread and reach the UART through a channel,
+//! so a program polling the line status never blocks the engine.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read, Write},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+pub const COM1_BASE: u16 = 0x3F8;
+/// IRQ4 is mapped to vector 0Ch, as on the PC.
+pub const SERIAL_VECTOR: u8 = 0x0C;
+
+// Register offsets from the base port
+const DATA: u16 = 0; // RBR on read, THR on write, DLL with DLAB set
+const INTERRUPT_ENABLE: u16 = 1; // IER, DLM with DLAB set
+const INTERRUPT_ID: u16 = 2; // IIR on read, FCR on write
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const MODEM_STATUS: u16 = 6;
+const SCRATCH: u16 = 7;
+
+const IER_RECEIVE: u8 = 0x01;
+const LCR_DLAB: u8 = 0x80;
+// OUT2 connects the UART's interrupt line to the PIC
+const MCR_OUT2: u8 = 0x08;
+const MCR_LOOPBACK: u8 = 0x10;
+const LSR_DATA_READY: u8 = 0x01;
+// The transmitter is never busy: bytes go straight to the bridge
+const LSR_TRANSMITTER_EMPTY: u8 = 0x60;
+const IIR_NONE: u8 = 0x01;
+const IIR_RECEIVE: u8 = 0x04;
+// CTS, DSR and DCD: the other end is always there
+const MSR_CONNECTED: u8 = 0xB0;
+
+pub struct Serial {
+    incoming: Receiver<u8>,
+    outgoing: Box<dyn Write + Send>,
+    received: VecDeque<u8>,
+    divisor: u16,
+    interrupt_enable: u8,
+    line_control: u8,
+    modem_control: u8,
+    scratch: u8,
+    // State of the interrupt line. The PIC only sees its rising edges.
+    irq_line: bool,
+}
+
+impl Serial {
+    /// Bridges the UART to `input` and `output`. `input` is read on a background thread.
+    pub fn new(input: impl Read + Send + 'static, output: impl Write + Send + 'static) -> Self {
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = input;
+            let mut buffer = [0; 256];
+            // Ends at EOF, on a read error, or once the UART is dropped
+            while let Ok(count @ 1..) = input.read(&mut buffer) {
+                if buffer[..count]
+                    .iter()
+                    .any(|byte| sender.send(*byte).is_err())
+                {
+                    break;
+                }
+            }
+        });
+        Self::with_receiver(incoming, Box::new(output))
+    }
+
+    // A UART whose receive line is never driven
+    fn transmit_only(output: impl Write + Send + 'static) -> Self {
+        let (_, incoming) = mpsc::channel();
+        Self::with_receiver(incoming, Box::new(output))
+    }
+
+    fn with_receiver(incoming: Receiver<u8>, outgoing: Box<dyn Write + Send>) -> Self {
+        Self {
+            incoming,
+            outgoing,
+            received: VecDeque::new(),
+            divisor: 12, // 9600 baud
+            interrupt_enable: 0,
+            line_control: 0x03, // 8N1
+            modem_control: 0,
+            scratch: 0,
+            irq_line: false,
+        }
+    }
+
+    /// Opens a bridge described by `spec`:
+    /// `stdio`, `file:OUTPUT` or `file:OUTPUT:INPUT`, or `unix:SOCKET` to connect to a listening socket.
+    pub fn open(spec: &str) -> io::Result<Self> {
+        if spec == "stdio" {
+            return Ok(Self::new(io::stdin(), io::stdout()));
+        }
+        if let Some(paths) = spec.strip_prefix("file:") {
+            let (output, input) = match paths.split_once(':') {
+                Some((output, input)) => (output, Some(input)),
+                None => (paths, None),
+            };
+            let output = File::create(output)?;
+            return match input {
+                Some(input) => Ok(Self::new(File::open(input)?, output)),
+                None => Ok(Self::transmit_only(output)),
+            };
+        }
+        #[cfg(unix)]
+        if let Some(path) = spec.strip_prefix("unix:") {
+            let stream = UnixStream::connect(path)?;
+            return Ok(Self::new(stream.try_clone()?, stream));
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown serial bridge {spec}, expected stdio, file:PATH or unix:PATH"),
+        ))
+    }
+
+    fn dlab(&self) -> bool {
+        self.line_control & LCR_DLAB != 0
+    }
+
+    fn interrupt_condition(&self) -> bool {
+        self.interrupt_enable & IER_RECEIVE != 0
+            && self.modem_control & MCR_OUT2 != 0
+            && !self.received.is_empty()
+    }
+
+    /// Moves newly arrived bytes into the receive buffer.
+    /// Returns true when the interrupt line rises, i.e. IRQ4 should be raised.
+    pub fn poll(&mut self) -> bool {
+        while let Ok(byte) = self.incoming.try_recv() {
+            self.received.push_back(byte);
+        }
+        let condition = self.interrupt_condition();
+        let rising = condition && !self.irq_line;
+        self.irq_line = condition;
+        rising
+    }
+
+    /// Whether HLT can wait for the receive interrupt.
+    pub fn receive_interrupt_armed(&self) -> bool {
+        self.interrupt_enable & IER_RECEIVE != 0 && self.modem_control & MCR_OUT2 != 0
+    }
+
+    /// Blocks until a byte arrives. Returns false once the other end has closed.
+    pub fn wait_for_data(&mut self) -> bool {
+        if !self.received.is_empty() {
+            return true;
+        }
+        match self.incoming.recv() {
+            Ok(byte) => {
+                self.received.push_back(byte);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Handles a read from `port`, or None when the port is not a COM1 register.
+    pub fn port_in(&mut self, port: u16) -> Option<u8> {
+        let register = port.checked_sub(COM1_BASE).filter(|offset| *offset < 8)?;
+        let value = match register {
+            DATA if self.dlab() => self.divisor as u8,
+            DATA => {
+                // Reading RBR drops the interrupt line until the next byte is seen
+                self.irq_line = false;
+                self.received.pop_front().unwrap_or(0)
+            }
+            INTERRUPT_ENABLE if self.dlab() => (self.divisor >> 8) as u8,
+            INTERRUPT_ENABLE => self.interrupt_enable,
+            INTERRUPT_ID => {
+                if self.interrupt_enable & IER_RECEIVE != 0 && !self.received.is_empty() {
+                    IIR_RECEIVE
+                } else {
+                    IIR_NONE
+                }
+            }
+            LINE_CONTROL => self.line_control,
+            MODEM_CONTROL => self.modem_control,
+            LINE_STATUS => {
+                let ready = if self.received.is_empty() {
+                    0
+                } else {
+                    LSR_DATA_READY
+                };
+                LSR_TRANSMITTER_EMPTY | ready
+            }
+            MODEM_STATUS => MSR_CONNECTED,
+            _ => self.scratch,
+        };
+        Some(value)
+    }
+
+    /// Handles a write to `port`. Returns false when the port is not a COM1 register.
+    pub fn port_out(&mut self, port: u16, value: u8) -> bool {
+        let Some(register) = port.checked_sub(COM1_BASE).filter(|offset| *offset < 8) else {
+            return false;
+        };
+        match register {
+            DATA if self.dlab() => self.divisor = (self.divisor & 0xFF00) | value as u16,
+            DATA if self.modem_control & MCR_LOOPBACK != 0 => self.received.push_back(value),
+            DATA => {
+                // A broken bridge behaves like an unplugged cable: the byte is lost
+                let _ = self
+                    .outgoing
+                    .write_all(&[value])
+                    .and_then(|_| self.outgoing.flush());
+            }
+            INTERRUPT_ENABLE if self.dlab() => {
+                self.divisor = (self.divisor & 0x00FF) | (value as u16) << 8
+            }
+            INTERRUPT_ENABLE => self.interrupt_enable = value & 0x0F,
+            LINE_CONTROL => self.line_control = value,
+            MODEM_CONTROL => self.modem_control = value & 0x1F,
+            SCRATCH => self.scratch = value,
+            // FIFO control, line and modem status writes have no effect
+            _ => {}
+        }
+        true
+    }
+}
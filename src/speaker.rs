@@ -0,0 +1,232 @@
+//! The PC speaker: PIT channel 2 drives a square wave that port 61h gates onto the speaker.
+//! Every change of tone is recorded against the instruction clock and rendered to a WAV file.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+pub const PIT_CHANNEL_2_PORT: u16 = 0x42;
+pub const PIT_COMMAND_PORT: u16 = 0x43;
+/// Bit 0 gates PIT channel 2, bit 1 connects its output to the speaker.
+pub const SPEAKER_PORT: u16 = 0x61;
+
+/// Input clock of the PIT, in Hz.
+pub const PIT_FREQUENCY: usize = 1_193_182;
+/// Virtual time: every executed instruction lasts one microsecond.
+pub const INSTRUCTIONS_PER_SECOND: usize = 1_000_000;
+pub const SAMPLE_RATE: usize = 44_100;
+
+const GATE_BIT: u8 = 0x01;
+const SPEAKER_DATA_BIT: u8 = 0x02;
+/// Read-only: the current output of PIT channel 2.
+const OUTPUT_BIT: u8 = 0x20;
+const AMPLITUDE: i16 = 8_000;
+
+// How the next reload byte written to port 42h is used
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    LowByte,
+    HighByte,
+    LowThenHigh,
+}
+
+pub struct Speaker {
+    access: Access,
+    // Waiting for the high byte of a low/high reload
+    expecting_high: bool,
+    // Reading the high byte of a low/high read next
+    reading_high: bool,
+    reload: u16,
+    port_61: u8,
+    // (instruction count, frequency) at every change, None while silent
+    timeline: Vec<(usize, Option<f64>)>,
+    output: Option<PathBuf>,
+}
+
+impl Default for Speaker {
+    fn default() -> Self {
+        Self {
+            access: Access::LowThenHigh,
+            expecting_high: false,
+            reading_high: false,
+            reload: 0,
+            port_61: 0,
+            timeline: Vec::new(),
+            output: None,
+        }
+    }
+}
+
+impl Speaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_output(&mut self, path: &Path) {
+        self.output = Some(path.to_path_buf());
+    }
+
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    pub fn timeline(&self) -> &[(usize, Option<f64>)] {
+        &self.timeline
+    }
+
+    // A reload value of 0 counts 65536 input clocks
+    fn divisor(&self) -> usize {
+        if self.reload == 0 {
+            0x10000
+        } else {
+            self.reload as usize
+        }
+    }
+
+    /// The tone currently reaching the speaker, in Hz.
+    pub fn frequency(&self) -> Option<f64> {
+        let audible = GATE_BIT | SPEAKER_DATA_BIT;
+        (self.port_61 & audible == audible).then(|| PIT_FREQUENCY as f64 / self.divisor() as f64)
+    }
+
+    fn record(&mut self, instruction_count: usize) {
+        let frequency = self.frequency();
+        let last = self.timeline.last().and_then(|(_, frequency)| *frequency);
+        if last != frequency {
+            self.timeline.push((instruction_count, frequency));
+        }
+    }
+
+    /// Handles a read from `port`, or None when the port is not a speaker register.
+    pub fn port_in(&mut self, port: u16, instruction_count: usize) -> Option<u8> {
+        match port {
+            PIT_CHANNEL_2_PORT => {
+                let [low, high] = self.reload.to_le_bytes();
+                let value = match self.access {
+                    Access::LowByte => low,
+                    Access::HighByte => high,
+                    Access::LowThenHigh => {
+                        self.reading_high = !self.reading_high;
+                        if self.reading_high {
+                            low
+                        } else {
+                            high
+                        }
+                    }
+                };
+                Some(value)
+            }
+            SPEAKER_PORT => {
+                // Channel 2 outputs a square wave while it is gated on
+                let mut value = self.port_61 & (GATE_BIT | SPEAKER_DATA_BIT);
+                if self.port_61 & GATE_BIT != 0 {
+                    let divisor = self.divisor();
+                    let clocks = instruction_count * PIT_FREQUENCY / INSTRUCTIONS_PER_SECOND;
+                    if clocks % divisor < divisor / 2 {
+                        value |= OUTPUT_BIT;
+                    }
+                }
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles a write to `port`. Returns false when the port is not a speaker register.
+    pub fn port_out(&mut self, port: u16, value: u8, instruction_count: usize) -> bool {
+        match port {
+            PIT_COMMAND_PORT => {
+                // Bits 6-7 select the channel, bits 4-5 the access mode.
+                // Only channel 2 is wired; latch commands (access 0) keep the current mode.
+                if value >> 6 == 2 {
+                    match (value >> 4) & 0x03 {
+                        1 => self.access = Access::LowByte,
+                        2 => self.access = Access::HighByte,
+                        3 => self.access = Access::LowThenHigh,
+                        _ => {}
+                    }
+                    self.expecting_high = false;
+                    self.reading_high = false;
+                }
+            }
+            PIT_CHANNEL_2_PORT => {
+                let [low, high] = self.reload.to_le_bytes();
+                self.reload = match self.access {
+                    Access::LowByte => u16::from_le_bytes([value, 0]),
+                    Access::HighByte => u16::from_le_bytes([0, value]),
+                    Access::LowThenHigh if self.expecting_high => u16::from_le_bytes([low, value]),
+                    Access::LowThenHigh => u16::from_le_bytes([value, high]),
+                };
+                if self.access == Access::LowThenHigh {
+                    self.expecting_high = !self.expecting_high;
+                    // The counter only reloads once both bytes are in
+                    if self.expecting_high {
+                        return true;
+                    }
+                }
+                self.record(instruction_count);
+            }
+            SPEAKER_PORT => {
+                self.port_61 = value;
+                self.record(instruction_count);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Renders the timeline up to `end` (an instruction count) as 16-bit mono PCM.
+    pub fn render(&self, end: usize) -> Vec<i16> {
+        let sample_count = end * SAMPLE_RATE / INSTRUCTIONS_PER_SECOND;
+        let mut samples = vec![0; sample_count];
+        // Phase carries over tone changes so the wave doesn't click
+        let mut phase = 0.0_f64;
+        for (i, (start, frequency)) in self.timeline.iter().enumerate() {
+            let stop = self.timeline.get(i + 1).map_or(end, |(next, _)| *next);
+            let first = start * SAMPLE_RATE / INSTRUCTIONS_PER_SECOND;
+            let last = (stop * SAMPLE_RATE / INSTRUCTIONS_PER_SECOND).min(sample_count);
+            let Some(frequency) = frequency else {
+                continue;
+            };
+            for sample in samples.iter_mut().take(last).skip(first) {
+                *sample = if phase < 0.5 { AMPLITUDE } else { -AMPLITUDE };
+                phase = (phase + frequency / SAMPLE_RATE as f64).fract();
+            }
+        }
+        samples
+    }
+
+    /// Writes the timeline up to `end` (an instruction count) to a WAV file.
+    pub fn write_wav(&self, path: &Path, end: usize) -> io::Result<()> {
+        let samples = self.render(end);
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_wav(&mut writer, &samples)?;
+        writer.flush()
+    }
+}
+
+// 16-bit mono PCM at SAMPLE_RATE
+pub fn write_wav(writer: &mut impl Write, samples: &[i16]) -> io::Result<()> {
+    let data_size = (samples.len() * 2) as u32;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16_u32.to_le_bytes())?;
+    writer.write_all(&1_u16.to_le_bytes())?; // PCM
+    writer.write_all(&1_u16.to_le_bytes())?; // Mono
+    writer.write_all(&(SAMPLE_RATE as u32).to_le_bytes())?;
+    writer.write_all(&(SAMPLE_RATE as u32 * 2).to_le_bytes())?; // Bytes per second
+    writer.write_all(&2_u16.to_le_bytes())?; // Bytes per frame
+    writer.write_all(&16_u16.to_le_bytes())?; // Bits per sample
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
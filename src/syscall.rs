@@ -0,0 +1,113 @@
+//! Linux i386 system calls through `int 80h`.
+//! The call number is in EAX and the arguments in EBX, ECX and EDX. The result, or a
+//! negated errno, is returned in EAX.
+
+use crate::{
+    error_code::ErrorCode, host_io::HostIo, memory_manager::MemoryManager, register::Register,
+    status::Status, RegisterName,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const LINUX_SYSCALL_VECTOR: u8 = 0x80;
+
+pub const SYS_EXIT: u32 = 1;
+pub const SYS_READ: u32 = 3;
+pub const SYS_WRITE: u32 = 4;
+pub const SYS_TIME: u32 = 13;
+pub const SYS_BRK: u32 = 45;
+pub const SYS_EXIT_GROUP: u32 = 252;
+
+const EBADF: i32 = 9;
+const EFAULT: i32 = 14;
+const ENOSYS: i32 = 38;
+
+pub fn linux_syscall(
+    memory: &mut MemoryManager,
+    registers: &mut [Register; 10],
+    host: &mut HostIo,
+    exit_code: &mut Option<u32>,
+) -> Result<Status, ErrorCode> {
+    let number = registers[RegisterName::EAX.to_index()].get_dword();
+    let ebx = registers[RegisterName::EBX.to_index()].get_dword();
+    let ecx = registers[RegisterName::ECX.to_index()].get_dword() as usize;
+    let edx = registers[RegisterName::EDX.to_index()].get_dword() as usize;
+
+    let result: Result<u32, i32> = match number {
+        SYS_EXIT | SYS_EXIT_GROUP => {
+            *exit_code = Some(ebx);
+            let _ = host.stdout.flush();
+            return Ok(Status::Halted);
+        }
+        SYS_READ => {
+            if ebx != 0 {
+                Err(EBADF)
+            } else if memory.check_memory_range(ecx, edx).is_err() {
+                Err(EFAULT)
+            } else {
+                let mut buffer = vec![0; edx];
+                host.stdin
+                    .read(&mut buffer)
+                    .map_err(|_| EFAULT)
+                    .and_then(|count| {
+                        for (i, byte) in buffer[..count].iter().enumerate() {
+                            memory.set_byte(ecx + i, *byte).map_err(|_| EFAULT)?;
+                        }
+                        Ok(count as u32)
+                    })
+            }
+        }
+        SYS_WRITE => {
+            let stream = match ebx {
+                1 => Some(&mut host.stdout),
+                2 => Some(&mut host.stderr),
+                _ => None,
+            };
+            match stream {
+                None => Err(EBADF),
+                // Every byte is checked before the buffer is allocated, as EDX can be anything
+                // and the span may run from memory into the framebuffer
+                Some(_) if memory.check_memory_range(ecx, edx).is_err() => Err(EFAULT),
+                Some(stream) => (0..edx)
+                    .map(|i| memory.get_byte(ecx + i))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| EFAULT)
+                    .and_then(|bytes| {
+                        stream
+                            .write_all(&bytes)
+                            .and_then(|_| stream.flush())
+                            .map(|_| edx as u32)
+                            .map_err(|_| EFAULT)
+                    }),
+            }
+        }
+        SYS_TIME => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as u32)
+                .unwrap_or(0);
+            if ebx != 0 && memory.set_dword(ebx as usize, now).is_err() {
+                Err(EFAULT)
+            } else {
+                Ok(now)
+            }
+        }
+        // brk(0) queries the break; the heap may grow up to the stack segment.
+        // An invalid request leaves the break unchanged, as on Linux.
+        SYS_BRK => {
+            let (heap_start, heap_end) = memory.heap_bounds();
+            let requested = ebx as usize;
+            if requested >= heap_start && requested <= heap_end {
+                memory.program_break = requested;
+            }
+            Ok(memory.program_break as u32)
+        }
+        _ => Err(ENOSYS),
+    };
+
+    let eax = match result {
+        Ok(value) => value,
+        Err(errno) => (-errno) as u32,
+    };
+    registers[RegisterName::EAX.to_index()].load_dword(eax);
+    Ok(Status::Ok)
+}
@@ -3,7 +3,7 @@
 // use std::io::BufReader;
 // use std::io::BufRead;
 
-use crate::Engine;
+use crate::{lexer::Dialect, variable_metadata::VariableSize, Engine};
 use std::{
     fs::File,
     io::{self, BufRead, BufReader},
@@ -15,22 +15,78 @@ pub fn read_lines_from_file(filename: &str) -> io::Result<Vec<String>> {
     reader.lines().collect()
 }
 
-pub fn parse_string_to_usize(value: &str) -> Option<u32> {
-    let (radix, number) = if let Some(stripped) = value.strip_suffix('h') {
-        // Hexadecimal format
-        (16, stripped)
-    } else if let Some(stripped) = value.strip_suffix('b') {
-        // Binary format
-        (2, stripped)
+/// Parses an integer literal in MASM/TASM or NASM notation:
+/// `42`, `42d`, `0d42`, `0FFh`, `0xFF`, `101b`, `0b101`, `17q`, `17o`, `0o17`,
+/// with an optional sign and `_` between digits. Suffix forms must start with a digit,
+/// so `FFh` is a name. Values must fit in 64 bits, signed or unsigned.
+pub fn parse_integer(text: &str) -> Option<i128> {
+    let (negative, literal) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if !literal.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let literal = literal.replace('_', "").to_ascii_lowercase();
+
+    let prefixed = |prefixes: &[&str]| {
+        prefixes
+            .iter()
+            .find_map(|prefix| literal.strip_prefix(prefix))
+            .filter(|digits| !digits.is_empty())
+    };
+    let suffixed = |suffixes: &[char]| literal.strip_suffix(suffixes);
+    // A trailing h wins over the 0b and 0d prefixes: 0Bh is eleven
+    let (radix, digits) = if let Some(digits) = suffixed(&['h']) {
+        (16, digits)
+    } else if let Some(digits) = prefixed(&["0x", "0h"]) {
+        (16, digits)
+    } else if let Some(digits) = prefixed(&["0b", "0y"]) {
+        (2, digits)
+    } else if let Some(digits) = prefixed(&["0o", "0q"]) {
+        (8, digits)
+    } else if let Some(digits) = prefixed(&["0d", "0t"]) {
+        (10, digits)
+    } else if let Some(digits) = suffixed(&['b', 'y']) {
+        (2, digits)
+    } else if let Some(digits) = suffixed(&['q', 'o']) {
+        (8, digits)
+    } else if let Some(digits) = suffixed(&['d', 't']) {
+        (10, digits)
     } else {
-        // Decimal format
-        (10, value)
+        (10, literal.as_str())
     };
 
-    match i32::from_str_radix(number, radix) {
-        Ok(v) => Some(v as u32),
-        Err(_) => None,
+    // from_str_radix would accept a sign of its own
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let magnitude = u64::from_str_radix(digits, radix).ok()? as i128;
+    if negative {
+        (magnitude <= 1 << 63).then_some(-magnitude)
+    } else {
+        Some(magnitude)
+    }
+}
+
+/// The value of a character immediate such as 'A' or 'AB'. MASM puts the first character in the
+/// high byte, NASM in the low byte, so 'AB' is 4142h in one and 4241h in the other.
+pub fn char_immediate(string: &str, dialect: Dialect) -> Option<i128> {
+    if string.is_empty() || string.chars().count() > 8 {
+        return None;
     }
+    let bytes = string.chars().map(|c| u8::try_from(c).ok());
+    let fold = |value: Option<i128>, byte: Option<u8>| Some(value? << 8 | byte? as i128);
+    match dialect {
+        Dialect::Nasm => bytes.rev().fold(Some(0), fold),
+        _ => bytes.fold(Some(0), fold),
+    }
+}
+
+/// Whether `value` can be stored in `size`, read as signed or unsigned.
+pub fn fits_in(value: i128, size: VariableSize) -> bool {
+    let bits = size.value() as u32 * 8;
+    value >= -(1 << (bits - 1)) && value < 1 << bits
 }
 
 pub fn initialize_engine(file_path: &str) -> Engine {
@@ -45,8 +101,13 @@ pub fn execute_engine(assembly: &mut Engine, verbose: bool) {
     match result {
         Ok(_) => (),
         Err(e) => {
-            let ip = assembly.lines.get_ip();
-            panic!("Errored during execution.\n{}\nLINE: {}", e, ip);
+            // The line that failed, not IP, which has already moved past it
+            let line = assembly.source_line().unwrap_or_default();
+            let location = assembly.source_location().unwrap_or_default();
+            panic!(
+                "Errored during execution.\n{}\nLINE: {}\nAT: {}",
+                e, line, location
+            );
         }
     }
 }
@@ -11,6 +11,8 @@ pub struct VariableMetadata {
     pub start_index: usize,
     pub length: usize,
     pub size: VariableSize,
+    // Bytes per element: 8 for dq and 10 for dt, whose elements are stored byte by byte
+    pub element_size: usize,
 }
 
 impl VariableMetadata {
@@ -19,6 +21,7 @@ impl VariableMetadata {
             start_index,
             length,
             size,
+            element_size: size.value(),
         }
     }
 }
@@ -0,0 +1,421 @@
+//! VGA mode 13h: a 320x200 framebuffer with 256 colors, mapped at A000:0000.
+//! The palette is programmed through the DAC ports and frames are dumped as PPM or PNG images.
+
+use crate::{
+    error_code::ErrorCode,
+    memory_manager::{get_register_value, MemoryManager},
+    register::Register,
+    status::Status,
+    RegisterName,
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+pub const VIDEO_VECTOR: u8 = 0x10;
+
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 200;
+/// A000:0000 as a linear address. There are no segment registers, so programs address it directly.
+pub const FRAMEBUFFER_ADDRESS: usize = 0xA0000;
+pub const FRAMEBUFFER_SIZE: usize = WIDTH * HEIGHT;
+
+pub const TEXT_MODE: u8 = 0x03;
+pub const GRAPHICS_MODE: u8 = 0x13;
+
+// DAC ports: read index, write index, and data (three 6-bit components per color)
+pub const DAC_READ_INDEX_PORT: u16 = 0x3C7;
+pub const DAC_WRITE_INDEX_PORT: u16 = 0x3C8;
+pub const DAC_DATA_PORT: u16 = 0x3C9;
+/// Input status register 1: bit 3 is set during vertical retrace.
+pub const INPUT_STATUS_PORT: u16 = 0x3DA;
+
+/// Virtual time is the instruction clock: one frame lasts this many instructions.
+pub const INSTRUCTIONS_PER_FRAME: usize = 10_000;
+/// The last instructions of every frame are the vertical retrace.
+const RETRACE_INSTRUCTIONS: usize = 500;
+
+const RETRACE_BIT: u8 = 0x08;
+const DISPLAY_DISABLED_BIT: u8 = 0x01;
+
+// The 16 EGA colors the BIOS loads into the first DAC registers
+const EGA_COLORS: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x2A],
+    [0x00, 0x2A, 0x00],
+    [0x00, 0x2A, 0x2A],
+    [0x2A, 0x00, 0x00],
+    [0x2A, 0x00, 0x2A],
+    [0x2A, 0x15, 0x00],
+    [0x2A, 0x2A, 0x2A],
+    [0x15, 0x15, 0x15],
+    [0x15, 0x15, 0x3F],
+    [0x15, 0x3F, 0x15],
+    [0x15, 0x3F, 0x3F],
+    [0x3F, 0x15, 0x15],
+    [0x3F, 0x15, 0x3F],
+    [0x3F, 0x3F, 0x15],
+    [0x3F, 0x3F, 0x3F],
+];
+
+const GRAY_LEVELS: [u8; 16] = [
+    0x00, 0x05, 0x08, 0x0B, 0x0E, 0x11, 0x14, 0x18, 0x1C, 0x20, 0x24, 0x28, 0x2D, 0x32, 0x38, 0x3F,
+];
+
+// Colors 32-247 are nine runs of 24 hues (blue, magenta, red, yellow, green, cyan),
+// at high/medium/low intensity and high/medium/low saturation.
+// Each run steps between its lowest and highest level in five steps.
+const HUE_LEVELS: [[u8; 5]; 9] = [
+    [0x00, 0x10, 0x1F, 0x2F, 0x3F],
+    [0x1F, 0x27, 0x2F, 0x37, 0x3F],
+    [0x2D, 0x31, 0x36, 0x3A, 0x3F],
+    [0x00, 0x07, 0x0E, 0x15, 0x1C],
+    [0x0E, 0x11, 0x15, 0x18, 0x1C],
+    [0x14, 0x16, 0x18, 0x1A, 0x1C],
+    [0x00, 0x04, 0x08, 0x0C, 0x10],
+    [0x08, 0x0A, 0x0C, 0x0E, 0x10],
+    [0x0B, 0x0C, 0x0D, 0x0F, 0x10],
+];
+
+/// The palette the BIOS programs when it sets mode 13h, as 6-bit DAC values.
+pub fn default_palette() -> [[u8; 3]; 256] {
+    let mut palette = [[0; 3]; 256];
+    palette[..16].copy_from_slice(&EGA_COLORS);
+    for (i, level) in GRAY_LEVELS.iter().enumerate() {
+        palette[16 + i] = [*level; 3];
+    }
+    let mut index = 32;
+    for levels in HUE_LEVELS {
+        let (low, high) = (levels[0], levels[4]);
+        for step in 0..4 {
+            let rising = levels[step];
+            let falling = levels[4 - step];
+            palette[index + step] = [rising, low, high]; // blue -> magenta
+            palette[index + 4 + step] = [high, low, falling]; // magenta -> red
+            palette[index + 8 + step] = [high, rising, low]; // red -> yellow
+            palette[index + 12 + step] = [falling, high, low]; // yellow -> green
+            palette[index + 16 + step] = [low, high, rising]; // green -> cyan
+            palette[index + 20 + step] = [low, falling, high]; // cyan -> blue
+        }
+        index += 24;
+    }
+    // 248-255 stay black
+    palette
+}
+
+pub struct Vga {
+    mode: u8,
+    palette: [[u8; 3]; 256],
+    write_index: u8,
+    read_index: u8,
+    // Component (R, G, B) the next data port access refers to
+    write_component: usize,
+    read_component: usize,
+    // Whether the DAC was last given a read index
+    reading: bool,
+    // Image written at exit, and every N frames when set
+    output: Option<PathBuf>,
+    dump_every: Option<usize>,
+    frame: usize,
+}
+
+impl Default for Vga {
+    fn default() -> Self {
+        Self {
+            mode: TEXT_MODE,
+            palette: default_palette(),
+            write_index: 0,
+            read_index: 0,
+            write_component: 0,
+            read_component: 0,
+            reading: false,
+            output: None,
+            dump_every: None,
+            frame: 0,
+        }
+    }
+}
+
+impl Vga {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    pub fn palette(&self) -> &[[u8; 3]; 256] {
+        &self.palette
+    }
+
+    pub fn set_output(&mut self, path: &Path, every: Option<usize>) {
+        self.output = Some(path.to_path_buf());
+        self.dump_every = every.map(|frames| frames.max(1));
+    }
+
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    /// Advances the frame counter to the instruction clock.
+    /// Returns the path of the periodic dump that is due, if any.
+    pub fn advance(&mut self, instruction_count: usize) -> Option<PathBuf> {
+        let frame = instruction_count / INSTRUCTIONS_PER_FRAME;
+        if frame == self.frame {
+            return None;
+        }
+        self.frame = frame;
+        let every = self.dump_every?;
+        let output = self.output.as_ref()?;
+        if self.mode != GRAPHICS_MODE || !frame.is_multiple_of(every) {
+            return None;
+        }
+        Some(numbered_path(output, frame))
+    }
+
+    /// Handles a read from `port`, or None when the port is not a VGA register.
+    pub fn port_in(&mut self, port: u16, instruction_count: usize) -> Option<u8> {
+        match port {
+            DAC_READ_INDEX_PORT => Some(if self.reading { 0x03 } else { 0x00 }),
+            DAC_WRITE_INDEX_PORT => Some(self.write_index),
+            DAC_DATA_PORT => {
+                let value = self.palette[self.read_index as usize][self.read_component];
+                self.read_component += 1;
+                if self.read_component == 3 {
+                    self.read_component = 0;
+                    self.read_index = self.read_index.wrapping_add(1);
+                }
+                Some(value)
+            }
+            INPUT_STATUS_PORT => {
+                let position = instruction_count % INSTRUCTIONS_PER_FRAME;
+                if position >= INSTRUCTIONS_PER_FRAME - RETRACE_INSTRUCTIONS {
+                    Some(RETRACE_BIT | DISPLAY_DISABLED_BIT)
+                } else {
+                    Some(0)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles a write to `port`. Returns false when the port is not a VGA register.
+    pub fn port_out(&mut self, port: u16, value: u8) -> bool {
+        match port {
+            DAC_READ_INDEX_PORT => {
+                self.read_index = value;
+                self.read_component = 0;
+                self.reading = true;
+            }
+            DAC_WRITE_INDEX_PORT => {
+                self.write_index = value;
+                self.write_component = 0;
+                self.reading = false;
+            }
+            DAC_DATA_PORT => {
+                self.palette[self.write_index as usize][self.write_component] = value & 0x3F;
+                self.write_component += 1;
+                if self.write_component == 3 {
+                    self.write_component = 0;
+                    self.write_index = self.write_index.wrapping_add(1);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Converts the framebuffer to 8-bit RGB through the current palette.
+    pub fn to_rgb(&self, framebuffer: &[u8]) -> Vec<u8> {
+        framebuffer
+            .iter()
+            .flat_map(|color| self.palette[*color as usize].map(scale_component))
+            .collect()
+    }
+
+    /// Writes the framebuffer to `path`: PNG for a .png extension, binary PPM otherwise.
+    pub fn write_image(&self, path: &Path, framebuffer: &[u8]) -> io::Result<()> {
+        let rgb = self.to_rgb(framebuffer);
+        let mut writer = BufWriter::new(File::create(path)?);
+        let is_png = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+        if is_png {
+            write_png(&mut writer, WIDTH, HEIGHT, &rgb)?;
+        } else {
+            write_ppm(&mut writer, WIDTH, HEIGHT, &rgb)?;
+        }
+        writer.flush()
+    }
+}
+
+// 6-bit DAC value to 8 bits, so that 3Fh maps to FFh
+fn scale_component(value: u8) -> u8 {
+    (value << 2) | (value >> 4)
+}
+
+// frame.png -> frame_00012.png
+fn numbered_path(path: &Path, frame: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}_{frame:05}.{}", extension.to_string_lossy()),
+        None => format!("{stem}_{frame:05}"),
+    };
+    path.with_file_name(name)
+}
+
+pub fn write_ppm(
+    writer: &mut impl Write,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> io::Result<()> {
+    write!(writer, "P6\n{width} {height}\n255\n")?;
+    writer.write_all(rgb)
+}
+
+// PNG with uncompressed (stored) deflate blocks, so no compression library is needed.
+pub fn write_png(
+    writer: &mut impl Write,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolor, default compression/filter, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_png_chunk(writer, b"IHDR", &header)?;
+
+    // Every scanline starts with filter type 0 (None)
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+    write_png_chunk(writer, b"IDAT", &zlib)?;
+
+    write_png_chunk(writer, b"IEND", &[])
+}
+
+fn write_png_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let mut checked = kind.to_vec();
+    checked.extend_from_slice(data);
+    writer.write_all(&crc32(&checked).to_be_bytes())
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// BIOS video services (`int 10h`).
+pub fn video_service(
+    memory: &mut MemoryManager,
+    registers: &mut [Register; 10],
+    vga: &mut Vga,
+) -> Result<Status, ErrorCode> {
+    let ah = get_register_value(registers, &RegisterName::AH);
+    let al = get_register_value(registers, &RegisterName::AL) as u8;
+    match ah {
+        // Set video mode. Bit 7 of AL keeps the video memory.
+        0x00 => match al & 0x7F {
+            GRAPHICS_MODE => {
+                if vga.mode != GRAPHICS_MODE || al & 0x80 == 0 {
+                    memory.map_framebuffer();
+                }
+                vga.mode = GRAPHICS_MODE;
+                vga.palette = default_palette();
+            }
+            TEXT_MODE => {
+                memory.unmap_framebuffer();
+                vga.mode = TEXT_MODE;
+            }
+            mode => {
+                return Err(ErrorCode::InvalidValue(format!(
+                    "Video mode {mode:02X}h is not supported, use 03h or 13h"
+                )))
+            }
+        },
+        // Write pixel AL at column CX, row DX / read it into AL
+        0x0C | 0x0D => {
+            let x = get_register_value(registers, &RegisterName::CX) as usize;
+            let y = get_register_value(registers, &RegisterName::DX) as usize;
+            if vga.mode == GRAPHICS_MODE && x < WIDTH && y < HEIGHT {
+                let address = FRAMEBUFFER_ADDRESS + y * WIDTH + x;
+                if ah == 0x0C {
+                    memory.set_byte(address, al)?;
+                } else {
+                    let color = memory.get_byte(address)?;
+                    registers[RegisterName::AX.to_index()].load_byte(color, false);
+                }
+            }
+        }
+        // Current mode in AL, text columns in AH, active page in BH
+        0x0F => {
+            let columns = if vga.mode == GRAPHICS_MODE { 40 } else { 80 };
+            registers[RegisterName::AX.to_index()].load_byte(columns, true);
+            registers[RegisterName::AX.to_index()].load_byte(vga.mode, false);
+            registers[RegisterName::BX.to_index()].load_byte(0, true);
+        }
+        // DAC: AL = 10h sets register BX to DH/CH/CL, AL = 15h reads register BL into them
+        0x10 => {
+            let index = get_register_value(registers, &RegisterName::BL) as usize;
+            if al == 0x10 {
+                vga.palette[index] = [
+                    get_register_value(registers, &RegisterName::DH) as u8 & 0x3F,
+                    get_register_value(registers, &RegisterName::CH) as u8 & 0x3F,
+                    get_register_value(registers, &RegisterName::CL) as u8 & 0x3F,
+                ];
+            } else if al == 0x15 {
+                let [red, green, blue] = vga.palette[index];
+                registers[RegisterName::DX.to_index()].load_byte(red, true);
+                registers[RegisterName::CX.to_index()].load_byte(green, true);
+                registers[RegisterName::CX.to_index()].load_byte(blue, false);
+            }
+        }
+        // Other functions are not emulated and leave the registers alone
+        _ => {}
+    }
+    Ok(Status::Ok)
+}
@@ -0,0 +1,39 @@
+# AT&T syntax, picked by the .s extension
+	.data
+total:
+	.long 0
+table:
+	.word 1
+	.word 2, 3
+msg:	.asciz "Hi\n"
+	.bss
+buffer:
+	.zero 4
+
+	.text
+	.globl _start
+# int sum(int a, int b), as gcc -m32 writes it
+sum:
+	pushl %ebp
+	movl %esp, %ebp
+	subl $4, %esp
+	movl 8(%ebp), %eax
+	addl 12(%ebp), %eax
+	movl %eax, -4(%ebp)
+	movl -4(%ebp), %eax
+	leave
+	ret
+
+_start:
+	pushl $30
+	pushl $12
+	call sum
+	addl $8, %esp
+	movl %eax, total
+	movl $2, %ebx
+	movw table(,%ebx,2), %dx
+	movb $5, buffer+1
+	movl $msg, %ecx
+.L2:
+	decl %ebx
+	jnz .L2